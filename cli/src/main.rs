@@ -1,11 +1,19 @@
-use better_minify_js::minify;
+use better_minify_js::minify_with;
+use better_minify_js::MinifyOptions;
+use better_minify_js::MinifyOutputs;
+use better_minify_js::NameCache;
+use better_minify_js::PropertyPattern;
+use better_minify_js::QuoteStyle;
 use better_minify_js::Session;
+use better_minify_js::SourceMap;
 use better_minify_js::TopLevelMode;
+use std::fmt::Display;
 use std::fs::File;
 use std::io::stdin;
 use std::io::stdout;
 use std::io::Read;
 use std::io::Write;
+use std::process::exit;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -20,22 +28,189 @@ struct Cli {
   #[structopt(short, long, parse(from_os_str))]
   output: Option<std::path::PathBuf>,
 
+  /// Also write a source map to this path, and reference it from the output.
+  #[structopt(long, parse(from_os_str))]
+  source_map: Option<std::path::PathBuf>,
+
+  /// Source map for the input, if it was generated by an earlier step; the written source map will point to its sources instead.
+  #[structopt(long, parse(from_os_str), requires = "source-map")]
+  input_source_map: Option<std::path::PathBuf>,
+
+  /// Write legal comments (e.g. license banners) to this path instead of the output.
+  #[structopt(long, parse(from_os_str), conflicts_with = "legal-comments")]
+  legal_comments_file: Option<std::path::PathBuf>,
+
+  /// Reuse minified names for top-level variables and mangled properties from this JSON file if it exists, and save the names chosen to it.
+  #[structopt(long, parse(from_os_str))]
+  name_cache: Option<std::path::PathBuf>,
+
   /// Whether file is a module or global script.
   #[structopt(short, long)]
   mode: TopLevelMode,
+
+  /// Don't rename variables, functions, classes and parameters.
+  #[structopt(long)]
+  no_mangle: bool,
+
+  /// Don't transform functions into arrow functions.
+  #[structopt(long)]
+  no_arrow_functions: bool,
+
+  /// Don't transform `if` statements into expressions.
+  #[structopt(long)]
+  no_if_to_expression: bool,
+
+  /// Keep `debugger` statements.
+  #[structopt(long)]
+  keep_debugger: bool,
+
+  /// Don't unwrap unnecessary block statements.
+  #[structopt(long)]
+  no_unwrap_blocks: bool,
+
+  /// Don't evaluate expressions of literals.
+  #[structopt(long)]
+  no_fold_constants: bool,
+
+  /// Don't remove unreachable code.
+  #[structopt(long)]
+  no_dead_code: bool,
+
+  /// Don't remove unused variables, functions and parameters.
+  #[structopt(long)]
+  no_remove_unused: bool,
+
+  /// Don't inline constants and functions that are used once.
+  #[structopt(long)]
+  no_inline: bool,
+
+  /// Rename properties whose names match this regex (e.g. `^_`) to short names.
+  #[structopt(long)]
+  mangle_properties: Option<PropertyPattern>,
+
+  /// Build minified names from the most common characters in the code, which compresses better.
+  #[structopt(long)]
+  frequency_alphabet: bool,
+
+  /// Keep legal comments (e.g. license banners) in the output.
+  #[structopt(long)]
+  legal_comments: bool,
+
+  /// Format the output with indentation and one statement per line, e.g. to debug minified code.
+  #[structopt(long)]
+  beautify: bool,
+
+  /// Quotes to write strings with: auto, double, single, or backtick. By default, the quote needing the fewest escapes is chosen per string.
+  #[structopt(long, default_value = "auto")]
+  quote_style: QuoteStyle,
+
+  /// Never write `</script`, `<!--` or `-->`, so the output can be inlined into an HTML <script> element.
+  #[structopt(long)]
+  inline_script: bool,
+
+  /// The deepest that code can be nested, counting both brackets and syntax; deeper code is rejected instead of running out of stack.
+  #[structopt(long, default_value = "10000")]
+  max_nesting_depth: usize,
+}
+
+// Errors in the input aren't bugs in the CLI, so they're printed as a message rather than a panic.
+fn exit_on_error<T, E: Display>(result: Result<T, E>) -> T {
+  result.unwrap_or_else(|err| {
+    eprintln!("error: {}", err);
+    exit(1);
+  })
 }
 
 fn main() {
   let args = Cli::from_args();
   let mut input = Vec::new();
-  let mut input_file: Box<dyn Read> = match args.input {
+  let mut input_file: Box<dyn Read> = match &args.input {
     Some(p) => Box::new(File::open(p).expect("open input file")),
     None => Box::new(stdin()),
   };
   input_file.read_to_end(&mut input).expect("read input");
   let mut output = Vec::new();
+  let options = MinifyOptions {
+    mangle: !args.no_mangle,
+    arrow_functions: !args.no_arrow_functions,
+    if_to_expression: !args.no_if_to_expression,
+    drop_debugger: !args.keep_debugger,
+    unwrap_blocks: !args.no_unwrap_blocks,
+    fold_constants: !args.no_fold_constants,
+    dead_code: !args.no_dead_code,
+    remove_unused: !args.no_remove_unused,
+    inline: !args.no_inline,
+    mangle_properties: args.mangle_properties,
+    frequency_alphabet: args.frequency_alphabet,
+    legal_comments: args.legal_comments,
+    beautify: args.beautify,
+    quote_style: args.quote_style,
+    inline_script: args.inline_script,
+    max_nesting_depth: args.max_nesting_depth,
+  };
+  let file_name = |p: &std::path::Path| p.file_name().unwrap().to_string_lossy().to_string();
+  let mut outputs = MinifyOutputs::default();
+  if args.source_map.is_some() {
+    let mut map = SourceMap::new(match &args.input {
+      Some(p) => file_name(p),
+      None => "<stdin>".to_string(),
+    });
+    map.file = args.output.as_deref().map(file_name);
+    outputs.source_map = Some(map);
+    outputs.input_source_map = args.input_source_map.as_ref().map(|p| {
+      let raw = exit_on_error(
+        std::fs::read_to_string(p).map_err(|err| format!("read input source map: {}", err)),
+      );
+      exit_on_error(
+        SourceMap::from_json(&raw).map_err(|err| format!("parse input source map: {}", err)),
+      )
+    });
+  };
+  if args.legal_comments_file.is_some() {
+    outputs.legal_comments = Some(Vec::new());
+  };
+  if let Some(cache_path) = &args.name_cache {
+    outputs.name_cache = Some(match std::fs::read_to_string(cache_path) {
+      Ok(raw) => exit_on_error(
+        NameCache::from_json(&raw).map_err(|err| format!("parse name cache: {}", err)),
+      ),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => NameCache::new(),
+      Err(err) => exit_on_error(Err(format!("read name cache: {}", err))),
+    });
+  };
   let session = Session::new();
-  minify(&session, args.mode, &input, &mut output).expect("minify");
+  exit_on_error(minify_with(
+    &session,
+    args.mode,
+    &options,
+    &input,
+    &mut output,
+    &mut outputs,
+  ));
+  for warning in &outputs.warnings {
+    eprintln!("warning: {}", warning);
+  }
+  if let (Some(map_path), Some(map)) = (&args.source_map, &outputs.source_map) {
+    write!(output, "\n//# sourceMappingURL={}", file_name(map_path)).unwrap();
+    File::create(map_path)
+      .expect("open source map file")
+      .write_all(map.to_json().as_bytes())
+      .expect("write source map");
+  };
+  if let (Some(comments_path), Some(legal_comments)) =
+    (&args.legal_comments_file, &outputs.legal_comments)
+  {
+    File::create(comments_path)
+      .expect("open legal comments file")
+      .write_all(legal_comments)
+      .expect("write legal comments");
+  };
+  if let (Some(cache_path), Some(name_cache)) = (&args.name_cache, &outputs.name_cache) {
+    File::create(cache_path)
+      .expect("open name cache file")
+      .write_all(name_cache.to_json().as_bytes())
+      .expect("write name cache");
+  };
   match args.output {
     Some(p) => File::create(p)
       .expect("open output file")
@@ -1,7 +1,85 @@
+/**
+ * Transforms to apply during minification. All are enabled by default.
+ */
+export interface MinifyOptions {
+  /** Rename declared variables, functions, classes and parameters. */
+  mangle?: boolean;
+  /** Transform functions into arrow functions when `new`, `this`, `arguments`, and `prototype` aren't used. */
+  arrowFunctions?: boolean;
+  /** Transform `if` statements into expressions where possible. */
+  ifToExpression?: boolean;
+  /** Drop `debugger` statements. */
+  dropDebugger?: boolean;
+  /** Unwrap unnecessary block statements. */
+  unwrapBlocks?: boolean;
+  /** Evaluate expressions of literals at compile time. */
+  foldConstants?: boolean;
+  /** Remove unreachable code. */
+  deadCode?: boolean;
+  /** Remove declarations in functions that are never used: variables with side-effect-free initialisers, nested function declarations, and trailing parameters. Defaults to true. */
+  removeUnused?: boolean;
+  /** Inline function-local constants and functions that are used once. Defaults to true. */
+  inline?: boolean;
+  /** Rename properties whose names match this regular expression (Rust `regex` syntax, e.g. `"^_"`) to short names. Only match properties that aren't accessed by other code. Disabled by default. */
+  mangleProperties?: string;
+  /** Build minified names from the most common characters in the code, which compresses better with gzip or brotli. Defaults to false. */
+  frequencyAlphabet?: boolean;
+  /** Keep legal comments (e.g. license banners) in the output. Defaults to false. */
+  legalComments?: boolean;
+  /** Format the output with indentation and one statement per line, e.g. to debug minified code. Defaults to false. */
+  beautify?: boolean;
+  /** Quotes to write strings with. Defaults to `"auto"`, which picks the quote needing the fewest escapes for each string. */
+  quoteStyle?: "auto" | "double" | "single" | "backtick";
+  /** Never write `</script`, `<!--` or `-->`, so the output can be inlined into an HTML `<script>` element. Defaults to false. */
+  inlineScript?: boolean;
+  /** The deepest that code can be nested, counting both brackets and syntax; deeper code throws an error instead of running out of stack. Stack space for this many levels is reserved while minifying. Defaults to 10000. */
+  maxNestingDepth?: number;
+}
+
 /**
  * Minifies a Buffer containing UTF-8 JavaScript code.
  *
  * @param src - Source JS code
- * @returns Minified JS code
+ * @param options - Transforms to apply
+ * @returns Minified JS code; use `minifyWith` to also get warnings about code that prevented some minification
+ * @throws If `src` isn't valid JavaScript (e.g. has a syntax error or declares a variable twice) or is nested too deeply; the message says what and where
+ */
+export function minify(
+  topLevelType: "global" | "module",
+  src: Buffer,
+  options?: MinifyOptions
+): Buffer;
+
+/** Which outputs `minifyWith` produces besides the code; any combination can be requested. */
+export interface MinifyOutputs {
+  /** Generate a source map for the output, referencing the source file with this name. */
+  sourceMap?: string;
+  /** Source map JSON for `src`, if it was generated by an earlier step; the returned map will point to its sources instead. */
+  inputSourceMap?: string;
+  /** Return the legal comments (e.g. license banners) separately, one per line, instead of in the code; `MinifyOptions.legalComments` is ignored. */
+  legalComments?: boolean;
+  /** Reuse minified names for top-level variables and mangled properties from the name cache JSON returned by a previous call where possible; pass `"{}"` to start a new one. */
+  nameCache?: string;
+}
+
+/**
+ * Minifies a Buffer containing UTF-8 JavaScript code, and also produces the requested outputs.
+ *
+ * @param src - Source JS code
+ * @param options - Transforms to apply
+ * @param outputs - Outputs to produce besides the code
+ * @returns Minified JS code, each requested output (the source map as a JSON string, the legal comments, and the updated name cache as a JSON string to pass to the next call), and a message for each piece of code that prevented some minification, such as a direct call to `eval`
+ * @throws If `src` isn't valid JavaScript (e.g. has a syntax error or declares a variable twice) or is nested too deeply; the message says what and where
  */
-export function minify(topLevelType: "global" | "module", src: Buffer): Buffer;
+export function minifyWith(
+  topLevelType: "global" | "module",
+  src: Buffer,
+  options?: MinifyOptions,
+  outputs?: MinifyOutputs
+): {
+  code: Buffer;
+  map?: string;
+  legalComments?: string;
+  nameCache?: string;
+  warnings: string[];
+};
@@ -1,32 +1,190 @@
+use better_minify_js::MinifyOptions;
+use better_minify_js::MinifyOutputs;
+use better_minify_js::NameCache;
+use better_minify_js::PropertyPattern;
+use better_minify_js::QuoteStyle;
 use better_minify_js::Session;
+use better_minify_js::SourceMap;
+use better_minify_js::TopLevelMode;
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
 use std::str::FromStr;
 
-fn minify(mut cx: FunctionContext) -> JsResult<JsBuffer> {
-  let top_level_mode_raw = cx.argument::<JsString>(0).map(|v| v.value(&mut cx))?;
-  let top_level_mode = match better_minify_js::TopLevelMode::from_str(&top_level_mode_raw) {
-    Ok(m) => m,
-    Err(_) => return cx.throw_type_error("invalid top-level mode"),
+// Reads an optional boolean property, leaving the default untouched if it's missing or `undefined`.
+fn read_bool_option(
+  cx: &mut FunctionContext,
+  obj: Handle<JsObject>,
+  key: &str,
+  dest: &mut bool,
+) -> NeonResult<()> {
+  if let Some(v) = obj.get_opt::<JsBoolean, _, _>(cx, key)? {
+    *dest = v.value(cx);
+  };
+  Ok(())
+}
+
+fn read_options(cx: &mut FunctionContext, obj: Handle<JsObject>) -> NeonResult<MinifyOptions> {
+  let mut options = MinifyOptions::default();
+  read_bool_option(cx, obj, "mangle", &mut options.mangle)?;
+  read_bool_option(cx, obj, "arrowFunctions", &mut options.arrow_functions)?;
+  read_bool_option(cx, obj, "ifToExpression", &mut options.if_to_expression)?;
+  read_bool_option(cx, obj, "dropDebugger", &mut options.drop_debugger)?;
+  read_bool_option(cx, obj, "unwrapBlocks", &mut options.unwrap_blocks)?;
+  read_bool_option(cx, obj, "foldConstants", &mut options.fold_constants)?;
+  read_bool_option(cx, obj, "deadCode", &mut options.dead_code)?;
+  read_bool_option(cx, obj, "removeUnused", &mut options.remove_unused)?;
+  read_bool_option(cx, obj, "inline", &mut options.inline)?;
+  if let Some(pattern) = obj.get_opt::<JsString, _, _>(cx, "mangleProperties")? {
+    let pattern = pattern.value(cx);
+    match pattern.parse::<PropertyPattern>() {
+      Ok(pattern) => options.mangle_properties = Some(pattern),
+      Err(err) => return cx.throw_type_error(err.to_string()),
+    };
   };
+  read_bool_option(cx, obj, "frequencyAlphabet", &mut options.frequency_alphabet)?;
+  read_bool_option(cx, obj, "legalComments", &mut options.legal_comments)?;
+  read_bool_option(cx, obj, "beautify", &mut options.beautify)?;
+  read_bool_option(cx, obj, "inlineScript", &mut options.inline_script)?;
+  if let Some(depth) = obj.get_opt::<JsNumber, _, _>(cx, "maxNestingDepth")? {
+    let depth = depth.value(cx);
+    if depth.fract() != 0.0 || depth < 0.0 {
+      return cx.throw_type_error("invalid maxNestingDepth");
+    };
+    options.max_nesting_depth = depth as usize;
+  };
+  if let Some(style) = obj.get_opt::<JsString, _, _>(cx, "quoteStyle")? {
+    let style = style.value(cx);
+    match QuoteStyle::from_str(&style) {
+      Ok(s) => options.quote_style = s,
+      Err(_) => return cx.throw_type_error("invalid quoteStyle"),
+    };
+  };
+  Ok(options)
+}
+
+fn top_level_mode_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<TopLevelMode> {
+  let top_level_mode_raw = cx.argument::<JsString>(i)?.value(cx);
+  match TopLevelMode::from_str(&top_level_mode_raw) {
+    Ok(m) => Ok(m),
+    Err(_) => cx.throw_type_error("invalid top-level mode"),
+  }
+}
+
+fn options_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<MinifyOptions> {
+  match cx.argument_opt(i) {
+    Some(v) if !v.is_a::<JsUndefined, _>(cx) => {
+      let obj = v.downcast_or_throw::<JsObject, _>(cx)?;
+      read_options(cx, obj)
+    }
+    _ => Ok(MinifyOptions::default()),
+  }
+}
+
+fn minify(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+  let top_level_mode = top_level_mode_argument(&mut cx, 0)?;
   let src = cx.argument::<JsBuffer>(1)?;
+  let options = options_argument(&mut cx, 2)?;
   let mut out = Vec::new();
   // TODO Allow reuse by creating a JS function that creates a native object.
   let session = Session::new();
-  let res =
-    match better_minify_js::minify(&session, top_level_mode, src.as_slice(&mut cx), &mut out) {
-      Ok(()) => Ok(JsBuffer::external(&mut cx, out)),
-      // We can't call `cx.throw_error` here as `cx` is already borrowed, so we create the error string and then throw later.
-      Err(err) => Err(format!("{:?}", err)),
-    };
+  let res = match better_minify_js::minify(
+    &session,
+    top_level_mode,
+    &options,
+    src.as_slice(&mut cx),
+    &mut out,
+  ) {
+    Ok(()) => Ok(JsBuffer::external(&mut cx, out)),
+    // We can't call `cx.throw_error` here as `cx` is already borrowed, so we create the error string and then throw later.
+    Err(err) => Err(err.to_string()),
+  };
   match res {
     Ok(res) => Ok(res),
     Err(msg) => cx.throw_error(msg),
   }
 }
 
+// Reads which outputs to produce besides the code. The source map is requested by naming the source it maps to, and the name cache by passing the JSON of the previous cache (`"{}"` to start a new one).
+fn outputs_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<MinifyOutputs> {
+  let mut outputs = MinifyOutputs::default();
+  let obj = match cx.argument_opt(i) {
+    Some(v) if !v.is_a::<JsUndefined, _>(cx) => v.downcast_or_throw::<JsObject, _>(cx)?,
+    _ => return Ok(outputs),
+  };
+  if let Some(source_name) = obj.get_opt::<JsString, _, _>(cx, "sourceMap")? {
+    outputs.source_map = Some(SourceMap::new(source_name.value(cx)));
+  };
+  if let Some(raw) = obj.get_opt::<JsString, _, _>(cx, "inputSourceMap")? {
+    let raw = raw.value(cx);
+    match SourceMap::from_json(&raw) {
+      Ok(m) => outputs.input_source_map = Some(m),
+      Err(err) => return cx.throw_error(err.to_string()),
+    };
+  };
+  let mut legal_comments = false;
+  read_bool_option(cx, obj, "legalComments", &mut legal_comments)?;
+  if legal_comments {
+    outputs.legal_comments = Some(Vec::new());
+  };
+  if let Some(raw) = obj.get_opt::<JsString, _, _>(cx, "nameCache")? {
+    let raw = raw.value(cx);
+    match NameCache::from_json(&raw) {
+      Ok(c) => outputs.name_cache = Some(c),
+      Err(err) => return cx.throw_error(err.to_string()),
+    };
+  };
+  Ok(outputs)
+}
+
+fn minify_with(mut cx: FunctionContext) -> JsResult<JsObject> {
+  let top_level_mode = top_level_mode_argument(&mut cx, 0)?;
+  let src = cx.argument::<JsBuffer>(1)?;
+  let options = options_argument(&mut cx, 2)?;
+  let mut outputs = outputs_argument(&mut cx, 3)?;
+  let mut out = Vec::new();
+  let session = Session::new();
+  let res = match better_minify_js::minify_with(
+    &session,
+    top_level_mode,
+    &options,
+    src.as_slice(&cx),
+    &mut out,
+    &mut outputs,
+  ) {
+    Ok(()) => Ok(()),
+    // See `minify`.
+    Err(err) => Err(err.to_string()),
+  };
+  if let Err(msg) = res {
+    return cx.throw_error(msg);
+  };
+  let obj = cx.empty_object();
+  let code = JsBuffer::external(&mut cx, out);
+  obj.set(&mut cx, "code", code)?;
+  if let Some(map) = outputs.source_map {
+    let map = cx.string(map.to_json());
+    obj.set(&mut cx, "map", map)?;
+  };
+  if let Some(legal_comments) = outputs.legal_comments {
+    let legal_comments = cx.string(String::from_utf8_lossy(&legal_comments));
+    obj.set(&mut cx, "legalComments", legal_comments)?;
+  };
+  if let Some(name_cache) = outputs.name_cache {
+    let name_cache = cx.string(name_cache.to_json());
+    obj.set(&mut cx, "nameCache", name_cache)?;
+  };
+  let warnings = cx.empty_array();
+  for (i, warning) in outputs.warnings.iter().enumerate() {
+    let warning = cx.string(warning.to_string());
+    warnings.set(&mut cx, i as u32, warning)?;
+  }
+  obj.set(&mut cx, "warnings", warnings)?;
+  Ok(obj)
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
   cx.export_function("minify", minify)?;
+  cx.export_function("minifyWith", minify_with)?;
   Ok(())
 }
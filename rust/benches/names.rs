@@ -0,0 +1,67 @@
+use better_minify_js::minify;
+use better_minify_js::MinifyOptions;
+use better_minify_js::Session;
+use better_minify_js::TopLevelMode;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+
+// Short global names, which every scope that uses them inherits, so they're a long run of blocked minified names in each one.
+fn globals() -> Vec<String> {
+  let letters = b"abcdefghijklmnopqrstuvwxyz";
+  let mut names = Vec::new();
+  for &a in letters {
+    names.push((a as char).to_string());
+    for &b in letters {
+      names.push(format!("{}{}", a as char, b as char));
+    }
+  }
+  // Avoid keywords.
+  names.retain(|n| !["do", "if", "in"].contains(&n.as_str()));
+  names
+}
+
+// A chain of `depth` nested functions, each declaring a variable and a JSX component, with the innermost using every global.
+fn deep_scope_chain(depth: usize) -> String {
+  let mut src = String::new();
+  for i in 0..depth {
+    src.push_str(&format!(
+      "function f{i}(p{i}) {{ var v{i} = p{i} + 1; const C{i} = () => v{i}; g(<C{i} />); "
+    ));
+  }
+  src.push_str(&format!("g({});", globals().join(", ")));
+  for _ in 0..depth {
+    src.push('}');
+  }
+  src
+}
+
+fn bench_names(c: &mut Criterion) {
+  let mut group = c.benchmark_group("deep_scope_chain");
+  for depth in [50, 100, 200, 400] {
+    let src = deep_scope_chain(depth);
+    // Time per scope should stay the same as the depth increases.
+    group.throughput(Throughput::Elements(depth as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(depth), &src, |b, src| {
+      b.iter(|| {
+        let session = Session::new();
+        let mut out = Vec::new();
+        minify(
+          &session,
+          TopLevelMode::Global,
+          &MinifyOptions::default(),
+          src.as_bytes(),
+          &mut out,
+        )
+        .unwrap();
+        out
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_names);
+criterion_main!(benches);
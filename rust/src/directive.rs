@@ -0,0 +1,90 @@
+use parse_js::ast::NodeData;
+use parse_js::ast::Syntax;
+use parse_js::source::SourceRange;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+
+// Returns the source of the string literal in `stmt` if it's an expression statement of only a string literal as written in the source, which is what a directive (e.g. `"use strict"`) is when it's at the start of a function or script. Strings computed by transforms (e.g. from folding `"use " + "strict"`) have the location of the expression they replaced, so they're never mistaken for one, and parenthesised strings (e.g. `("use strict")`) have a statement location that ends after the string; see `ParenthesisedStrings`.
+pub fn directive_source<'a>(stmt: &NodeData<'a>) -> Option<SourceRange<'a>> {
+  let Syntax::ExpressionStmt { expression } = &stmt.stx else {
+    return None;
+  };
+  let Syntax::LiteralStringExpr { .. } = &expression.stx else {
+    return None;
+  };
+  if stmt.loc.start() != expression.loc.start() || stmt.loc.end() != expression.loc.end() {
+    return None;
+  };
+  let raw = expression.loc.as_slice();
+  let (&quote, rest) = raw.split_first()?;
+  if quote != b'"' && quote != b'\'' {
+    return None;
+  };
+  // The literal must end at the first unescaped closing quote.
+  let mut i = 0;
+  while i < rest.len() {
+    match rest[i] {
+      b'\\' => i += 2,
+      c if c == quote => return (i == rest.len() - 1).then_some(expression.loc),
+      _ => i += 1,
+    };
+  }
+  None
+}
+
+// Returns whether `raw`, the source of a directive, is `"use strict"`. It only counts if written exactly like that, without escapes.
+pub fn is_use_strict(raw: &[u8]) -> bool {
+  &raw[1..raw.len() - 1] == b"use strict"
+}
+
+// Returns how many statements at the start of `body` are directives. Other string literal statements are removed during minification, so any left at the start of a function or script body are directives.
+pub fn directive_prologue_len<'a>(body: &[&mut NodeData<'a>]) -> usize {
+  body
+    .iter()
+    .take_while(|stmt| directive_source(stmt).is_some())
+    .count()
+}
+
+// Returns the index of the first byte at or after `i` that isn't whitespace or part of a comment.
+fn skip_whitespace_and_comments(source: &[u8], mut i: usize) -> usize {
+  while i < source.len() {
+    let rest = &source[i..];
+    if rest.starts_with(b"//") {
+      i += rest
+        .iter()
+        .position(|&c| c == b'\n' || c == b'\r')
+        .unwrap_or(rest.len());
+    } else if rest.starts_with(b"/*") {
+      i += rest[2..]
+        .windows(2)
+        .position(|w| w == b"*/")
+        .map_or(rest.len(), |end| end + 4);
+    } else if rest[0].is_ascii_whitespace() {
+      i += 1;
+    } else {
+      break;
+    };
+  }
+  i
+}
+
+// The parser doesn't record parentheses, so an expression statement of a parenthesised string (e.g. `("use strict");`) has the same location as the string, like a directive, even though it isn't one. This extends the location of such statements to their closing parenthesis, so `directive_source` can tell them apart, and they're removed like other string statements. It must run before minification, while locations still match the source.
+pub struct ParenthesisedStrings<'a> {
+  pub source: &'a [u8],
+}
+
+impl<'a> Visitor<'a> for ParenthesisedStrings<'a> {
+  fn on_syntax_down(&mut self, node: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    let Syntax::ExpressionStmt { expression } = &node.stx else {
+      return;
+    };
+    let Syntax::LiteralStringExpr { .. } = &expression.stx else {
+      return;
+    };
+    // The statement is only the string, so if it's parenthesised, the next token must be the closing parenthesis; otherwise, it's a semicolon, a closing brace, or the next statement.
+    let next = skip_whitespace_and_comments(self.source, expression.loc.end());
+    if self.source.get(next) == Some(&b')') {
+      node.loc = SourceRange::new(self.source, node.loc.start(), next + 1);
+    };
+  }
+}
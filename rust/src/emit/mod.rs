@@ -1,4 +1,11 @@
-use aho_corasick::AhoCorasick;
+use crate::directive::directive_source;
+use crate::legal_comments::LegalComments;
+use crate::number::shortest_bigint_literal;
+use crate::number::shortest_number_literal;
+use crate::MinifyOptions;
+use crate::QuoteStyle;
+use crate::source_map::SourceMap;
+use crate::source_map::SourceMapBuilder;
 use lazy_static::lazy_static;
 use parse_js::ast::ArrayElement;
 use parse_js::ast::ClassMember;
@@ -16,8 +23,10 @@ use parse_js::ast::VarDeclMode;
 use parse_js::operator::OperatorName;
 use parse_js::operator::OPERATORS;
 use parse_js::session::SessionVec;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::Write;
+use std::ops::Deref;
+use std::ops::DerefMut;
 
 #[cfg(test)]
 mod tests;
@@ -97,16 +106,151 @@ lazy_static! {
 
 }
 
-lazy_static! {
-  static ref TEMPLATE_LITERAL_ESCAPE_MAT: AhoCorasick = AhoCorasick::new(&[b"\\", b"`", b"$",])
-    .expect("Failed to build AhoCorasick instance for TEMPLATE_LITERAL_ESCAPE_MAT");
+// Destination of emitted code. This derefs to the output buffer so emit logic can write to it directly, and additionally records source map mappings if enabled.
+pub struct Emitter<'o, 'a> {
+  out: &'o mut Vec<u8>,
+  source_map: Option<SourceMapBuilder<'o, 'a>>,
+  legal_comments: Option<&'o mut LegalComments<'a>>,
+  // The current indentation level if beautifying, or None if minifying.
+  indentation: Option<usize>,
+  quote_style: QuoteStyle,
+  // Whether to avoid writing `</script`, `<!--` and `-->`, so the output can be inlined into an HTML `<script>` element.
+  inline_script: bool,
 }
 
-const TEMPLATE_LITERAL_ESCAPE_REP: &[&[u8]] = &[b"\\\\", b"\\`", b"\\$"];
+impl<'o, 'a> Emitter<'o, 'a> {
+  pub fn new(out: &'o mut Vec<u8>) -> Self {
+    Self {
+      out,
+      source_map: None,
+      legal_comments: None,
+      indentation: None,
+      quote_style: QuoteStyle::Auto,
+      inline_script: false,
+    }
+  }
+
+  // Mappings will only be recorded for nodes whose `loc` is within `source`.
+  pub fn with_source_map(out: &'o mut Vec<u8>, source: &'a [u8], map: &'o mut SourceMap) -> Self {
+    let output_start = out.len();
+    Self {
+      out,
+      source_map: Some(SourceMapBuilder::new(map, source, output_start)),
+      legal_comments: None,
+      indentation: None,
+      quote_style: QuoteStyle::Auto,
+      inline_script: false,
+    }
+  }
+
+  // Writes each legal comment before the statement that followed it in the source.
+  pub fn with_legal_comments(mut self, legal_comments: &'o mut LegalComments<'a>) -> Self {
+    self.legal_comments = Some(legal_comments);
+    self
+  }
+
+  // Applies the options that control how code is written rather than what's written, which are `beautify`, `quote_style` and `inline_script`.
+  pub fn with_options(mut self, options: &MinifyOptions) -> Self {
+    if options.beautify {
+      self = self.beautified();
+    };
+    self.quote_style = options.quote_style;
+    self.inline_script = options.inline_script;
+    self
+  }
+
+  // Writes readable code instead of the densest code: one statement per line with indentation, spaces around operators and after commas, and braces around every control flow body. This is still an exact reflection of the tree otherwise, so it can also be used to format unminified code.
+  pub fn beautified(mut self) -> Self {
+    self.indentation = Some(0);
+    self
+  }
+
+  fn is_beautified(&self) -> bool {
+    self.indentation.is_some()
+  }
+
+  // Writes a space, but only when beautifying.
+  fn space(&mut self) -> () {
+    if self.is_beautified() {
+      self.out.push(b' ');
+    };
+  }
+
+  // Starts a new line at the current indentation, but only when beautifying.
+  fn newline(&mut self) -> () {
+    if let Some(indentation) = self.indentation {
+      self.out.push(b'\n');
+      for _ in 0..indentation {
+        self.out.extend_from_slice(b"  ");
+      }
+    };
+  }
+
+  fn indent(&mut self) -> () {
+    if let Some(indentation) = &mut self.indentation {
+      *indentation += 1;
+    };
+  }
+
+  fn dedent(&mut self) -> () {
+    if let Some(indentation) = &mut self.indentation {
+      *indentation -= 1;
+    };
+  }
+
+  // Writes legal comments that weren't written before their statement, which should be called once all code has been emitted.
+  pub fn finish(&mut self) -> () {
+    if let Some(legal_comments) = &mut self.legal_comments {
+      let len = self.out.len();
+      legal_comments.write_remaining(self.out);
+      self.escape_comments_from(len);
+    };
+  }
+
+  // Escapes `</script`, `<!--` and `-->` in the comments written from `start` if the output will be inlined into a script, by inserting a backslash, which has no meaning in a comment.
+  fn escape_comments_from(&mut self, start: usize) -> () {
+    if !self.inline_script {
+      return;
+    };
+    let comments = self.out.split_off(start);
+    for (i, &c) in comments.iter().enumerate() {
+      let needs_backslash = match c {
+        b'/' => closes_script(&comments, i),
+        b'!' => comments[..i].ends_with(b"<") && comments[i + 1..].starts_with(b"--"),
+        b'>' => comments[..i].ends_with(b"--"),
+        _ => false,
+      };
+      if needs_backslash {
+        self.out.push(b'\\');
+      };
+      self.out.push(c);
+    }
+  }
+
+  fn add_mapping(&mut self, original: &[u8], name: Option<&[u8]>) {
+    if let Some(source_map) = &mut self.source_map {
+      source_map.add(&self.out[..], original, name);
+    };
+  }
+}
+
+impl<'o, 'a> Deref for Emitter<'o, 'a> {
+  type Target = Vec<u8>;
+
+  fn deref(&self) -> &Self::Target {
+    self.out
+  }
+}
+
+impl<'o, 'a> DerefMut for Emitter<'o, 'a> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.out
+  }
+}
 
 // Returns whether or not the value is a property.
 fn emit_class_or_object_member<'a>(
-  out: &mut Vec<u8>,
+  out: &mut Emitter,
   key: &'a ClassOrObjectMemberKey,
   value: &'a ClassOrObjectMemberValue,
   value_delimiter: &'static [u8],
@@ -146,7 +290,8 @@ fn emit_class_or_object_member<'a>(
   };
   match key {
     ClassOrObjectMemberKey::Direct(name) => {
-      out.extend_from_slice(name.as_slice());
+      // Quoted keys are kept as written.
+      emit_source(out, name.as_slice(), false);
     }
     ClassOrObjectMemberKey::Computed(expr) => {
       out.extend_from_slice(b"[");
@@ -157,7 +302,7 @@ fn emit_class_or_object_member<'a>(
   match value {
     ClassOrObjectMemberValue::Getter { body } => {
       out.extend_from_slice(b"()");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     ClassOrObjectMemberValue::Method {
       signature, body, ..
@@ -165,11 +310,16 @@ fn emit_class_or_object_member<'a>(
       out.extend_from_slice(b"(");
       emit_js(out, *signature);
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     ClassOrObjectMemberValue::Property { initializer } => {
       if let Some(v) = initializer {
+        // Class fields are spaced like assignments, object properties like `key: value`.
+        if value_delimiter == b"=" {
+          out.space();
+        };
         out.extend_from_slice(value_delimiter);
+        out.space();
         let is_comma = is_comma_expression(&v.stx);
         if is_comma {
           out.extend_from_slice(b"(");
@@ -184,7 +334,7 @@ fn emit_class_or_object_member<'a>(
       out.extend_from_slice(b"(");
       emit_js(out, *parameter);
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
   };
 
@@ -195,7 +345,7 @@ fn emit_class_or_object_member<'a>(
 }
 
 fn emit_class<'a>(
-  out: &mut Vec<u8>,
+  out: &mut Emitter,
   name: &Option<&mut NodeData<'a>>,
   extends: &Option<&mut NodeData<'a>>,
   members: &SessionVec<'a, ClassMember<'a>>,
@@ -209,7 +359,26 @@ fn emit_class<'a>(
     out.extend_from_slice(b" extends ");
     emit_js(out, s);
   }
+  out.space();
   out.extend_from_slice(b"{");
+  if out.is_beautified() {
+    if !members.is_empty() {
+      out.indent();
+      for m in members.iter() {
+        out.newline();
+        if m.statik {
+          out.extend_from_slice(b"static ");
+        }
+        if emit_class_or_object_member(out, &m.key, &m.value, b"=") {
+          out.extend_from_slice(b";");
+        };
+      }
+      out.dedent();
+      out.newline();
+    };
+    out.extend_from_slice(b"}");
+    return;
+  };
   let mut last_member_was_property = false;
   for (i, m) in members.iter().enumerate() {
     if i > 0 && last_member_was_property {
@@ -224,47 +393,169 @@ fn emit_class<'a>(
 }
 
 fn emit_import_or_export_statement_trailer<'a>(
-  out: &mut Vec<u8>,
+  out: &mut Emitter,
   names: Option<&ExportNames<'a>>,
   from: Option<&'a str>,
 ) -> () {
   match names {
     Some(ExportNames::All(alias)) => {
+      out.space();
       out.extend_from_slice(b"*");
       if let Some(alias) = alias {
+        out.space();
         out.extend_from_slice(b"as ");
         emit_js(out, *alias);
-        if from.is_some() {
+        if from.is_some() && !out.is_beautified() {
           out.extend_from_slice(b" ");
         }
       };
     }
     Some(ExportNames::Specific(names)) => {
+      out.space();
       out.extend_from_slice(b"{");
+      if !names.is_empty() {
+        out.space();
+      };
       for (i, e) in names.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         out.extend_from_slice(e.target.as_slice());
         // TODO Omit if identical to `target`.
         out.extend_from_slice(b" as ");
         emit_js(out, e.alias);
       }
+      if !names.is_empty() {
+        out.space();
+      };
       out.extend_from_slice(b"}");
     }
     None => {}
   };
   if let Some(from) = from {
-    out.extend_from_slice(b"from\"");
-    // TODO Escape?
-    out.extend_from_slice(from.as_bytes());
-    out.extend_from_slice(b"\"");
+    out.space();
+    out.extend_from_slice(b"from");
+    out.space();
+    emit_string(out, from, false);
   };
 }
 
+// Picks the quote for a string literal. Only the quote itself, and newlines and `${` for templates, need different escapes between quotes; everything else costs the same.
+fn choose_quote(quote_style: QuoteStyle, value: &str, allow_backtick: bool) -> u8 {
+  match quote_style {
+    QuoteStyle::Double => return b'"',
+    QuoteStyle::Single => return b'\'',
+    QuoteStyle::Backtick if allow_backtick => return b'`',
+    QuoteStyle::Backtick => return b'"',
+    QuoteStyle::Auto => {}
+  };
+  let (mut double, mut single, mut backtick) = (0, 0, 0);
+  let bytes = value.as_bytes();
+  for (i, c) in bytes.iter().enumerate() {
+    match c {
+      b'"' => double += 1,
+      b'\'' => single += 1,
+      b'`' => backtick += 1,
+      b'$' if bytes.get(i + 1) == Some(&b'{') => backtick += 1,
+      // Only templates can contain line feeds without escaping them.
+      b'\n' => {
+        double += 1;
+        single += 1;
+      }
+      _ => {}
+    };
+  }
+  if allow_backtick && backtick < double.min(single) {
+    b'`'
+  } else if single < double {
+    b'\''
+  } else {
+    b'"'
+  }
+}
+
+// Writes the contents of a string literal or template delimited by `quote`, escaping only what's necessary. Line and paragraph separators are always escaped as some older tools don't allow them in strings.
+fn emit_escaped(out: &mut Emitter, value: &str, quote: u8) -> () {
+  let bytes = value.as_bytes();
+  let mut written = 0;
+  for (i, c) in value.char_indices() {
+    let escaped: &[u8] = match c {
+      '\\' => b"\\\\",
+      '\n' if quote != b'`' => b"\\n",
+      // Carriage returns are normalised to line feeds in templates, so they must always be escaped.
+      '\r' => b"\\r",
+      '\u{2028}' => b"\\u2028",
+      '\u{2029}' => b"\\u2029",
+      '"' if quote == b'"' => b"\\\"",
+      '\'' if quote == b'\'' => b"\\'",
+      '`' if quote == b'`' => b"\\`",
+      '$' if quote == b'`' && bytes.get(i + 1) == Some(&b'{') => b"\\$",
+      '/' if out.inline_script && closes_script(bytes, i) => b"\\/",
+      '<' if out.inline_script && bytes[i + 1..].starts_with(b"!--") => b"\\x3c",
+      '>' if out.inline_script && bytes[..i].ends_with(b"--") => b"\\x3e",
+      _ => continue,
+    };
+    out.extend_from_slice(&bytes[written..i]);
+    out.extend_from_slice(escaped);
+    written = i + c.len_utf8();
+  }
+  out.extend_from_slice(&bytes[written..]);
+}
+
+// Returns whether the `/` at `i` is part of `</script`, which ends an inline script regardless of case or what follows it.
+fn closes_script(bytes: &[u8], i: usize) -> bool {
+  bytes[..i].ends_with(b"<")
+    && matches!(bytes.get(i + 1..i + 7), Some(name) if name.eq_ignore_ascii_case(b"script"))
+}
+
+// Writes the source text of a string literal or regex, escaping `</script`, `<!--` and `-->` if the output will be inlined into a script. In these sequences, only `<` can already be escaped (e.g. `\<` in a string), in which case its backslash is replaced too. In a regex, `(?<!--` is a lookbehind, so the first `-` is escaped instead of `<` outside character classes, where `-` has no special meaning.
+fn emit_source(out: &mut Emitter, raw: &[u8], is_regex: bool) -> () {
+  if !out.inline_script {
+    out.extend_from_slice(raw);
+    return;
+  };
+  let mut written = 0;
+  let mut is_escaped = false;
+  let mut in_class = false;
+  for (i, &c) in raw.iter().enumerate() {
+    let was_escaped = is_escaped;
+    is_escaped = !was_escaped && c == b'\\';
+    if is_regex && !was_escaped {
+      match c {
+        b'[' => in_class = true,
+        b']' => in_class = false,
+        _ => {}
+      };
+    };
+    let (start, escaped): (usize, &[u8]) = match c {
+      b'/' if closes_script(raw, i) => (i, b"\\/"),
+      b'<' if (in_class || !is_regex) && raw[i + 1..].starts_with(b"!--") => {
+        (if was_escaped { i - 1 } else { i }, b"\\x3c")
+      }
+      b'-' if is_regex && !in_class && raw[..i].ends_with(b"<!") && raw[i + 1..].starts_with(b"-") => {
+        (i, b"\\x2d")
+      }
+      b'>' if raw[..i].ends_with(b"--") => (i, b"\\x3e"),
+      _ => continue,
+    };
+    out.extend_from_slice(&raw[written..start]);
+    out.extend_from_slice(escaped);
+    written = i + 1;
+  }
+  out.extend_from_slice(&raw[written..]);
+}
+
+fn emit_string(out: &mut Emitter, value: &str, allow_backtick: bool) -> () {
+  let quote = choose_quote(out.quote_style, value, allow_backtick);
+  out.push(quote);
+  emit_escaped(out, value, quote);
+  out.push(quote);
+}
+
 // NOTE: We no longer support outputting to a generic Write, as that incurs significant performance overhead (even with a BufWriter<Vec<u8>>) and our parser is not streaming anyway.
 // WARNING: We use this function for testing minification passes (it's easier than trying to write up and then match/compare trees), so all emit logic should be deterministic and not alter/deviate from the tree in any way (i.e. it's a genuine exact unopinionated/objective unmodified reflection of the tree).
-pub fn emit_js<'a>(out: &mut Vec<u8>, n: &NodeData<'a>) -> () {
+pub fn emit_js<'a>(out: &mut Emitter, n: &NodeData<'a>) -> () {
   emit_js_under_operator(out, n, None);
 }
 
@@ -298,7 +589,11 @@ fn get_leaf_node_type<'a>(n: &NodeData<'a>) -> LeafNodeType {
 // It's important to use this function:
 // - Omit semicolons where possible.
 // - Insert semicolon after last statement if its leaf is a `if`, `for`, `while`, or `with` statement with an empty statement as its body e.g. `if (x) label: for (;;) while (x)` but not `if (x) for (;;) label: while (x) {}` or `if (x) for (;;) label: while (x) return`.
-fn emit_statements<'a>(out: &mut Vec<u8>, statements: &[&mut NodeData<'a>]) -> () {
+fn emit_statements<'a>(out: &mut Emitter, statements: &[&mut NodeData<'a>]) -> () {
+  if out.is_beautified() {
+    emit_beautified_statements(out, statements);
+    return;
+  };
   // Since we skip over some statements, the last actual statement may not be the last in the list.
   let mut last_statement: Option<&NodeData<'a>> = None;
   for n in statements {
@@ -316,6 +611,7 @@ fn emit_statements<'a>(out: &mut Vec<u8>, statements: &[&mut NodeData<'a>]) -> (
         _ => out.extend_from_slice(b";"),
       }
     }
+    emit_legal_comments_before(out, n);
     emit_js(out, *n);
     last_statement = Some(*n);
   }
@@ -326,6 +622,157 @@ fn emit_statements<'a>(out: &mut Vec<u8>, statements: &[&mut NodeData<'a>]) -> (
   }
 }
 
+fn emit_legal_comments_before<'a>(out: &mut Emitter, n: &NodeData<'a>) -> () {
+  let Some(legal_comments) = &mut out.legal_comments else {
+    return;
+  };
+  let len = out.out.len();
+  legal_comments.write_before_statement(out.out, n.loc.start());
+  out.escape_comments_from(len);
+  // Put the comments on their own line, at the same indentation as the statement.
+  if out.is_beautified() && out.len() > len {
+    if out.ends_with(b"\n") {
+      out.pop();
+    };
+    out.newline();
+  };
+}
+
+// Writes the legal comments after the last statement of a block, on their own indented line when beautifying. Returns whether there were any.
+fn emit_legal_comments_before_block_end<'a>(out: &mut Emitter, n: &NodeData<'a>) -> bool {
+  let block_end = n.loc.end().saturating_sub(1);
+  let Some(legal_comments) = &out.legal_comments else {
+    return false;
+  };
+  if !legal_comments.has_before_block_end(block_end) {
+    return false;
+  };
+  out.indent();
+  out.newline();
+  let len = out.out.len();
+  if let Some(legal_comments) = &mut out.legal_comments {
+    legal_comments.write_before_statement(out.out, block_end);
+  };
+  out.escape_comments_from(len);
+  if out.is_beautified() && out.ends_with(b"\n") {
+    out.pop();
+  };
+  out.dedent();
+  true
+}
+
+// Whether a statement needs to be terminated with a semicolon when beautifying. Unlike when minifying, every statement ends up on its own line, and control flow bodies are always wrapped in braces.
+fn needs_semicolon_when_beautified<'a>(stx: &Syntax<'a>) -> bool {
+  match stx {
+    // An empty labelled statement must be terminated, or the label would apply to the next statement.
+    Syntax::LabelStmt { statement, .. } => {
+      matches!(statement.stx, Syntax::EmptyStmt {})
+        || needs_semicolon_when_beautified(&statement.stx)
+    }
+    Syntax::BlockStmt { .. }
+    | Syntax::ClassDecl { .. }
+    | Syntax::EmptyStmt {}
+    | Syntax::ForStmt { .. }
+    | Syntax::FunctionDecl { .. }
+    | Syntax::IfStmt { .. }
+    | Syntax::SwitchStmt { .. }
+    | Syntax::TryStmt { .. }
+    | Syntax::WhileStmt { .. } => false,
+    _ => true,
+  }
+}
+
+fn emit_beautified_statement<'a>(out: &mut Emitter, n: &NodeData<'a>) -> () {
+  emit_js(out, n);
+  if needs_semicolon_when_beautified(&n.stx) {
+    out.extend_from_slice(b";");
+  };
+}
+
+// Emits each statement on its own line. The caller is responsible for starting the line of the first statement.
+fn emit_beautified_statements<'a>(out: &mut Emitter, statements: &[&mut NodeData<'a>]) -> () {
+  for (i, n) in statements
+    .iter()
+    .filter(|n| !matches!(n.stx, Syntax::EmptyStmt {}))
+    .enumerate()
+  {
+    if i > 0 {
+      out.newline();
+    };
+    emit_legal_comments_before(out, n);
+    emit_beautified_statement(out, n);
+  }
+}
+
+fn has_statements<'a>(statements: &[&mut NodeData<'a>]) -> bool {
+  statements
+    .iter()
+    .any(|n| !matches!(n.stx, Syntax::EmptyStmt {}))
+}
+
+// Emits statements as an indented block on the following lines when beautifying, or as is when minifying.
+fn emit_indented_statements<'a>(out: &mut Emitter, statements: &[&mut NodeData<'a>]) -> () {
+  if !out.is_beautified() {
+    emit_statements(out, statements);
+    return;
+  };
+  if has_statements(statements) {
+    out.indent();
+    out.newline();
+    emit_beautified_statements(out, statements);
+    out.dedent();
+  };
+}
+
+// Emits the body of a function, method, or control flow statement. When beautifying, it's separated from what precedes it by a space, and wrapped in braces if it's not already a block.
+fn emit_body<'a>(out: &mut Emitter, body: &NodeData<'a>) -> () {
+  if !out.is_beautified() {
+    emit_js(out, body);
+    return;
+  };
+  out.space();
+  match &body.stx {
+    Syntax::BlockStmt { .. } => emit_js(out, body),
+    Syntax::EmptyStmt {} => out.extend_from_slice(b"{}"),
+    _ => {
+      out.extend_from_slice(b"{");
+      out.indent();
+      out.newline();
+      emit_beautified_statement(out, body);
+      out.dedent();
+      out.newline();
+      out.extend_from_slice(b"}");
+    }
+  };
+}
+
+// Transforms can create numbers that are negative or not finite, which have no literal syntax, so these are written as the shortest equivalent expression instead (e.g. `1/0` for Infinity, which unlike `Infinity` can't be shadowed), parenthesised if necessary.
+fn emit_number(out: &mut Emitter, value: f64, parent_operator_precedence: Option<u8>) -> () {
+  let (syntax, operator_name) = if value.is_nan() {
+    (Cow::Borrowed("0/0"), OperatorName::Division)
+  } else if value.is_infinite() {
+    let syntax = if value > 0.0 { "1/0" } else { "-1/0" };
+    (Cow::Borrowed(syntax), OperatorName::Division)
+  } else if value.is_sign_negative() {
+    // Includes -0.
+    let syntax = format!("-{}", shortest_number_literal(-value));
+    (Cow::Owned(syntax), OperatorName::UnaryNegation)
+  } else {
+    out.extend_from_slice(shortest_number_literal(value).as_bytes());
+    return;
+  };
+  // There's no source parenthesisation to rely on, so also parenthesise at the same precedence (e.g. `a/(1/0)` and `-(-1)`).
+  let must_parenthesise = parent_operator_precedence
+    .is_some_and(|po| po >= OPERATORS[&operator_name].precedence);
+  if must_parenthesise {
+    out.extend_from_slice(b"(");
+  };
+  out.extend_from_slice(syntax.as_bytes());
+  if must_parenthesise {
+    out.extend_from_slice(b")");
+  };
+}
+
 fn is_comma_expression<'a>(stx: &Syntax<'a>) -> bool {
   match stx {
     Syntax::BinaryExpr { operator, .. } => *operator == OperatorName::Comma,
@@ -360,19 +807,40 @@ For `do <stmt> while (...)` and `if <stmt> else (...)`, when does a semicolon ne
 */
 
 fn emit_js_under_operator<'a>(
-  out: &mut Vec<u8>,
+  out: &mut Emitter,
   node: &NodeData<'a>,
   parent_operator_precedence: Option<u8>,
 ) -> () {
+  if out.source_map.is_some() {
+    // Identifiers may have been renamed, so record the original name too.
+    let original_name = match &node.stx {
+      Syntax::ClassOrFunctionName { name }
+      | Syntax::IdentifierExpr { name }
+      | Syntax::IdentifierPattern { name }
+        if node.loc != *name =>
+      {
+        Some(node.loc.as_slice())
+      }
+      _ => None,
+    };
+    out.add_mapping(node.loc.as_slice(), original_name);
+  };
   match &node.stx {
     Syntax::EmptyStmt {} => {}
-    Syntax::LiteralBigIntExpr { .. } => {
-      // TODO This is invalid as `loc` may not be valid (e.g. newly created node during transform).
-      out.extend_from_slice(node.loc.as_slice());
+    Syntax::LiteralBigIntExpr { value } => {
+      // The parser keeps the literal as written (e.g. `0x1_0n`), so a new node only needs to set `value`. Unlike numbers, BigInts can't be negative literals, so there's no sign or precedence to handle.
+      match shortest_bigint_literal(value) {
+        Some(literal) => out.extend_from_slice(literal.as_bytes()),
+        None => out.extend_from_slice(value.as_bytes()),
+      };
     }
-    Syntax::LiteralRegexExpr { .. } => {
-      // TODO This is invalid as `loc` may not be valid (e.g. newly created node during transform).
-      out.extend_from_slice(node.loc.as_slice());
+    Syntax::LiteralRegexExpr {} => {
+      // TODO This is invalid as `loc` may not be valid (e.g. newly created node during transform), but `parse-js` (as of 0.21) doesn't keep the pattern and flags on the node, so there's nothing else to emit from. Emitting from those is deferred until the parser keeps them (see the README's In progress list).
+      if out.inline_script && out.ends_with(b"<") {
+        // Prevent `a</script/.test(b)` from closing the script.
+        out.push(b' ');
+      };
+      emit_source(out, node.loc.as_slice(), true);
     }
     Syntax::LiteralBooleanExpr { value } => {
       match *value {
@@ -381,16 +849,10 @@ fn emit_js_under_operator<'a>(
       };
     }
     Syntax::LiteralNumberExpr { value } => {
-      // TODO Possibly invalid.
-      write!(out, "{}", value).unwrap();
+      emit_number(out, value.0, parent_operator_precedence);
     }
     Syntax::LiteralStringExpr { value } => {
-      // Replace stream_replace_all with try_stream_replace_all
-      out.extend_from_slice(b"`");
-      TEMPLATE_LITERAL_ESCAPE_MAT
-        .try_stream_replace_all(value.as_bytes(), &mut *out, TEMPLATE_LITERAL_ESCAPE_REP)
-        .expect("Failed to perform template literal escape replacement");
-      out.extend_from_slice(b"`");
+      emit_string(out, value, true);
     }
     Syntax::LiteralTemplateExpr { parts } => {
       out.extend_from_slice(b"`");
@@ -401,9 +863,10 @@ fn emit_js_under_operator<'a>(
             emit_js(out, *sub);
             out.extend_from_slice(b"}");
           }
+          // The parser only keeps the cooked value, which is all that matters for untagged templates, so we escape it again rather than writing the source.
+          // TODO Tagged templates (e.g. `String.raw`) need the raw value, but `parse-js` (as of 0.21) rejects them and doesn't keep it; see notes/Template literals.md.
           LiteralTemplatePart::String(str) => {
-            // TODO Escape.
-            out.extend_from_slice(str.as_bytes());
+            emit_escaped(out, str, b'`');
           }
         }
       }
@@ -422,10 +885,13 @@ fn emit_js_under_operator<'a>(
       for (i, decl) in declarators.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         emit_js(out, decl.pattern);
         if let Some(expr) = &decl.initializer {
+          out.space();
           out.extend_from_slice(b"=");
+          out.space();
           // This is only really done for the Comma operator, which is the only operator below Assignment.
           let operator = &OPERATORS[&OperatorName::Assignment];
           emit_js_under_operator(out, *expr, Some(operator.precedence));
@@ -440,11 +906,14 @@ fn emit_js_under_operator<'a>(
       for (i, e) in elements.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         if let Some(e) = e {
           emit_js(out, e.target);
           if let Some(v) = &e.default_value {
+            out.space();
             out.extend_from_slice(b"=");
+            out.space();
             emit_js(out, *v);
           }
         };
@@ -452,6 +921,7 @@ fn emit_js_under_operator<'a>(
       if let Some(r) = rest {
         if !elements.is_empty() {
           out.extend_from_slice(b",");
+          out.space();
         }
         out.extend_from_slice(b"...");
         emit_js(out, *r);
@@ -460,19 +930,28 @@ fn emit_js_under_operator<'a>(
     }
     Syntax::ObjectPattern { properties, rest } => {
       out.extend_from_slice(b"{");
+      let is_empty = properties.is_empty() && rest.is_none();
+      if !is_empty {
+        out.space();
+      };
       for (i, e) in properties.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         emit_js(out, *e);
       }
       if let Some(r) = rest {
         if !properties.is_empty() {
           out.extend_from_slice(b",");
+          out.space();
         }
         out.extend_from_slice(b"...");
         emit_js(out, *r);
       };
+      if !is_empty {
+        out.space();
+      };
       out.extend_from_slice(b"}");
     }
     Syntax::ClassOrFunctionName { name } => {
@@ -482,6 +961,7 @@ fn emit_js_under_operator<'a>(
       for (i, p) in parameters.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         };
         emit_js(out, *p);
       }
@@ -531,7 +1011,7 @@ fn emit_js_under_operator<'a>(
       out.extend_from_slice(b"(");
       emit_js(out, *signature);
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     Syntax::ParamDecl {
       rest,
@@ -543,7 +1023,9 @@ fn emit_js_under_operator<'a>(
       };
       emit_js(out, *pattern);
       if let Some(v) = default_value {
+        out.space();
         out.extend_from_slice(b"=");
+        out.space();
         emit_js(out, *v);
       }
     }
@@ -589,7 +1071,9 @@ fn emit_js_under_operator<'a>(
       if !can_omit_parentheses {
         out.extend_from_slice(b")");
       };
+      out.space();
       out.extend_from_slice(b"=>");
+      out.space();
       let must_parenthesise_body = match &body.stx {
         expr if is_comma_expression(expr) => true,
         // `{a: b}.b`, `{a: b} + 1`, etc. need to be wrapped.
@@ -630,20 +1114,43 @@ fn emit_js_under_operator<'a>(
         out.extend_from_slice(b"(");
       };
       emit_js_under_operator(out, *left, Some(operator.precedence));
-      out.extend_from_slice(
-        BINARY_OPERATOR_SYNTAX
-          .get(operator_name)
-          .unwrap()
-          .as_bytes(),
-      );
+      let syntax = BINARY_OPERATOR_SYNTAX.get(operator_name).unwrap();
+      if out.inline_script
+        && syntax.starts_with('>')
+        && out.ends_with(b"--")
+        && !out.is_beautified()
+      {
+        // Prevent `a-- > b` from becoming the HTML comment delimiter `a-->b`. When beautifying, there's already a space before the operator.
+        out.push(b' ');
+      };
+      if out.is_beautified() {
+        if *operator_name != OperatorName::Comma {
+          out.space();
+        };
+        out.extend_from_slice(syntax.trim().as_bytes());
+        out.space();
+      } else {
+        out.extend_from_slice(syntax.as_bytes());
+      };
       match operator_name {
-        OperatorName::Addition | OperatorName::Subtraction => {
+        OperatorName::Addition | OperatorName::Subtraction if !out.is_beautified() => {
           // Prevent potential confict with following unary operator e.g. `a+ +b` => `a++b`.
           // TODO Omit when possible.
           out.extend_from_slice(b" ");
         }
         _ => {}
       };
+      if out.inline_script
+        && syntax.ends_with('<')
+        && matches!(leftmost_expression(&right.stx), Syntax::UnaryExpr {
+          operator: OperatorName::LogicalNot,
+          ..
+        })
+        && !out.ends_with(b" ")
+      {
+        // Prevent `a < !--b` from becoming the HTML comment delimiter `a<!--b`, unless there's already a space after the operator (e.g. when beautifying).
+        out.push(b' ');
+      };
       emit_js_under_operator(out, *right, Some(operator.precedence));
       if must_parenthesise {
         out.extend_from_slice(b")");
@@ -675,6 +1182,7 @@ fn emit_js_under_operator<'a>(
       for (i, a) in arguments.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         emit_js(out, *a);
       }
@@ -700,9 +1208,13 @@ fn emit_js_under_operator<'a>(
         out.extend_from_slice(b"(");
       };
       emit_js_under_operator(out, *test, Some(operator.precedence));
+      out.space();
       out.extend_from_slice(b"?");
+      out.space();
       emit_js_under_operator(out, *consequent, Some(operator.precedence));
+      out.space();
       out.extend_from_slice(b":");
+      out.space();
       emit_js_under_operator(out, *alternate, Some(operator.precedence));
       if must_parenthesise {
         out.extend_from_slice(b")");
@@ -737,7 +1249,7 @@ fn emit_js_under_operator<'a>(
       out.extend_from_slice(b"(");
       emit_js(out, *signature);
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
       // TODO Omit parentheses if possible.
       if *parenthesised {
         out.extend_from_slice(b")");
@@ -820,6 +1332,7 @@ fn emit_js_under_operator<'a>(
       for (i, e) in elements.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         };
         match e {
           ArrayElement::Single(expr) => {
@@ -836,12 +1349,19 @@ fn emit_js_under_operator<'a>(
     }
     Syntax::LiteralObjectExpr { members } => {
       out.extend_from_slice(b"{");
+      if !members.is_empty() {
+        out.space();
+      };
       for (i, e) in members.iter().enumerate() {
         if i > 0 {
           out.extend_from_slice(b",");
+          out.space();
         }
         emit_js(out, *e);
       }
+      if !members.is_empty() {
+        out.space();
+      };
       out.extend_from_slice(b"}");
     }
     Syntax::LiteralNull {} => {
@@ -893,7 +1413,11 @@ fn emit_js_under_operator<'a>(
     }
     Syntax::BlockStmt { body } => {
       out.extend_from_slice(b"{");
-      emit_statements(out, &body);
+      emit_indented_statements(out, body);
+      let has_comments = emit_legal_comments_before_block_end(out, node);
+      if out.is_beautified() && (has_statements(body) || has_comments) {
+        out.newline();
+      };
       out.extend_from_slice(b"}");
     }
     Syntax::BreakStmt { label } => {
@@ -939,31 +1463,45 @@ fn emit_js_under_operator<'a>(
       out.extend_from_slice(b"export");
       emit_import_or_export_statement_trailer(out, Some(names), *from);
     }
-    Syntax::ExpressionStmt { expression } => {
-      emit_js(out, *expression);
-    }
+    Syntax::ExpressionStmt { expression } => match directive_source(node) {
+      // Directives must be written as is, as they're only recognised without escapes (e.g. `"use\x20strict"` isn't `"use strict"`).
+      Some(raw) => emit_source(out, raw.as_slice(), false),
+      None => emit_js(out, expression),
+    },
     Syntax::IfStmt {
       test,
       consequent,
       alternate,
     } => {
-      out.extend_from_slice(b"if(");
+      out.extend_from_slice(b"if");
+      out.space();
+      out.extend_from_slice(b"(");
       emit_js(out, *test);
       out.extend_from_slice(b")");
-      emit_js(out, *consequent);
+      emit_body(out, consequent);
       if let Some(alternate) = alternate {
-        if get_leaf_node_type(*consequent) == LeafNodeType::Block {
-          // Do nothing.
-        } else {
-          out.extend_from_slice(b";");
-        };
-        out.extend_from_slice(b"else");
-        if let Syntax::BlockStmt { .. } = &alternate.stx {
-          // Do nothing.
+        if out.is_beautified() {
+          out.extend_from_slice(b" else");
+          if let Syntax::IfStmt { .. } = &alternate.stx {
+            out.extend_from_slice(b" ");
+            emit_js(out, alternate);
+          } else {
+            emit_body(out, alternate);
+          };
         } else {
-          out.extend_from_slice(b" ");
+          if get_leaf_node_type(consequent) == LeafNodeType::Block {
+            // Do nothing.
+          } else {
+            out.extend_from_slice(b";");
+          };
+          out.extend_from_slice(b"else");
+          if let Syntax::BlockStmt { .. } = &alternate.stx {
+            // Do nothing.
+          } else {
+            out.extend_from_slice(b" ");
+          };
+          emit_js(out, alternate);
         };
-        emit_js(out, *alternate);
       };
     }
     Syntax::ForStmt { header, body } => {
@@ -974,6 +1512,7 @@ fn emit_js_under_operator<'a>(
           condition,
           post,
         } => {
+          out.space();
           out.extend_from_slice(b"(");
           match init {
             ForThreeInit::None => {}
@@ -981,10 +1520,12 @@ fn emit_js_under_operator<'a>(
           };
           out.extend_from_slice(b";");
           if let Some(n) = condition {
+            out.space();
             emit_js(out, *n);
           };
           out.extend_from_slice(b";");
           if let Some(n) = post {
+            out.space();
             emit_js(out, *n);
           };
         }
@@ -997,6 +1538,7 @@ fn emit_js_under_operator<'a>(
           if *await_ {
             out.extend_from_slice(b" await");
           }
+          out.space();
           out.extend_from_slice(b"(");
           match lhs {
             ForInOfStmtHeaderLhs::Declaration(n) | ForInOfStmtHeaderLhs::Pattern(n) => {
@@ -1012,7 +1554,7 @@ fn emit_js_under_operator<'a>(
         }
       };
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     Syntax::ImportStmt {
       default,
@@ -1025,7 +1567,7 @@ fn emit_js_under_operator<'a>(
         emit_js(out, *default);
         if names.is_some() {
           out.extend_from_slice(b",");
-        } else {
+        } else if !out.is_beautified() {
           out.extend_from_slice(b" ");
         };
       };
@@ -1055,58 +1597,79 @@ fn emit_js_under_operator<'a>(
       finally,
     } => {
       out.extend_from_slice(b"try");
-      emit_js(out, *wrapped);
+      emit_body(out, wrapped);
       if let Some(c) = catch {
+        out.space();
         emit_js(out, *c);
       }
       if let Some(f) = finally {
+        out.space();
         out.extend_from_slice(b"finally");
-        emit_js(out, *f);
+        emit_body(out, f);
       };
     }
     Syntax::WhileStmt { condition, body } => {
-      out.extend_from_slice(b"while(");
+      out.extend_from_slice(b"while");
+      out.space();
+      out.extend_from_slice(b"(");
       emit_js(out, *condition);
       out.extend_from_slice(b")");
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     Syntax::DoWhileStmt { condition, body } => {
       out.extend_from_slice(b"do");
-      if let Syntax::BlockStmt { .. } = &body.stx {
-        // Do nothing.
-      } else {
-        out.extend_from_slice(b" ");
-      };
-      emit_js(out, *body);
-      if get_leaf_node_type(*body) == LeafNodeType::Block {
-        // Do nothing.
+      if out.is_beautified() {
+        emit_body(out, body);
+        out.extend_from_slice(b" while ");
       } else {
-        out.extend_from_slice(b";");
+        if let Syntax::BlockStmt { .. } = &body.stx {
+          // Do nothing.
+        } else {
+          out.extend_from_slice(b" ");
+        };
+        emit_js(out, body);
+        if get_leaf_node_type(body) == LeafNodeType::Block {
+          // Do nothing.
+        } else {
+          out.extend_from_slice(b";");
+        };
+        out.extend_from_slice(b"while");
       };
-      out.extend_from_slice(b"while(");
+      out.extend_from_slice(b"(");
       emit_js(out, *condition);
       out.extend_from_slice(b")");
     }
     Syntax::SwitchStmt { test, branches } => {
-      out.extend_from_slice(b"switch(");
+      out.extend_from_slice(b"switch");
+      out.space();
+      out.extend_from_slice(b"(");
       emit_js(out, *test);
-      out.extend_from_slice(b"){");
+      out.extend_from_slice(b")");
+      out.space();
+      out.extend_from_slice(b"{");
+      out.indent();
       for (i, b) in branches.iter().enumerate() {
-        if i > 0 {
+        if i > 0 && !out.is_beautified() {
           out.extend_from_slice(b";");
         };
+        out.newline();
         emit_js(out, *b);
       }
+      out.dedent();
+      if !branches.is_empty() {
+        out.newline();
+      };
       out.extend_from_slice(b"}");
     }
     Syntax::CatchBlock { parameter, body } => {
       out.extend_from_slice(b"catch");
       if let Some(p) = parameter {
+        out.space();
         out.extend_from_slice(b"(");
         emit_js(out, *p);
         out.extend_from_slice(b")");
       }
-      emit_js(out, *body);
+      emit_body(out, body);
     }
     Syntax::SwitchBranch { case, body } => {
       match case {
@@ -1120,7 +1683,7 @@ fn emit_js_under_operator<'a>(
           out.extend_from_slice(b"default:");
         }
       }
-      emit_statements(out, &body);
+      emit_indented_statements(out, body);
     }
     Syntax::ObjectPatternProperty {
       key,
@@ -1130,7 +1693,7 @@ fn emit_js_under_operator<'a>(
     } => {
       match key {
         ClassOrObjectMemberKey::Direct(name) => {
-          out.extend_from_slice(name.as_slice());
+          emit_source(out, name.as_slice(), false);
         }
         ClassOrObjectMemberKey::Computed(expr) => {
           out.extend_from_slice(b"[");
@@ -1138,12 +1701,21 @@ fn emit_js_under_operator<'a>(
           out.extend_from_slice(b"]");
         }
       };
-      if !*shorthand {
+      // The parser also marks properties in destructuring assignments as shorthand even when they have a different target (e.g. `({a: b} = c)`), so check the target.
+      let is_shorthand = *shorthand
+        && matches!(
+          (key, &target.stx),
+          (ClassOrObjectMemberKey::Direct(key), Syntax::IdentifierPattern { name }) if key == name
+        );
+      if !is_shorthand {
         out.extend_from_slice(b":");
+        out.space();
         emit_js(out, *target);
       };
       if let Some(v) = default_value {
+        out.space();
         out.extend_from_slice(b"=");
+        out.space();
         emit_js(out, *v);
       };
     }
@@ -1182,7 +1754,14 @@ fn emit_js_under_operator<'a>(
       if must_parenthesise {
         out.extend_from_slice(b"(");
       };
+      let left_start = out.len();
       emit_js_under_operator(out, *left, Some(operator.precedence));
+      // A dot directly after an integer would be parsed as its decimal point (e.g. `1.toString()`), so add one.
+      if let Syntax::LiteralNumberExpr { .. } = &left.stx {
+        if !*optional_chaining && out[left_start..].iter().all(|c| c.is_ascii_digit()) {
+          out.extend_from_slice(b".");
+        };
+      };
       out.extend_from_slice(
         BINARY_OPERATOR_SYNTAX
           .get(operator_name)
@@ -1214,6 +1793,7 @@ fn emit_js_under_operator<'a>(
     Syntax::LabelStmt { name, statement } => {
       out.extend_from_slice(name.as_slice());
       out.extend_from_slice(b":");
+      out.space();
       emit_js(out, *statement);
     }
     Syntax::CallArg { spread, value } => {
@@ -1,5 +1,14 @@
 use crate::emit::emit_js;
+use crate::emit::Emitter;
+use crate::nesting::find_too_deep_source;
 use crate::minify::minify_js;
+use crate::source_map::Mapping;
+use crate::source_map::SourceMap;
+use crate::MinifyError;
+use crate::MinifyOptions;
+use crate::MinifyOutputs;
+use crate::NameCache;
+use crate::QuoteStyle;
 use crate::TopLevelMode;
 use parse_js::lex::Lexer;
 use parse_js::parse::Parser;
@@ -7,14 +16,23 @@ use parse_js::session::Session;
 use parse_js::symbol::SymbolGenerator;
 
 fn check(top_level_mode: TopLevelMode, src: &str, expected: &str) -> () {
+  check_with_options(top_level_mode, &MinifyOptions::default(), src, expected);
+}
+
+fn check_with_options(
+  top_level_mode: TopLevelMode,
+  options: &MinifyOptions,
+  src: &str,
+  expected: &str,
+) -> () {
   let session = Session::new();
   let mut parser = Parser::new(Lexer::new(src.as_bytes()));
   let node = parser
     .parse_top_level(&session, SymbolGenerator::new(), top_level_mode)
     .unwrap();
   let mut out = Vec::new();
-  minify_js(&session, node);
-  emit_js(&mut out, node);
+  minify_js(&session, options, node, None).unwrap();
+  emit_js(&mut Emitter::new(&mut out).with_options(options), node);
   assert_eq!(
     unsafe { std::str::from_utf8_unchecked(out.as_slice()) },
     expected
@@ -23,8 +41,14 @@ fn check(top_level_mode: TopLevelMode, src: &str, expected: &str) -> () {
 
 #[test]
 fn test_emit_global() {
-  check(
+  // Don't fold `(1/7)/(2/7)` or remove the unused declarations, as we want to test how they're emitted.
+  check_with_options(
     TopLevelMode::Global,
+    &MinifyOptions {
+      fold_constants: false,
+      remove_unused: false,
+      ..MinifyOptions::default()
+    },
     r#"
       /* Test code */
       function * gen () {
@@ -55,15 +79,15 @@ fn test_emit_global() {
       }()
     "#,
     "\
-      function*gen(){yield*`hello world!`}\
+      function*gen(){yield*\"hello world!\"}\
       !()=>{\
       var a=(()=>{a()});\
       com.java.names.long;\
       module.functions;\
-      var b=1,c,{brown:d,_:[e,f,,,...g],...h}=i;\
-      (({the:b}=a,[c]=2)=>{{let b=a(e)}b,c,d,e;return})();\
-      const j=({})=>{};\
-      const k=a=>(1,2),l=(1/7)/(2/7)\
+      var b=1,e,{brown:d,_:[c,b,,,...b],...b}=i;\
+      (({the:b}=a,[e]=2)=>{{let b=a(c)}b,e,d,c;return})();\
+      const f=({})=>{};\
+      const g=a=>(1,2),h=(1/7)/(2/7)\
       }()\
     ",
   )
@@ -98,15 +122,15 @@ fn test_emit_module() {
       };
     "#,
     "\
-      import a,{useState as b,useEffect as c,createElement as d,memo as e}from\"react\";\
-      import{default as f}from\"react-dom\";\
-      const g=1;\
-      const {meaning:h}={meaning:42},i=10;\
-      console.log(`meaning`,h);\
-      function j(){}\
-      console.log(j(i));\
-      f.hello();\
-      export{h as meaning,i as life,j as default,b as use_state,c as reactUseEffect}\
+      import c,{useState as d,useEffect as a,createElement as e,memo as f}from\"react\";\
+      import{default as b}from\"react-dom\";\
+      const j=1;\
+      const {meaning:g}={meaning:42},h=10;\
+      console.log(\"meaning\",g);\
+      function i(){}\
+      console.log(i(h));\
+      b.hello();\
+      export{g as meaning,h as life,i as default,d as use_state,a as reactUseEffect}\
     ",
   );
   check(
@@ -224,7 +248,7 @@ fn test_emit_jsx() {
 
       render(<CompImp><CompLocal/></CompImp>);
     "#,
-    r#"import A from"./comp";let a={a:`div`};const B=()=><a.a><strong/></a.a>;render(<A><B/></A>)"#,
+    r#"import A from"./comp";let a={a:"div"};const B=()=><a.a><strong/></a.a>;render(<A><B/></A>)"#,
   );
 }
 
@@ -301,3 +325,1242 @@ fn test_advanced_if_minification() {
     r#"var foo=(a=>{var d,c;if(!a)return d=3,d;var b=1;if(cond)return c=2,c;return b})"#,
   );
 }
+
+#[test]
+fn test_minify_options() {
+  let src = r#"
+    function foo(arg) {
+      debugger;
+      if (arg) {
+        bar();
+      }
+    }
+  "#;
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      mangle: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "var foo=(arg=>{arg&&bar()})",
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      arrow_functions: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "function foo(a){a&&bar()}",
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      if_to_expression: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "var foo=(a=>{if(a)bar()})",
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      drop_debugger: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "var foo=(a=>{debugger;a&&bar()})",
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      if_to_expression: false,
+      unwrap_blocks: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "var foo=(a=>{if(a){bar()}})",
+  );
+  // Functions that use `this` or `arguments`, including through nested arrow functions, can't become arrow functions.
+  check(
+    TopLevelMode::Global,
+    "x(function(){return this});function f(a,b,c){return arguments.length}f();x(function(){return()=>this});x(function(){return 1})",
+    "function f(a,b,c){return arguments.length}x(function(){return this});f();x(function(){return ()=>this});x((()=>1))",
+  );
+}
+
+#[test]
+fn test_emit_source_map() {
+  let src = "let first = 1;\nfunction inc() {\n  first++;\n}\n";
+  let session = Session::new();
+  let mut parser = Parser::new(Lexer::new(src.as_bytes()));
+  let node = parser
+    .parse_top_level(&session, SymbolGenerator::new(), TopLevelMode::Module)
+    .unwrap();
+  minify_js(&session, &MinifyOptions::default(), node, None).unwrap();
+  let mut out = Vec::new();
+  let mut map = SourceMap::new("input.js");
+  emit_js(
+    &mut Emitter::with_source_map(&mut out, src.as_bytes(), &mut map),
+    node,
+  );
+  assert_eq!(
+    unsafe { std::str::from_utf8_unchecked(out.as_slice()) },
+    "var b=(()=>{a++});let a=1",
+  );
+  let find = |generated_column: u32| {
+    map
+      .mappings()
+      .iter()
+      .find(|m| m.generated_line == 0 && m.generated_column == generated_column)
+      .copied()
+      .unwrap()
+  };
+  // `b` in `var b` is `inc` on line 2.
+  let m = find(4);
+  assert_eq!((m.original_line, m.original_column), (1, 9));
+  assert_eq!(map.names()[m.name.unwrap() as usize], "inc");
+  // `a` in `a++` is `first` on line 3.
+  let m = find(12);
+  assert_eq!((m.original_line, m.original_column), (2, 2));
+  assert_eq!(map.names()[m.name.unwrap() as usize], "first");
+  // `let` is on line 1.
+  let m = find(18);
+  assert_eq!((m.original_line, m.original_column, m.name), (0, 0, None));
+  assert_eq!(
+    map.to_json(),
+    format!(
+      r#"{{"version":3,"sources":["input.js"],"names":["inc","first"],"mappings":"{}"}}"#,
+      map.encode_mappings()
+    )
+  );
+}
+
+#[test]
+fn test_source_map_from_json() {
+  let mut map = SourceMap::new("input.js");
+  let inc = map.add_name("inc");
+  map.add_mapping(Mapping {
+    generated_line: 0,
+    generated_column: 4,
+    source: 0,
+    original_line: 1,
+    original_column: 9,
+    name: Some(inc),
+  });
+  map.add_mapping(Mapping {
+    generated_line: 2,
+    generated_column: 1,
+    source: 0,
+    original_line: 0,
+    original_column: 0,
+    name: None,
+  });
+  let parsed = SourceMap::from_json(&map.to_json()).unwrap();
+  assert_eq!(parsed.sources, vec!["input.js".to_string()]);
+  assert_eq!(parsed.names(), map.names());
+  assert_eq!(parsed.mappings(), map.mappings());
+
+  let parsed = SourceMap::from_json(
+    r#"{"version":3,"sourceRoot":"src/","sources":["a.ts",null],"sourcesContent":["x"],"names":[],"mappings":"AAAA,CCCC;A"}"#,
+  )
+  .unwrap();
+  assert_eq!(parsed.sources, vec![
+    "src/a.ts".to_string(),
+    "src/".to_string()
+  ]);
+  assert_eq!(parsed.sources_content, vec![Some("x".to_string()), None]);
+  // The single-field segment on the second line has no source, so it's dropped.
+  assert_eq!(parsed.mappings().len(), 2);
+  assert_eq!(parsed.mappings()[1].source, 1);
+
+  assert!(SourceMap::from_json(r#"{"version":2,"sources":[],"mappings":""}"#).is_err());
+  assert!(SourceMap::from_json(r#"{"version":3,"sources":[],"mappings":"AAAA"}"#).is_err());
+  assert!(SourceMap::from_json(r#"{"version":3,"sources":["a"],"mappings":"A!"}"#).is_err());
+}
+
+#[test]
+fn test_minify_with_input_source_map() {
+  // Pretend the source was compiled from `main.ts`, where every line is indented by two more spaces and shifted down by one line, and `first` was originally called `firstValue`.
+  let src = "let first = 1;\nfunction inc() {\n  first++;\n}\n";
+  let mut input = SourceMap::new("main.ts");
+  let first_value = input.add_name("firstValue");
+  for (generated_line, generated_column, name) in [
+    (0, 0, None),
+    (0, 4, Some(first_value)),
+    (1, 0, None),
+    (2, 2, Some(first_value)),
+  ] {
+    input.add_mapping(Mapping {
+      generated_line,
+      generated_column,
+      source: 0,
+      original_line: generated_line + 1,
+      original_column: generated_column + 2,
+      name,
+    });
+  }
+  let session = Session::new();
+  let mut out = Vec::new();
+  let mut outputs = MinifyOutputs {
+    source_map: Some(SourceMap::new("input.js")),
+    input_source_map: Some(input),
+    ..MinifyOutputs::default()
+  };
+  crate::minify_with(
+    &session,
+    TopLevelMode::Module,
+    &MinifyOptions::default(),
+    src.as_bytes(),
+    &mut out,
+    &mut outputs,
+  )
+  .unwrap();
+  let map = outputs.source_map.unwrap();
+  assert_eq!(
+    unsafe { std::str::from_utf8_unchecked(out.as_slice()) },
+    "var b=(()=>{a++});let a=1",
+  );
+  assert_eq!(map.sources, vec!["main.ts".to_string()]);
+  let find = |generated_column: u32| {
+    map
+      .mappings()
+      .iter()
+      .find(|m| m.generated_line == 0 && m.generated_column == generated_column)
+      .copied()
+      .unwrap()
+  };
+  // `b` in `var b` is `inc`, which falls within the mapping for the start of line 2.
+  let m = find(4);
+  assert_eq!((m.original_line, m.original_column), (2, 2));
+  assert_eq!(map.names()[m.name.unwrap() as usize], "inc");
+  // `a` in `a++` takes its name from the input map.
+  let m = find(12);
+  assert_eq!((m.original_line, m.original_column), (3, 4));
+  assert_eq!(map.names()[m.name.unwrap() as usize], "firstValue");
+}
+
+#[test]
+fn test_legal_comments() {
+  let src = r#"/*! Banner */
+// Not legal.
+let x = 1;
+/** @license MIT */
+function foo() {
+  //! Inside.
+  x++;
+  /* "@preserve" */
+  if (x) {
+    x--;
+  }
+}
+/* Removed statement. @preserve */
+;
+"#;
+  let minify = |options: &MinifyOptions| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      TopLevelMode::Module,
+      options,
+      src.as_bytes(),
+      &mut out,
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!(
+    minify(&MinifyOptions::default()),
+    "var b=(()=>{a++;a&&a--});let a=1"
+  );
+  assert_eq!(
+    minify(&MinifyOptions {
+      legal_comments: true,
+      ..MinifyOptions::default()
+    }),
+    "/** @license MIT */var b=(()=>{//! Inside.\na++;/* \"@preserve\" */a&&a--});/*! Banner */let a=1/* Removed statement. @preserve */"
+  );
+
+  let session = Session::new();
+  let mut out = Vec::new();
+  let mut outputs = MinifyOutputs {
+    legal_comments: Some(Vec::new()),
+    ..MinifyOutputs::default()
+  };
+  crate::minify_with(
+    &session,
+    TopLevelMode::Module,
+    &MinifyOptions::default(),
+    src.as_bytes(),
+    &mut out,
+    &mut outputs,
+  )
+  .unwrap();
+  assert_eq!(
+    String::from_utf8(out).unwrap(),
+    "var b=(()=>{a++;a&&a--});let a=1"
+  );
+  assert_eq!(
+    String::from_utf8(outputs.legal_comments.unwrap()).unwrap(),
+    "/*! Banner */\n/** @license MIT */\n//! Inside.\n/* \"@preserve\" */\n/* Removed statement. @preserve */\n"
+  );
+
+  // Comments after the last statement of the file or a block have no statement to follow.
+  let trailing = |src: &str| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      TopLevelMode::Global,
+      &MinifyOptions {
+        legal_comments: true,
+        ..MinifyOptions::default()
+      },
+      src.as_bytes(),
+      &mut out,
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!(
+    trailing("let a=1;foo(a)/*! trailing */"),
+    "let a=1;foo(a)/*! trailing */"
+  );
+  assert_eq!(
+    trailing("function f(b) { g(b); //! End of f.\n}\nfunction h() { /*! Empty. */ }"),
+    "var f=(a=>{g(a)//! End of f.\n});var h=(()=>{/*! Empty. */})"
+  );
+}
+
+#[test]
+fn test_inline_script() {
+  let src = r#"/*! </script> <!-- --> */
+x("</script>", '<!-- a -->', `</SCRIPT ${y}-->`, "<\!--", "</scrip", {"</script>": 1, '\<!--': 2});
+x(/<\/script>[</script]/i, /(?<!--)\<!--[<!--]-->/, a < /script/.test(b), a < !--b, a << !--b, a-- > b, a-- >= b);
+"#;
+  let minify = |options: &MinifyOptions| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      TopLevelMode::Global,
+      options,
+      src.as_bytes(),
+      &mut out,
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!(
+    minify(&MinifyOptions {
+      legal_comments: true,
+      ..MinifyOptions::default()
+    }),
+    r#"/*! </script> <!-- --> */x("</script>","<!-- a -->",`</SCRIPT ${y}-->`,"<!--","</scrip",{"</script>":1,'\<!--':2});x(/<\/script>[</script]/i,/(?<!--)\<!--[<!--]-->/,a</script/.test(b),a<!--b,a<<!--b,a-->b,a-->=b)"#
+  );
+  assert_eq!(
+    minify(&MinifyOptions {
+      legal_comments: true,
+      inline_script: true,
+      ..MinifyOptions::default()
+    }),
+    r#"/*! <\/script> <\!-- --\> */x("<\/script>","\x3c!-- a --\x3e",`<\/SCRIPT ${y}--\x3e`,"\x3c!--","</scrip",{"<\/script>":1,'\x3c!--':2});x(/<\/script>[<\/script]/i,/(?<!\x2d-)\<!\x2d-[\x3c!--]--\x3e/,a< /script/.test(b),a< !--b,a<< !--b,a-- >b,a-- >=b)"#
+  );
+  // When beautifying, the spaces around operators already separate the delimiters.
+  let session = Session::new();
+  let mut out = Vec::new();
+  crate::minify(
+    &session,
+    TopLevelMode::Global,
+    &MinifyOptions {
+      inline_script: true,
+      beautify: true,
+      ..MinifyOptions::default()
+    },
+    b"x(a < !--b, a-- > b, a < /script/.test(b));",
+    &mut out,
+  )
+  .unwrap();
+  assert_eq!(
+    String::from_utf8(out).unwrap(),
+    "x(a < !--b, a-- > b, a < /script/.test(b));"
+  );
+}
+
+#[test]
+fn test_dead_code() {
+  // `inner` is only called from unreachable code, so would otherwise be removed as unused.
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      remove_unused: false,
+      ..MinifyOptions::default()
+    },
+    r#"
+      function foo(x) {
+        if (x) {
+          return bar();
+          baz();
+        }
+        throw x;
+        var y = 1, {z} = x;
+        function inner() { return y + z; }
+        let unused = 2;
+        inner();
+      }
+    "#,
+    "var foo=(a=>{var d=(()=>b+ c);if(a)return bar();throw a;var b,c})",
+  );
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        while (x) {
+          if (x()) {
+            continue;
+            x = 1;
+          }
+          break;
+          x();
+        }
+        switch (x) {
+          case 1:
+            bar();
+            break;
+            bar();
+          default:
+            baz();
+        }
+      }
+    "#,
+    "var foo=(a=>{while(a){if(a())continue;break};switch(a){case 1:bar();break;default:baz()}})",
+  );
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        if (false) {
+          var a = x();
+        } else if (!0) {
+          bar();
+        } else {
+          baz();
+        }
+        if (0) bar();
+        if ("") bar(); else baz();
+        while (null) {
+          function b() {}
+        }
+        do { bar(); } while (false);
+        return [a, b];
+      }
+    "#,
+    "var foo=(()=>{var b=(()=>{});bar();baz();do bar();while(!1);return [a,b];var a})",
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      dead_code: false,
+      ..MinifyOptions::default()
+    },
+    "function foo() { if (false) { bar(); } return; baz(); }",
+    "var foo=(()=>{!1&&bar();return;baz()})",
+  );
+  // The top level is processed too, but unreachable imports, exports and lexical declarations are kept, as they're visible outside the module or script.
+  check(
+    TopLevelMode::Global,
+    "if (false) { a() } while (false) { b() } c(); throw d; e(); var f = 1; let g = 2;",
+    "c();throw d;let g=2;var f",
+  );
+  check(
+    TopLevelMode::Module,
+    r#"
+      import a from "a";
+      throw a;
+      b();
+      export const c = 1;
+      export { d };
+      let d = 2;
+      export default 3;
+    "#,
+    r#"import a from"a";throw a;const c=1;let b=2;export default 3;export{c as c,b as d}"#,
+  );
+}
+
+#[test]
+fn test_fold_constants() {
+  check(
+    TopLevelMode::Global,
+    r#"
+      x(1 + 2 * 3, "a" + 1 + "b", 1 + 2 + "c", "1" - 1, 0.1 + 0.2, 2 ** 10, 7 % -3, 1 / 0, 0 - 1);
+      x(!0, !"", -"2" * -1, +true, ~5, ~-6, typeof null, typeof "a", typeof undefined, void 1);
+      x(1 < 2, "b" < "a", "10" < "9", 10 < "9", 1 < NaN, null == undefined, null == 0, "1" == 1, true == "1", "1" === 1, 1 != 2);
+      x(5 & 3, 5 | 3, 5 ^ 3, 1 << 31 >>> 0, -1 >>> 28, 1e21 + "", 1 / 3 + "", 1e-7 + "", 123e-20 + "");
+      x(0 || y, 1 && y(), null ?? y, 0 ?? y, 1 ? y : z, "" ? y : z, 0 || a.b, 0 || eval, 1 && function () {});
+    "#,
+    "x(7,\"a1b\",\"3c\",0,.30000000000000004,1024,1,1/0,0- 1);x(!0,!0,-\"2\"*-1,1,~5,5,\"object\",\"string\",\"undefined\",void 1);x(!0,!1,!0,!1,1<NaN,!0,!1,!0,!0,!1,!0);x(1,7,6,1<<31>>>0,15,\"1e+21\",\"0.3333333333333333\",\"1e-7\",\"1.23e-18\");x(y,y(),y,0,y,z,0||a.b,0||eval,1&&(()=>{}))",
+  );
+  let src =
+    "let undefined = 1; x(typeof undefined); export function f() { if (1 + 1 !== 2) { y(); } }";
+  check(
+    TopLevelMode::Module,
+    src,
+    "let a=1;x(typeof a);function b(){}export{b as f}",
+  );
+  check_with_options(
+    TopLevelMode::Module,
+    &MinifyOptions {
+      fold_constants: false,
+      ..MinifyOptions::default()
+    },
+    src,
+    "let a=1;x(typeof a);function b(){1+ 1!==2&&y()}export{b as f}",
+  );
+  // Operands replacing a parenthesised expression keep its parentheses, or aren't used if parentheses can't be kept.
+  check(
+    TopLevelMode::Global,
+    "x = new (1 ? f() : g); x = new (0 || f()); x = (1 ? -a : b) ** 2; x = d - (1 ? a - b : c); x = (1 ? a ** b : c) ** 2; f(1 ? (a, b) : c);",
+    "x=new (1?f():g);x=new (0||f());x=(1?-a:b)**2;x=d- (a- b);x=(a**b)**2;f(1?(a,b):c)",
+  );
+  // U+0085 isn't whitespace in JS, unlike U+00A0, so it isn't trimmed and the string isn't folded to 1.
+  check(
+    TopLevelMode::Global,
+    "x(+\"\u{85}1\", +\"\u{a0}1\\n\")",
+    "x(+\"\u{85}1\",1)",
+  );
+}
+
+#[test]
+fn test_remove_unused() {
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(a, b, c) {
+        var x = 1, y = bar(), z;
+        let s = `s`;
+        const f = () => 1;
+        function unused() {}
+        function used() { return a; }
+        return used();
+      }
+      foo();
+    "#,
+    "var foo=(a=>{var b=(()=>a);var f=bar();return b()});foo()",
+  );
+  // Destructuring and assigned variables are kept, as is the top level.
+  check(
+    TopLevelMode::Global,
+    r#"
+      var top = 1;
+      function foo({a}, b) {
+        let [c] = d;
+        var e = 1;
+        e = 2;
+      }
+      foo();
+    "#,
+    "var foo=(({a:b})=>{let [e]=d;var a=1;a=2});var top=1;foo()",
+  );
+  // `eval` could use any variable.
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(a) {
+        var b = 1;
+        eval("a");
+      }
+      foo();
+    "#,
+    "function foo(a){var b=1;eval(\"a\")}foo()",
+  );
+  // Setters are parsed as methods and must keep their parameter, and functions that use `arguments` keep all of theirs.
+  check(
+    TopLevelMode::Global,
+    "class A { static set b(v) {} m(a, b) {} } x = { set c(v) {}, n(a, b) { return arguments.length } };",
+    "class A{static b(a){}m(a){}}x={c(a){},n(a,b){return arguments.length}}",
+  );
+}
+
+#[test]
+fn test_direct_eval() {
+  // Only the scopes containing the call keep their names, which includes the top level, so `indirect` isn't renamed either. `inner` is still minified, and converted to an arrow function. Indirect calls can only use globals, so they don't stop `indirect` from being converted.
+  check(
+    TopLevelMode::Module,
+    r#"
+      const config = 1;
+      export function run(code, other) {
+        const local = 2;
+        function inner(first, second) {
+          return first + second + other;
+        }
+        return eval(code) + inner(local, config);
+      }
+      export const indirect = function (code, other) {
+        return (0, eval)(code) + eval?.(other);
+      };
+    "#,
+    "const config=1;function run(code,other){var inner=((a,b)=>a+ b+ other);const local=2;return eval(code)+ inner(local,config)}const indirect=((a,b)=>(0,eval)(a)+ eval?.(b));export{run as run,indirect as indirect}",
+  );
+}
+
+#[test]
+fn test_directives() {
+  // Directives are kept as written, even if escaped, with hoisted declarations after them. Duplicates are removed, as are `"use strict"` in classes and nested strict functions, and strings that aren't directives, including ones folded into what looks like one.
+  let src = r#"
+    'use client';
+    "use client";
+    function outer(a) {
+      "use strict";
+      "use\x20asm";
+      "use asm";
+      { "not a directive"; }
+      return inner(a);
+      function inner(b) {
+        "use strict";
+        return b;
+      }
+    }
+    function folded() {
+      "use " + "strict";
+      return 1;
+    }
+    class C {
+      m() {
+        "use strict";
+        return 1;
+      }
+    }
+    outer(folded());
+  "#;
+  check(
+    TopLevelMode::Global,
+    src,
+    r#"'use client';var outer=(a=>{"use strict";"use\x20asm";"use asm";var b=(a=>a);return b(a)});var folded=(()=>1);class C{m(){return 1}}outer(folded())"#,
+  );
+  // Everything is already strict at the top level of a strict script or module.
+  check(
+    TopLevelMode::Global,
+    &format!("\"use strict\";{}", src),
+    r#""use strict";'use client';var outer=(a=>{"use\x20asm";"use asm";var b=(a=>a);return b(a)});var folded=(()=>1);class C{m(){return 1}}outer(folded())"#,
+  );
+  check(
+    TopLevelMode::Module,
+    src,
+    r#"'use client';var a=(a=>{"use\x20asm";"use asm";var b=(a=>a);return b(a)});var b=(()=>1);class c{m(){return 1}}a(b())"#,
+  );
+  // A parenthesised string isn't a directive, so it doesn't make the code strict and is removed like other strings, even if a comment follows it. It also ends the directive prologue, so strings after it aren't directives either. Parentheses are only found when minifying from source.
+  let minify = |src: &str| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      TopLevelMode::Global,
+      &MinifyOptions::default(),
+      src.as_bytes(),
+      &mut out,
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!(
+    minify(r#"("use strict");function f(a){( "use strict" /* not a directive */ );"use asm";return a}f(1);"#),
+    "var f=(a=>a);f(1)"
+  );
+  assert_eq!(
+    minify(r#"'use strict';("use strict");let a=1;foo(a)"#),
+    r#"'use strict';let a=1;foo(a)"#
+  );
+}
+
+#[test]
+fn test_deterministic_output() {
+  // Hoisted functions and names must be in source order rather than the order maps happen to hash them in, so the output is the same on every run.
+  let options = MinifyOptions {
+    mangle_properties: Some("^_".parse().unwrap()),
+    ..MinifyOptions::default()
+  };
+  for _ in 0..100 {
+    check_with_options(
+      TopLevelMode::Module,
+      &options,
+      r#"
+        function e(x) { return d(x) + c(x); }
+        function d(x) { function z() { return x._p; } function y() { return z(); } return y(); }
+        function c(x) { var q = x._q, r = x._r; return q + r; }
+        function b() { return { _p: 1, _q: 2, _r: 3 }; }
+        function a() { return e(b()); }
+        a();
+      "#,
+      "var a=(a=>b(a)+ c(a));var b=(a=>{var b=(()=>a.a);var c=(()=>b());return c()});var c=(a=>{var b=a.b,c=a.c;return b+ c});var d=(()=>({a:1,b:2,c:3}));var e=(()=>a(d()));e()",
+    );
+  }
+}
+
+#[test]
+fn test_inline() {
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        const a = 1, s = "str";
+        const double = (y) => y * 2;
+        if (x) {
+          const half = function (y) { return y / 2; };
+          return half(x);
+        }
+        return double(x) + a + s;
+      }
+      foo();
+    "#,
+    "var foo=(a=>{if(a)return (a=>a/2)(a);return (a=>a*2)(a)+ 1+ \"str\"});foo()",
+  );
+  // Usages that must stay identifiers, calls under a shadowing declaration, and functions used more than once are kept.
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        const a = 1, b = 2, c = 3;
+        const bar = () => x;
+        const baz = () => x;
+        a.toString();
+        b++;
+        if (x) {
+          let x = 1;
+          bar(x);
+        }
+        return [baz(), baz, {c}];
+      }
+      foo();
+    "#,
+    "var foo=(a=>{const c=1,d=2,e=3;const f=()=>a;const b=()=>a;c.toString();d++;if(a){let a=1;f(a)};return [b(),b,{c:e}]});foo()",
+  );
+  // Functions aren't inlined into a loop they weren't declared in, as they'd be created on every iteration.
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        const double = function (a) { return a * 2; };
+        while (x) double(x);
+        for (;;) {
+          const triple = (a) => a * 3;
+          triple(x);
+        }
+      }
+      foo();
+    "#,
+    "var foo=(a=>{const b=(a=>a*2);while(a)b(a);for(;;)(a=>a*3)(a)});foo()",
+  );
+}
+
+#[test]
+fn test_mangle_properties() {
+  let options = MinifyOptions {
+    mangle_properties: Some("^_".parse().unwrap()),
+    ..MinifyOptions::default()
+  };
+  check_with_options(
+    TopLevelMode::Global,
+    &options,
+    r#"
+      class Counter {
+        _count = 0;
+        _inc() {
+          this._count++;
+          return { _count: this._count, length: 1, "_quoted": 2 };
+        }
+      }
+      var c = new Counter();
+      ({ _inc, __proto__: _count } = c);
+      var _global = 1;
+      console.log({ _global, a: c._inc()._count, b: c["_count"] });
+    "#,
+    "class Counter{_count=0;c(){this._count++;return {_count:this._count,length:1,\"_quoted\":2}}}var c=new Counter();({c:_inc,__proto__:_count}=c);var _global=1;console.log({d:_global,a:c.c()._count,b:c[\"_count\"]})",
+  );
+}
+
+#[test]
+fn test_mangle_properties_accessed_by_string() {
+  let options = MinifyOptions {
+    mangle_properties: Some("^_".parse().unwrap()),
+    ..MinifyOptions::default()
+  };
+  // Properties whose names appear in strings anywhere could be accessed through them, so they aren't mangled, and mangled names don't collide with them.
+  check_with_options(
+    TopLevelMode::Global,
+    &options,
+    r#"
+      var p = { "a": 1, _x: 2 };
+      var o = { "_c": 1, ["_d"]: 4, _e: 5, _f: 6, _g: 7, _h: 8 };
+      console.log(p.a, p._x, o._c, o._d, o._e, "_e" in o, o?.["_f"], o.hasOwnProperty("_g"), o._h);
+    "#,
+    r#"var p={"a":1,b:2};var o={"_c":1,["_d"]:4,_e:5,_f:6,_g:7,c:8};console.log(p.a,p.b,o._c,o._d,o._e,"_e" in o,o?.["_f"],o.hasOwnProperty("_g"),o.c)"#,
+  );
+}
+
+#[test]
+fn test_name_cache() {
+  let options = MinifyOptions {
+    mangle_properties: Some("^_".parse().unwrap()),
+    ..MinifyOptions::default()
+  };
+  let src =
+    "let first = 1, second = 2; console.log(first, second, { _count: first, _size: second });";
+  let session = Session::new();
+  let mut parser = Parser::new(Lexer::new(src.as_bytes()));
+  let node = parser
+    .parse_top_level(&session, SymbolGenerator::new(), TopLevelMode::Module)
+    .unwrap();
+  let mut name_cache =
+    NameCache::from_json(r#"{"properties":{"_count":"a"},"topLevel":{"second":"a"}}"#).unwrap();
+  let mut out = Vec::new();
+  minify_js(&session, &options, node, Some(&mut name_cache)).unwrap();
+  emit_js(&mut Emitter::new(&mut out), node);
+  // Cached names are reused, and new names are chosen around them.
+  assert_eq!(
+    unsafe { std::str::from_utf8_unchecked(out.as_slice()) },
+    "let b=1,a=2;console.log(b,a,{a:b,b:a})"
+  );
+  assert_eq!(
+    name_cache.to_json(),
+    r#"{"properties":{"_count":"a","_size":"b"},"topLevel":{"first":"b","second":"a"}}"#
+  );
+
+  // Cached names are written as-is, so ones that aren't identifiers are rejected when read, and skipped if added directly.
+  assert!(NameCache::from_json(r#"{"topLevel":{"helper":"1bad"}}"#).is_err());
+  assert!(NameCache::from_json(r#"{"properties":{"_count":"a-b"}}"#).is_err());
+  assert!(NameCache::from_json(r#"{"topLevel":{"helper":""}}"#).is_err());
+  let session = Session::new();
+  let mut parser = Parser::new(Lexer::new(src.as_bytes()));
+  let node = parser
+    .parse_top_level(&session, SymbolGenerator::new(), TopLevelMode::Module)
+    .unwrap();
+  let mut name_cache = NameCache::new();
+  name_cache.top_level.insert("first".to_string(), "1bad".to_string());
+  name_cache.properties.insert("_count".to_string(), "".to_string());
+  let mut out = Vec::new();
+  minify_js(&session, &options, node, Some(&mut name_cache)).unwrap();
+  emit_js(&mut Emitter::new(&mut out), node);
+  assert_eq!(
+    unsafe { std::str::from_utf8_unchecked(out.as_slice()) },
+    "let a=1,b=2;console.log(a,b,{a:a,b:b})"
+  );
+}
+
+#[test]
+fn test_lexical_lifetimes() {
+  // Variables that are no longer used by the time another is assigned share its name and `var`.
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        var a = x + 1, b = x * 2;
+        log(a, b);
+        var c = x;
+        log(c);
+        for (var i = 0; i < x; i++) {
+          var t = i * 2;
+          log(t);
+        }
+        var d = x;
+        log({d});
+      }
+      foo();
+    "#,
+    "var foo=(b=>{var a=b+ 1,c=b*2;log(a,c);a=b;log(a);for(var a=0;a<b;a++){c=a*2;log(c)};a=b;log({d:a})});foo()",
+  );
+  // Variables that are used before they're assigned in a loop or branch, or used in a nested closure, keep their own names.
+  check(
+    TopLevelMode::Global,
+    r#"
+      function foo(x) {
+        var a = x;
+        log(a);
+        for (;;) {
+          log(b);
+          var b = x;
+        }
+        var c = x;
+        log(() => c);
+        switch (x) {
+          case 1:
+            var d = x;
+          case 2:
+            log(d);
+        }
+      }
+      foo();
+    "#,
+    "var foo=(a=>{var b=a;log(b);for(;;){log(c);var c=a};var d=a;log(()=>d);switch(a){case 1:var e=a;case 2:log(e)}});foo()",
+  );
+}
+
+#[test]
+fn test_frequency_names() {
+  // The most used variables get the shortest names.
+  let src = r#"
+    function foo(rare, common) {
+      log(common, common, common);
+      return rare;
+    }
+    foo();
+  "#;
+  check(
+    TopLevelMode::Global,
+    src,
+    "var foo=((b,a)=>{log(a,a,a);return b});foo()",
+  );
+  // Names are built from the characters that are most common in the rest of the code.
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      frequency_alphabet: true,
+      ..MinifyOptions::default()
+    },
+    src,
+    "var foo=((f,o)=>{log(o,o,o);return f});foo()",
+  );
+}
+
+#[test]
+fn test_beautify() {
+  check_with_options(
+    TopLevelMode::Module,
+    &MinifyOptions {
+      beautify: true,
+      ..MinifyOptions::default()
+    },
+    r#"
+      import def, { a as b } from "mod";
+      export class Animal extends Base {
+        legs = 4;
+        static create(name, { age = 1, ...rest }) {
+          return new Animal(name, age, rest);
+        }
+      }
+      export function run(items) {
+        let total = 0;
+        for (let i = 0; i < items.length; i++) {
+          if (items[i] > 10) continue;
+          total += items[i] * 2;
+        }
+        outer: while (total > 100) {
+          switch (total % 3) {
+            case 0:
+              total -= 7;
+              break outer;
+            default:
+          }
+          total = total / 2;
+        }
+        try {
+          def([1, 2], { x: total, y: b });
+        } catch (e) {
+          do total--; while (total > 0 && e)
+        } finally {
+          b.done = total ? "yes" : "no";
+        }
+        return (x) => x + total;
+      }
+    "#,
+    r#"import b, { a as a } from "mod";
+class c extends Base {
+  legs = 4;
+  static create(a, { age: b = 1, ...d }) {
+    return new c(a, b, d);
+  }
+}
+function d(d) {
+  let c = 0;
+  for (let a = 0; a < d.length; a++) {
+    if (d[a] > 10) {
+      continue;
+    }
+    c += d[a] * 2;
+  }
+  outer: while (c > 100) {
+    switch (c % 3) {
+      case 0:
+        c -= 7;
+        break outer;
+      default:
+    }
+    c = c / 2;
+  }
+  try {
+    b([1, 2], { x: c, y: a });
+  } catch (a) {
+    do {
+      c--;
+    } while (c > 0 && a);
+  } finally {
+    a.done = c ? "yes" : "no";
+  }
+  return a => a + c;
+}
+export { c as Animal, d as run };"#,
+  );
+  check_with_options(
+    TopLevelMode::Global,
+    &MinifyOptions {
+      beautify: true,
+      if_to_expression: false,
+      ..MinifyOptions::default()
+    },
+    "if (a) { b() } else if (c) d(); else {} label:;",
+    "if (a) {\n  b();\n} else if (c) {\n  d();\n} else {}\nlabel: ;",
+  );
+}
+
+#[test]
+fn test_emit_numbers() {
+  check(
+    TopLevelMode::Global,
+    "x(0, 1000, 1000000, 1230000, 0.5, 0.001, 0.0001, 1.5e-7, 12.5, 1099511627775, 1e21, 1.7976931348623157e308);",
+    "x(0,1e3,1e6,123e4,.5,.001,1e-4,15e-8,12.5,0xffffffffff,1e21,17976931348623157e292)",
+  );
+  check(
+    TopLevelMode::Global,
+    "x(1..toString(), 1.5.toFixed(), 255 .toString(16), 1e6.toString(), 0x10000000000.toString(), 1?.toString());",
+    "x(1..toString(),1.5.toFixed(),255..toString(16),1e6.toString(),1099511627776..toString(),1?.toString())",
+  );
+}
+
+#[test]
+fn test_emit_bigints() {
+  check(
+    TopLevelMode::Global,
+    "x(0n, 0x10n, 0o777n, 0b1010n, 1000000n, 281474976710655n, 0xFFn.toString(), -5n);",
+    "x(0n,16n,511n,10n,1000000n,0xffffffffffffn,255n.toString(),-5n)",
+  );
+}
+
+#[test]
+fn test_quote_style() {
+  // The second to last string ends with a raw line separator (U+2028).
+  let src = concat!(
+    r#"import a from 'mod'; a("plain", "say \"hi\"", 'it\'s "so"', "it's \"so\" `${x}`", "line\nbreak", "\\ \r "#,
+    "\u{2028}",
+    r#"", 'back`tick');"#
+  );
+  check(
+    TopLevelMode::Module,
+    src,
+    r#"import a from"mod";a("plain",'say "hi"',`it's "so"`,'it\'s "so" `${x}`',`line
+break`,"\\ \r \u2028","back`tick")"#,
+  );
+  check_with_options(
+    TopLevelMode::Module,
+    &MinifyOptions {
+      quote_style: QuoteStyle::Single,
+      ..MinifyOptions::default()
+    },
+    src,
+    r#"import a from'mod';a('plain','say "hi"','it\'s "so"','it\'s "so" `${x}`','line\nbreak','\\ \r \u2028','back`tick')"#,
+  );
+  check_with_options(
+    TopLevelMode::Module,
+    &MinifyOptions {
+      quote_style: QuoteStyle::Backtick,
+      ..MinifyOptions::default()
+    },
+    src,
+    r#"import a from"mod";a(`plain`,`say "hi"`,`it's "so"`,`it's "so" \`\${x}\``,`line
+break`,`\\ \r \u2028`,`back\`tick`)"#,
+  );
+}
+
+#[test]
+fn test_emit_template_literals() {
+  // Template strings are written from their cooked value, so escapes are normalised but must still produce the same string.
+  check(
+    TopLevelMode::Global,
+    r#"x(`a\`b ${c} \${d} $ {e} $${f} \\ \r\n \t
+g`);"#,
+    "x(`a\\`b ${c} \\${d} $ {e} $${f} \\\\ \\r\n \t\ng`)",
+  );
+  // Tagged templates aren't supported yet, so they must fail rather than be written from the cooked value.
+  let session = Session::new();
+  let mut out = Vec::new();
+  assert!(crate::minify(
+    &session,
+    TopLevelMode::Global,
+    &MinifyOptions::default(),
+    b"String.raw`a\\nb`",
+    &mut out,
+  )
+  .is_err());
+}
+
+#[test]
+fn test_max_nesting_depth() {
+  let minify = |max_nesting_depth: usize, src: &str| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      TopLevelMode::Global,
+      &MinifyOptions {
+        max_nesting_depth,
+        ..MinifyOptions::default()
+      },
+      src.as_bytes(),
+      &mut out,
+    )
+    .map(|()| String::from_utf8(out).unwrap())
+    .map_err(|err| err.to_string())
+  };
+  // Redundant brackets and prefix operators are counted even though the parser doesn't create nodes for all of them.
+  assert_eq!(minify(4, "((((a))))"), Ok("a".to_string()));
+  assert_eq!(
+    minify(3, "((((a))))"),
+    Err("code at byte 3 is nested too deeply; raise the maximum nesting depth to minify it".to_string())
+  );
+  assert_eq!(
+    minify(3, "x(/(((/, '((((', `${`${`${`${a}`}`}`}`)"),
+    Err("code at byte 23 is nested too deeply; raise the maximum nesting depth to minify it".to_string())
+  );
+  assert!(minify(3, "!!!!a").is_err());
+  // Operators nest without any brackets, so the tree is checked after parsing too.
+  assert!(minify(4, "a+b+c+d+e").is_err());
+
+  // Code much deeper than the default thread stack allows is fine within the limit.
+  let depth = 5000;
+  let src = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+  assert_eq!(minify(10000, &src), Ok("a".to_string()));
+  let src = format!("x=a{}", "*a".repeat(depth));
+  assert_eq!(minify(10000, &src), Ok(src.clone()));
+  let src = format!("x={}a{}", "[".repeat(depth), "]".repeat(depth));
+  assert_eq!(minify(10000, &src), Ok(src.clone()));
+
+  // Right-associative operators and arrows nest to the right without any brackets, so the parser recurses for each of them before the tree can be checked.
+  let max_nesting_depth = MinifyOptions::default().max_nesting_depth;
+  for (prefix, repeated) in [
+    ("x=", "a="),
+    ("x=", "a+="),
+    ("x=", "a**="),
+    ("x=", "a?b:"),
+    ("x=", "a**"),
+    ("x=", "a=>"),
+  ] {
+    let src = format!("{}{}a", prefix, repeated.repeat(max_nesting_depth + 1));
+    let session = Session::new();
+    let result = crate::minify(
+      &session,
+      TopLevelMode::Global,
+      &MinifyOptions::default(),
+      src.as_bytes(),
+      &mut Vec::new(),
+    );
+    assert!(
+      matches!(result, Err(MinifyError::TooDeeplyNested { .. })),
+      "{}",
+      repeated
+    );
+  }
+  // Comparisons, nullish coalescing and optional chaining are left-associative, so they don't count, and neither do operators after the end of a statement.
+  assert_eq!(
+    find_too_deep_source(b"x=a<=b>=c!=d!==e==f===g??h?.i;x=1\ny=1", 1),
+    None
+  );
+  assert_eq!(find_too_deep_source(b"x=a?.5:b", 1), Some(3));
+  assert_eq!(find_too_deep_source(b"x=\ny=\nz=1", 2), Some(7));
+}
+
+#[test]
+fn test_if_to_expression() {
+  check(
+    TopLevelMode::Global,
+    "if (a) b(); if (a) { var x = 1; b(x) } else if (c) d(); else e()",
+    "var x;a&&b();a?(x=1,b(x)):c?d():e()",
+  );
+  check(
+    TopLevelMode::Module,
+    "export function f() { if (a) b(); else c() }",
+    "function d(){a?b():c()}export{d as f}",
+  );
+}
+
+#[test]
+fn test_errors() {
+  let minify = |top_level_mode: TopLevelMode, src: &str| {
+    let session = Session::new();
+    let mut out = Vec::new();
+    crate::minify(
+      &session,
+      top_level_mode,
+      &MinifyOptions::default(),
+      src.as_bytes(),
+      &mut out,
+    )
+    .map(|()| String::from_utf8(out).unwrap())
+    .map_err(|err| err.to_string())
+  };
+  let check_error = |top_level_mode: TopLevelMode, src: &str, expected: &str| {
+    assert_eq!(minify(top_level_mode, src), Err(expected.to_string()));
+  };
+  check_error(
+    TopLevelMode::Global,
+    "a b",
+    "syntax error at byte 2: ExpectedSyntax(\"expression operator\") [token=Some(Identifier)]",
+  );
+  check_error(
+    TopLevelMode::Global,
+    "let a; let a;",
+    "`a` at byte 11 has already been declared",
+  );
+  check_error(
+    TopLevelMode::Global,
+    "function f(a) { let a; }",
+    "`a` at byte 20 has already been declared",
+  );
+  check_error(
+    TopLevelMode::Global,
+    "let a; { { var a; } }",
+    "`a` at byte 15 has already been declared",
+  );
+  check_error(
+    TopLevelMode::Global,
+    "try {} catch (e) { let e; }",
+    "`e` at byte 23 has already been declared",
+  );
+  check_error(
+    TopLevelMode::Module,
+    "import a from 'a'; class a {}",
+    "`a` at byte 25 has already been declared",
+  );
+  check_error(
+    TopLevelMode::Module,
+    "let a, c; export { a as b, c as b }",
+    "`b` at byte 32 has already been exported",
+  );
+  check_error(
+    TopLevelMode::Module,
+    "export default 1; export default function () {}",
+    "`default` at byte 33 has already been exported",
+  );
+  check_error(
+    TopLevelMode::Module,
+    "export { a }",
+    "`a` at byte 9 is exported but isn't declared at the top level",
+  );
+  check_error(
+    TopLevelMode::Global,
+    "let a; export { a }",
+    "export at byte 7 is only allowed in a module; minify it in module mode",
+  );
+  // These redeclarations are allowed.
+  assert!(minify(
+    TopLevelMode::Global,
+    "var a; var a; function a() {} function f(b) { var b; function b() {} } for (let i;;) { let i; }",
+  )
+  .is_ok());
+  assert!(minify(TopLevelMode::Global, "try {} catch (e) { var e; }").is_ok());
+  assert!(minify(TopLevelMode::Global, "let x; ({ get y() { var x; } })").is_ok());
+  assert!(minify(
+    TopLevelMode::Module,
+    "export * from 'a'; export * as b from 'b'; export { b as c } from 'c'; let b;"
+  )
+  .is_ok());
+}
@@ -0,0 +1,78 @@
+use parse_js::error::SyntaxError;
+use std::error::Error;
+use std::fmt;
+
+/// Why code couldn't be minified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MinifyError<'a> {
+  /// The code isn't valid JavaScript.
+  Syntax(SyntaxError<'a>),
+  /// The code is nested more than `MinifyOptions::max_nesting_depth` levels deep at this byte offset in the source.
+  TooDeeplyNested { position: usize },
+  /// A variable is declared more than once where that isn't allowed (e.g. `let a; let a` or `let a; { var a }`). `position` is the byte offset of the later declaration.
+  DuplicateDeclaration { name: String, position: usize },
+  /// More than one export has the same name (e.g. `export { a as b, c as b }`). `position` is the byte offset of the later export.
+  DuplicateExport { name: String, position: usize },
+  /// A variable is exported (e.g. `export { a }`) but isn't declared at the top level.
+  UndeclaredExport { name: String, position: usize },
+  /// Code minified with `TopLevelMode::Global` has an `export`, which is only allowed in modules.
+  ExportOutsideModule { position: usize },
+  /// `MinifyOptions::mangle_properties` was given a pattern that isn't a valid regular expression.
+  InvalidPropertyPattern { message: String },
+  /// The minifier reached a state it assumes isn't possible. This is a bug in the minifier rather than the code; please report it with the code that caused it.
+  Internal(&'static str),
+}
+
+impl<'a> From<SyntaxError<'a>> for MinifyError<'a> {
+  fn from(err: SyntaxError<'a>) -> Self {
+    MinifyError::Syntax(err)
+  }
+}
+
+impl<'a> fmt::Display for MinifyError<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MinifyError::Syntax(err) => write!(
+        f,
+        "syntax error at byte {}: {}",
+        err.source.start(),
+        err
+      ),
+      MinifyError::TooDeeplyNested { position } => write!(
+        f,
+        "code at byte {} is nested too deeply; raise the maximum nesting depth to minify it",
+        position
+      ),
+      MinifyError::DuplicateDeclaration { name, position } => write!(
+        f,
+        "`{}` at byte {} has already been declared",
+        name, position
+      ),
+      MinifyError::DuplicateExport { name, position } => write!(
+        f,
+        "`{}` at byte {} has already been exported",
+        name, position
+      ),
+      MinifyError::UndeclaredExport { name, position } => write!(
+        f,
+        "`{}` at byte {} is exported but isn't declared at the top level",
+        name, position
+      ),
+      MinifyError::ExportOutsideModule { position } => write!(
+        f,
+        "export at byte {} is only allowed in a module; minify it in module mode",
+        position
+      ),
+      MinifyError::InvalidPropertyPattern { message } => {
+        write!(f, "invalid property pattern: {}", message)
+      }
+      MinifyError::Internal(message) => write!(
+        f,
+        "internal error: {}; please report this as a bug with the code that caused it",
+        message
+      ),
+    }
+  }
+}
+
+impl<'a> Error for MinifyError<'a> {}
@@ -0,0 +1,154 @@
+use parse_js::ast::Node;
+use parse_js::ast::NodeData;
+use parse_js::ast::Syntax;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+use std::collections::BTreeMap;
+
+// Follows the conventions of other minifiers: `/*!` and `//!` comments, and comments containing `@license` or `@preserve`, must be kept.
+fn is_legal_comment(comment: &[u8]) -> bool {
+  comment.starts_with(b"/*!")
+    || comment.starts_with(b"//!")
+    || comment.windows(8).any(|w| w == b"@license")
+    || comment.windows(9).any(|w| w == b"@preserve")
+}
+
+// Finds the legal comments in `gap`, which should be the source between two statements and so only contain whitespace, semicolons, and comments. We stop at anything else, so an incorrect gap can't cause part of a string or regex to be mistaken for a comment.
+fn scan_gap<'a>(gap: &'a [u8], out: &mut Vec<&'a [u8]>) -> () {
+  let mut i = 0;
+  while i < gap.len() {
+    let rest = &gap[i..];
+    let len = if rest.starts_with(b"/*") {
+      match rest[2..].windows(2).position(|w| w == b"*/") {
+        Some(end) => end + 4,
+        None => return,
+      }
+    } else if rest.starts_with(b"//") {
+      rest
+        .iter()
+        .position(|&c| c == b'\n' || c == b'\r')
+        .unwrap_or(rest.len())
+    } else if rest[0].is_ascii_whitespace() || rest[0] == b';' {
+      1
+    } else {
+      return;
+    };
+    if len > 1 && is_legal_comment(&rest[..len]) {
+      out.push(&rest[..len]);
+    };
+    i += len;
+  }
+}
+
+/// Legal comments (e.g. license banners) found in the source, which would otherwise be dropped by the parser.
+pub struct LegalComments<'a> {
+  // Keyed by the start offset of the statement that directly follows the comments, or for comments after the last statement of a block or the file, the offset of the closing brace or the end of the source. This is ordered so that comments are always written in source order.
+  by_statement: BTreeMap<usize, Vec<&'a [u8]>>,
+}
+
+struct Collector<'a> {
+  source: &'a [u8],
+  by_statement: BTreeMap<usize, Vec<&'a [u8]>>,
+}
+
+impl<'a> Collector<'a> {
+  fn collect_gap(&mut self, gap_start: Option<usize>, gap_end: usize) -> () {
+    if let Some(start) = gap_start.filter(|&s| s <= gap_end) {
+      let mut comments = Vec::new();
+      scan_gap(&self.source[start..gap_end], &mut comments);
+      if !comments.is_empty() {
+        self
+          .by_statement
+          .entry(gap_end)
+          .or_default()
+          .extend(comments);
+      };
+    };
+  }
+
+  // `gap_start` is where the source before the first statement begins, and `body_end` is where the source after the last statement ends, if known.
+  fn collect_statements(
+    &mut self,
+    mut gap_start: Option<usize>,
+    body: &[Node<'a>],
+    body_end: Option<usize>,
+  ) -> () {
+    for stmt in body {
+      self.collect_gap(gap_start, stmt.loc.start());
+      gap_start = Some(stmt.loc.end());
+    }
+    if let Some(end) = body_end {
+      self.collect_gap(gap_start, end);
+    };
+  }
+}
+
+impl<'a> Visitor<'a> for Collector<'a> {
+  fn on_syntax_down(&mut self, node: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    match &node.stx {
+      Syntax::TopLevel { body } => {
+        self.collect_statements(Some(0), body, Some(self.source.len()))
+      }
+      // Skip the opening and closing braces.
+      Syntax::BlockStmt { body } => self.collect_statements(
+        Some(node.loc.start() + 1),
+        body,
+        Some(node.loc.end() - 1),
+      ),
+      // We don't know where the `case` expression ends, so comments before the first statement aren't found, and nor are comments after the last statement, as the branch has no closing brace to scan up to.
+      Syntax::SwitchBranch { body, .. } => self.collect_statements(None, body, None),
+      _ => {}
+    };
+  }
+}
+
+impl<'a> LegalComments<'a> {
+  /// Finds legal comments between the statements of `top_level_node`. This must be called before minification, while the statements are still in their original positions.
+  pub fn collect(source: &'a [u8], top_level_node: &mut NodeData<'a>) -> LegalComments<'a> {
+    let mut collector = Collector {
+      source,
+      by_statement: BTreeMap::new(),
+    };
+    collector.visit(top_level_node);
+    LegalComments {
+      by_statement: collector.by_statement,
+    }
+  }
+
+  fn write_comments(out: &mut Vec<u8>, comments: &[&[u8]]) -> () {
+    for c in comments {
+      out.extend_from_slice(c);
+      // Line comments must be terminated before the next statement.
+      if c.starts_with(b"//") {
+        out.push(b'\n');
+      };
+    }
+  }
+
+  // Writes the comments that preceded the statement starting at `statement_start`, if they haven't already been written.
+  pub fn write_before_statement(&mut self, out: &mut Vec<u8>, statement_start: usize) -> () {
+    if let Some(comments) = self.by_statement.remove(&statement_start) {
+      Self::write_comments(out, &comments);
+    };
+  }
+
+  // Whether there are unwritten comments after the last statement of the block whose closing brace is at `block_end`.
+  pub fn has_before_block_end(&self, block_end: usize) -> bool {
+    self.by_statement.contains_key(&block_end)
+  }
+
+  // Writes the comments whose statements were removed or moved out of a statement list during minification, so they're never lost.
+  pub fn write_remaining(&mut self, out: &mut Vec<u8>) -> () {
+    for comments in std::mem::take(&mut self.by_statement).values() {
+      Self::write_comments(out, comments);
+    }
+  }
+
+  /// Writes every comment on its own line.
+  pub fn write_all(&self, out: &mut Vec<u8>) -> () {
+    for c in self.by_statement.values().flatten() {
+      out.extend_from_slice(c);
+      out.push(b'\n');
+    }
+  }
+}
@@ -1,14 +1,42 @@
+use directive::ParenthesisedStrings;
 use emit::emit_js;
+use emit::Emitter;
+use legal_comments::LegalComments;
 use minify::minify_js;
+use nesting::find_too_deep_source;
+use nesting::find_too_deep_node;
+use nesting::with_stack_for_depth;
 use parse_js::ast::Node;
 use parse_js::parse;
+use parse_js::visit::Visitor;
 
+mod directive;
 mod emit;
+mod error;
+mod legal_comments;
 mod minify;
+mod name_cache;
+mod nesting;
+mod number;
+mod options;
+mod outputs;
+mod source_map;
+mod warning;
 
+pub use error::MinifyError;
+pub use name_cache::NameCache;
+pub use name_cache::NameCacheError;
+pub use options::MinifyOptions;
+pub use options::PropertyPattern;
+pub use options::QuoteStyle;
+pub use outputs::MinifyOutputs;
 pub use parse_js::error::SyntaxError;
 pub use parse_js::parse::toplevel::TopLevelMode;
 pub use parse_js::session::Session;
+pub use source_map::Mapping;
+pub use source_map::SourceMap;
+pub use source_map::SourceMapError;
+pub use warning::Warning;
 
 /// Emits UTF-8 JavaScript code from a parsed AST in a minified way. This allows custom introspections and transforms on the tree before emitting it to code.
 ///
@@ -17,7 +45,68 @@ pub use parse_js::session::Session;
 /// * `node` - The root node from the parsed AST.
 /// * `output` - Destination to write output JavaScript code.
 pub fn emit<'a>(node: Node<'a>, output: &mut Vec<u8>) -> () {
-  emit_js(output, node);
+  emit_js(&mut Emitter::new(output), node);
+}
+
+/// Emits UTF-8 JavaScript code from a parsed AST in a readable way, with one statement per line, indentation, and spaces around operators. This can be used to format code without minifying it.
+///
+/// # Arguments
+///
+/// * `node` - The root node from the parsed AST.
+/// * `output` - Destination to write output JavaScript code.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::{Session, TopLevelMode, emit_beautified};
+/// use parse_js::parse;
+///
+/// let session = Session::new();
+/// let parsed = parse(&session, b"if(a)b(1,2);else{let c=a?1:2}", TopLevelMode::Global).unwrap();
+/// let mut out = Vec::new();
+/// emit_beautified(parsed, &mut out);
+/// assert_eq!(out.as_slice(), b"if (a) {\n  b(1, 2);\n} else {\n  let c = a ? 1 : 2;\n}");
+/// ```
+pub fn emit_beautified<'a>(node: Node<'a>, output: &mut Vec<u8>) -> () {
+  emit_js(&mut Emitter::new(output).beautified(), node);
+}
+
+/// Emits UTF-8 JavaScript code like `emit`, and records mappings from the output back to the original source into a source map.
+///
+/// # Arguments
+///
+/// * `node` - The root node from the parsed AST.
+/// * `source` - The source code that `node` was parsed from. Only nodes located within it are mapped.
+/// * `output` - Destination to write output JavaScript code.
+/// * `source_map` - Destination to add mappings and names to.
+pub fn emit_with_source_map<'a>(
+  node: Node<'a>,
+  source: &'a [u8],
+  output: &mut Vec<u8>,
+  source_map: &mut SourceMap,
+) -> () {
+  emit_js(
+    &mut Emitter::with_source_map(output, source, source_map),
+    node,
+  );
+}
+
+// Parses `source`, rejecting code nested deeper than `options.max_nesting_depth`, and marks parenthesised strings so they aren't mistaken for directives. This must be called within `with_stack_for_depth`, as parsing recurses for each level.
+fn parse_for_minify<'a>(
+  session: &'a Session,
+  top_level_mode: TopLevelMode,
+  options: &MinifyOptions,
+  source: &'a [u8],
+) -> Result<Node<'a>, MinifyError<'a>> {
+  if let Some(position) = find_too_deep_source(source, options.max_nesting_depth) {
+    return Err(MinifyError::TooDeeplyNested { position });
+  };
+  let parsed = parse(session, source, top_level_mode)?;
+  if let Some(position) = find_too_deep_node(parsed, options.max_nesting_depth) {
+    return Err(MinifyError::TooDeeplyNested { position });
+  };
+  ParenthesisedStrings { source }.visit(parsed);
+  Ok(parsed)
 }
 
 /// Minifies UTF-8 JavaScript code, represented as an array of bytes.
@@ -26,28 +115,114 @@ pub fn emit<'a>(node: Node<'a>, output: &mut Vec<u8>) -> () {
 ///
 /// * `session` - Session to use as backing arena memory. Can be reused across calls and cleared at any time allowed by the Rust lifetime checker.
 /// * `top_level_mode` - How to parse the provided code.
+/// * `options` - Which transforms to apply; see `MinifyOptions`.
 /// * `source` - A vector of bytes representing the source code to minify.
 /// * `output` - Destination to write minified output JavaScript code.
 ///
+/// # Errors
+///
+/// Returns a `MinifyError` if the code isn't valid JavaScript (including declaring or exporting a name twice), or is nested more deeply than `MinifyOptions::max_nesting_depth`.
+///
 /// # Examples
 ///
 /// ```
-/// use better_minify_js::{Session, TopLevelMode, minify};
+/// use better_minify_js::{MinifyOptions, Session, TopLevelMode, minify};
 ///
-/// let mut code: &[u8] = b"const main = () => { let my_first_variable = 1; };";
+/// let mut code: &[u8] = b"const main = () => { let my_first_variable = 1; return my_first_variable; };";
 /// let session = Session::new();
 /// let mut out = Vec::new();
-/// minify(&session, TopLevelMode::Global, code, &mut out).unwrap();
-/// assert_eq!(out.as_slice(), b"const main=()=>{let a=1}");
+/// minify(&session, TopLevelMode::Global, &MinifyOptions::default(), code, &mut out).unwrap();
+/// assert_eq!(out.as_slice(), b"const main=()=>{let a=1;return a}");
 /// ```
 pub fn minify<'a>(
   session: &'a Session,
   top_level_mode: TopLevelMode,
+  options: &MinifyOptions,
   source: &'a [u8],
   output: &mut Vec<u8>,
-) -> Result<(), SyntaxError<'a>> {
-  let parsed = parse(session, source, top_level_mode)?;
-  minify_js(session, parsed);
-  emit(parsed, output);
-  Ok(())
+) -> Result<(), MinifyError<'a>> {
+  minify_with(
+    session,
+    top_level_mode,
+    options,
+    source,
+    output,
+    &mut MinifyOutputs::default(),
+  )
+}
+
+/// Minifies UTF-8 JavaScript code like `minify`, and also produces the outputs requested in `outputs`, such as a source map or the legal comments; see `MinifyOutputs`.
+///
+/// # Arguments
+///
+/// * `session` - Session to use as backing arena memory.
+/// * `top_level_mode` - How to parse the provided code.
+/// * `options` - Which transforms to apply; see `MinifyOptions`.
+/// * `source` - A vector of bytes representing the source code to minify.
+/// * `output` - Destination to write minified output JavaScript code.
+/// * `outputs` - Which other outputs to produce, and where they're written to.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::{MinifyOptions, MinifyOutputs, NameCache, Session, SourceMap, TopLevelMode, Warning, minify_with};
+///
+/// let code: &[u8] = b"/*! (c) Me */\nexport const helper = 1;\neval(\"helper\");";
+/// let session = Session::new();
+/// let mut out = Vec::new();
+/// let mut outputs = MinifyOutputs {
+///   source_map: Some(SourceMap::new("input.js")),
+///   legal_comments: Some(Vec::new()),
+///   name_cache: Some(NameCache::new()),
+///   ..MinifyOutputs::default()
+/// };
+/// minify_with(&session, TopLevelMode::Module, &MinifyOptions::default(), code, &mut out, &mut outputs).unwrap();
+/// assert_eq!(out.as_slice(), b"const helper=1;eval(\"helper\");export{helper as helper}");
+/// assert_eq!(outputs.legal_comments.unwrap().as_slice(), b"/*! (c) Me */\n");
+/// assert!(outputs.source_map.unwrap().to_json().starts_with(r#"{"version":3,"sources":["input.js"]"#));
+/// // `helper` is visible to `eval`, so it keeps its name.
+/// assert_eq!(outputs.name_cache.unwrap().to_json(), r#"{"properties":{},"topLevel":{"helper":"helper"}}"#);
+/// assert_eq!(outputs.warnings, vec![Warning::DirectEval { position: 39 }]);
+/// ```
+pub fn minify_with<'a>(
+  session: &'a Session,
+  top_level_mode: TopLevelMode,
+  options: &MinifyOptions,
+  source: &'a [u8],
+  output: &mut Vec<u8>,
+  outputs: &mut MinifyOutputs,
+) -> Result<(), MinifyError<'a>> {
+  with_stack_for_depth(options.max_nesting_depth, || {
+    let parsed = parse_for_minify(session, top_level_mode, options, source)?;
+    let mut legal_comments = match &mut outputs.legal_comments {
+      // Legal comments written separately aren't also written to the output.
+      Some(dest) => {
+        LegalComments::collect(source, parsed).write_all(dest);
+        None
+      }
+      None => options
+        .legal_comments
+        .then(|| LegalComments::collect(source, parsed)),
+    };
+    outputs.warnings.extend(minify_js(
+      session,
+      options,
+      parsed,
+      outputs.name_cache.as_mut(),
+    )?);
+    let mut emitter = match &mut outputs.source_map {
+      Some(source_map) => Emitter::with_source_map(output, source, source_map),
+      None => Emitter::new(output),
+    }
+    .with_options(options);
+    if let Some(legal_comments) = &mut legal_comments {
+      emitter = emitter.with_legal_comments(legal_comments);
+    };
+    emit_js(&mut emitter, parsed);
+    emitter.finish();
+    if let (Some(source_map), Some(input)) = (&mut outputs.source_map, &outputs.input_source_map) {
+      *source_map = source_map.remap(input);
+    };
+    Ok(())
+  })
 }
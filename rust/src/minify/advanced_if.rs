@@ -1,3 +1,4 @@
+use crate::MinifyError;
 use parse_js::ast::new_node;
 use parse_js::ast::Node;
 use parse_js::ast::NodeData;
@@ -29,13 +30,19 @@ use parse_js::symbol::Scope;
 // We only perform advanced statement analysis and transformation to expression in `if` and `else` blocks as that will allow opportunities to transform `if` into logical expressions. This isn't useful elsewhere, as a sequence of expression statements is the same size as a sequence of expressions separated by commas, so the fact it's an expression is not being leveraged.
 
 // We first perform some analysis to see if it's even worthwhile to perform this optimisation.
-pub fn analyse_if_branch<'a>(stx: &Syntax<'a>) -> bool {
+pub fn analyse_if_branch<'a>(stx: &Syntax<'a>, max_nesting_depth: usize) -> bool {
   let Syntax::BlockStmt { body } = stx else {
-    // We should have already normalised all `if` branches into a block if they were single statements, so this should not be possible.
-    unreachable!();
+    // We should have already normalised all `if` branches into a block if they were single statements, so this should not be possible, but there's nothing to optimise if it happens.
+    return false;
+  };
+  // Each statement becomes an operand of a comma expression nested in the next one, so a long branch would become much deeper than the source was checked to be; the later passes and the emitter would then need more stack than was reserved for `MinifyOptions::max_nesting_depth`.
+  if body.len() > max_nesting_depth / 2 {
+    return false;
   };
   let mut block_returned = false;
   let mut if_returned = false;
+  // The branch must have something to become an expression (e.g. not `{}` or `{ var a }`).
+  let mut has_expression = false;
   for stmt in body.iter() {
     match &stmt.stx {
       Syntax::VarDecl {
@@ -45,19 +52,23 @@ pub fn analyse_if_branch<'a>(stx: &Syntax<'a>) -> bool {
           // We can make `var` declarations into expressions by hoisting the declaration part and leaving behind an assignment expression (if an initialiser exists).
           // TODO Support non-identifier patterns, although they may not be worth minifying if we have to hoist and therefore duplicate the variable names.
           VarDeclMode::Var => {
-            if declarators.iter().any(|d| match d.pattern.stx {
-              Syntax::IdentifierPattern { .. } => false,
-              _ => true,
-            }) {
+            if declarators
+              .iter()
+              .any(|d| !matches!(d.pattern.stx, Syntax::IdentifierPattern { .. }))
+            {
               return false;
             }
+            has_expression |= declarators.iter().any(|d| d.initializer.is_some());
           }
           // TODO We currently disallow if `let` or `const`, however there is a complex approach we could consider in the future: they're scoped to the block, so we can either wrap our optimised expression in a block or create a unique variable in the nearest closure and hoist it like `var`. Since the former means we're still left with a (block) statement, we choose the latter, but that means we can't do this if we're in the global scope as we're not allowed to introduce global variables (even if they're very unlikely to collide in reality). We'd have to replace all usages of these variables, however.
           VarDeclMode::Const | VarDeclMode::Let => return false,
         };
       }
-      Syntax::ExpressionStmt { .. } => {}
-      Syntax::ReturnStmt { .. } => block_returned = true,
+      Syntax::ExpressionStmt { .. } => has_expression = true,
+      Syntax::ReturnStmt { .. } => {
+        block_returned = true;
+        has_expression = true;
+      }
       // Since we perform this optimisation bottom-up, any IfStmt should already be optimised, so if they were optimised and still exist as a statement, they should only have exactly one statement of `return` in `if` and `else`.
       Syntax::IfStmt {
         consequent: NodeData {
@@ -66,13 +77,16 @@ pub fn analyse_if_branch<'a>(stx: &Syntax<'a>) -> bool {
         },
         alternate: None,
         ..
-      } => if_returned = true,
+      } => {
+        if_returned = true;
+        has_expression = true;
+      }
       // Debugger and empty statements should already be removed.
       _ => return false,
     };
   }
   // We must only be left with at most one return statement (i.e. unconditional, although value can be conditional). Essentially, this means that if we have an `if (x) return`, we must have a block-level return, as otherwise we cannot represent it as a single `return`.
-  !if_returned || block_returned
+  has_expression && (!if_returned || block_returned)
 }
 
 pub struct ProcessedIfBranch<'a> {
@@ -86,7 +100,7 @@ fn process_if_branch_block<'a, 'b>(
   session: &'a Session,
   scope: Scope<'a>,
   body: &'b mut [Node<'a>],
-) -> ProcessedIfBranch<'a> {
+) -> Result<ProcessedIfBranch<'a>, MinifyError<'a>> {
   let mut returns = false;
   let mut hoisted_vars: SessionVec<'a, SourceRange<'a>> = session.new_vec();
   let mut expressions: SessionVec<'a, Node<'a>> = session.new_vec();
@@ -115,7 +129,9 @@ fn process_if_branch_block<'a, 'b>(
         for decl in declarators.iter_mut() {
           let target = decl.pattern.take(session);
           let Syntax::IdentifierPattern { name } = target.stx else {
-            unreachable!();
+            return Err(MinifyError::Internal(
+              "if branch declares a pattern that isn't an identifier",
+            ));
           };
           hoisted_vars.push(name);
           if let Some(init) = &mut decl.initializer {
@@ -157,8 +173,12 @@ fn process_if_branch_block<'a, 'b>(
           },
         ));
 
-        let mut remaining = process_if_branch_block(session, scope, &mut body[i + 1..]);
-        assert!(remaining.returns);
+        let mut remaining = process_if_branch_block(session, scope, &mut body[i + 1..])?;
+        if !remaining.returns {
+          return Err(MinifyError::Internal(
+            "if branch has a conditional return but no return after it",
+          ));
+        };
         hoisted_vars.append(&mut remaining.hoisted_vars);
         let alternate = remaining.expression;
         expressions.push(new_node(session, scope, loc, Syntax::ConditionalExpr {
@@ -169,12 +189,16 @@ fn process_if_branch_block<'a, 'b>(
         }));
         break;
       }
-      _ => unreachable!(),
+      _ => {
+        return Err(MinifyError::Internal(
+          "if branch has a statement that can't become an expression",
+        ))
+      }
     };
     i += 1;
   }
 
-  ProcessedIfBranch {
+  Ok(ProcessedIfBranch {
     expression: expressions
       .into_iter()
       .reduce(|left, right| {
@@ -185,20 +209,20 @@ fn process_if_branch_block<'a, 'b>(
           right,
         })
       })
-      .unwrap(),
+      .ok_or(MinifyError::Internal("if branch has no expressions"))?,
     hoisted_vars,
     returns,
-  }
+  })
 }
 
-pub fn process_if_branch<'a, 'b>(
+pub fn process_if_branch<'a>(
   session: &'a Session,
   scope: Scope<'a>,
-  branch: &'b mut NodeData<'a>,
-) -> ProcessedIfBranch<'a> {
+  branch: &mut NodeData<'a>,
+) -> Result<ProcessedIfBranch<'a>, MinifyError<'a>> {
   let Syntax::BlockStmt { body } = &mut branch.stx else {
-    // We should have already normalised all `if` branches into a block if they were single statements, so this should not be possible.
-    unreachable!()
+    // `analyse_if_branch` only accepts blocks.
+    return Err(MinifyError::Internal("if branch isn't a block"));
   };
   process_if_branch_block(session, scope, body)
 }
@@ -1,4 +1,5 @@
 use super::lexical_lifetimes::LexicalLifetime;
+use crate::MinifyOptions;
 use parse_js::ast::Node;
 use parse_js::session::Session;
 use parse_js::session::SessionHashMap;
@@ -7,6 +8,8 @@ use parse_js::session::SessionVec;
 use parse_js::source::SourceRange;
 use parse_js::symbol::Identifier;
 use parse_js::symbol::Scope;
+use parse_js::symbol::ScopeFlag;
+use parse_js::symbol::ScopeType;
 use parse_js::symbol::Symbol;
 
 // Our additional state that's associated with each Symbol.
@@ -14,12 +17,17 @@ pub struct MinifySymbol<'a> {
   pub minified_name: Option<SourceRange<'a>>,
   pub is_used_as_jsx_component: bool,
   pub has_usage: bool,
+  // How many times the variable is read or assigned, not counting its declaration.
+  pub usage_count: usize,
   // If this is true, and this symbol is associated with a function, don't transform the function into an arrow function, even if it doesn't use `this`.
   pub is_used_as_constructor: bool,
   // Similar to `is_used_as_constructor`, although a weaker signal, since the presence of `prototype` is highly likely to mean it's a constructor function, but not as certain as `new`.
   pub has_prototype: bool,
+  // The first and last lexical lifetime values (see [notes/Lexical lifetimes.md]) at which the variable holds a value that may still be used. Variables whose lifetimes don't overlap can share a name. These are only computed for `var` variables local to a function; other symbols' lifetimes overlap everything.
   pub lexical_lifetime_start: LexicalLifetime<'a>,
   pub lexical_lifetime_end: LexicalLifetime<'a>,
+  // Whether this variable shares its minified name with a variable whose lifetime ends before this one's starts, so this variable's own `var` declarations are redundant.
+  pub reuses_var_slot: bool,
 }
 
 impl<'a> MinifySymbol<'a> {
@@ -28,61 +36,123 @@ impl<'a> MinifySymbol<'a> {
       minified_name: None,
       is_used_as_jsx_component: false,
       has_usage: false,
+      usage_count: 0,
       is_used_as_constructor: false,
       has_prototype: false,
-      lexical_lifetime_start: LexicalLifetime::new_infinite(session),
-      lexical_lifetime_end: LexicalLifetime::new_zero(session),
+      lexical_lifetime_start: LexicalLifetime::new_zero(session),
+      lexical_lifetime_end: LexicalLifetime::new_infinite(session),
+      reuses_var_slot: false,
     }
   }
-
-  pub fn update_lifetime(&mut self, lifetime: LexicalLifetime<'a>) {
-    if lifetime < self.lexical_lifetime_start {
-      self.lexical_lifetime_start = lifetime.clone();
-    };
-    if lifetime > self.lexical_lifetime_end {
-      self.lexical_lifetime_end = lifetime.clone();
-    };
-  }
 }
 
 // Our additional state that's associated with each Scope.
 pub struct MinifyScope<'a> {
   // Variables that are declared by an ancestor (not own) scope (or is not declared anywhere and assumed to be global), and used by code in own or any descendant scope.
   pub inherited_vars: SessionHashSet<'a, Identifier<'a>>,
+  // The minified names of `inherited_vars`, keyed by original name. This is set by `minify_names`.
+  pub minified_inherited_vars: SessionHashMap<'a, Identifier<'a>, Identifier<'a>>,
   // Function declarations within this closure-like scope that must be hoisted to declarations at the very beginning of this closure's code (so we can transform them to `var` and still have them work correctly). There may be multiple closures with the same name, nested deep with many blocks and branches, which is why we use a map; the last visited (lexical) declaration wins. Note that this is only populated if this scope is a closure; function declarations don't hoist to blocks.
   // Since they could be deep and anywhere, we must take them and move them into this map; we can't just look at a BlockStmt's children as they may not always be there.
   pub hoisted_functions: SessionHashMap<'a, Identifier<'a>, Node<'a>>,
   // `var` declarations in this closure that need to be moved to allow for some optimisation.
   pub hoisted_vars: SessionVec<'a, Identifier<'a>>,
+  // Whether `eval` is called directly in this scope or a descendant. The evaluated code can access every variable visible to the call by its original name, so they must all keep their names and declarations.
+  pub has_direct_eval: bool,
+  // Whether this closure's body starts with a `"use strict"` directive.
+  pub has_use_strict: bool,
 }
 
 impl<'a> MinifyScope<'a> {
   pub fn new(session: &'a Session) -> MinifyScope<'a> {
     MinifyScope {
       inherited_vars: session.new_hashset(),
+      minified_inherited_vars: session.new_hashmap(),
       hoisted_functions: session.new_hashmap(),
       hoisted_vars: session.new_vec(),
+      has_direct_eval: false,
+      has_use_strict: false,
+    }
+  }
+}
+
+pub fn has_direct_eval<'a>(
+  scopes: &SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  scope: Scope<'a>,
+) -> bool {
+  scopes.get(&scope).is_some_and(|s| s.has_direct_eval)
+}
+
+// Checks each flag separately, as `ScopeFlag::UsesThis | ScopeFlag::UsesArguments` is always empty: `parse-js` (as of 0.21) builds it with `Flags::from_raw`, which drops its argument.
+pub fn uses_this_or_arguments(scope: Scope) -> bool {
+  let flags = scope.flags();
+  flags.has(ScopeFlag::UsesThis) || flags.has(ScopeFlag::UsesArguments)
+}
+
+// Code is strict in modules, and in functions that have, or are nested in one that has, a `"use strict"` directive. Code in classes is strict too, but the parser doesn't create scopes for classes, so that must be tracked separately.
+pub fn is_strict<'a>(
+  scopes: &SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  scope: Scope<'a>,
+) -> bool {
+  let mut cur = Some(scope);
+  while let Some(scope) = cur {
+    if scope.typ() == ScopeType::Module || scopes.get(&scope).is_some_and(|s| s.has_use_strict) {
+      return true;
+    };
+    cur = scope.parent();
+  }
+  false
+}
+
+// Function declarations are no longer in the tree once Pass2 has moved them into `hoisted_functions`, so passes that run after it must visit them separately. This takes them all out so they can be visited while `scopes` is borrowed; put them back with `restore_hoisted_functions`.
+pub fn take_hoisted_functions<'a>(
+  scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+) -> Vec<(Scope<'a>, Identifier<'a>, Node<'a>)> {
+  let mut hoisted = Vec::new();
+  for (scope, min_scope) in scopes.iter_mut() {
+    for (name, func) in min_scope.hoisted_functions.drain() {
+      hoisted.push((*scope, name, func));
     }
   }
+  // Visit them in source order rather than map order, so passes that depend on the order they're visited in (e.g. lexical lifetimes) give the same result on every run.
+  hoisted.sort_by_key(|(_, _, func)| func.loc.start());
+  hoisted
+}
+
+pub fn restore_hoisted_functions<'a>(
+  scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  hoisted: Vec<(Scope<'a>, Identifier<'a>, Node<'a>)>,
+) -> () {
+  for (scope, name, func) in hoisted {
+    scopes
+      .get_mut(&scope)
+      .unwrap()
+      .hoisted_functions
+      .insert(name, func);
+  }
 }
 
 pub struct Ctx<'a, 'b> {
   pub session: &'a Session,
+  pub options: &'b MinifyOptions,
   pub symbols: &'b mut SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
   pub scopes: &'b mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
 }
 
 impl<'a, 'b> Ctx<'a, 'b> {
   // See [notes/Name minification.md] for the algorithm in more detail.
-  pub fn track_variable_usage(&mut self, scope: Scope<'a>, name: Identifier<'a>) {
+  fn track_variable(&mut self, scope: Scope<'a>, name: Identifier<'a>, is_usage: bool) {
     let mut cur = Some(scope);
     while let Some(scope) = cur {
       if let Some(sym) = scope.get_symbol(name) {
-        self
+        let min_sym = self
           .symbols
           .entry(sym)
-          .or_insert_with(|| MinifySymbol::new(self.session))
-          .has_usage = true;
+          .or_insert_with(|| MinifySymbol::new(self.session));
+        if is_usage {
+          min_sym.has_usage = true;
+          min_sym.usage_count += 1;
+        };
         break;
       };
       self
@@ -94,4 +164,26 @@ impl<'a, 'b> Ctx<'a, 'b> {
       cur = scope.parent();
     }
   }
+
+  pub fn track_variable_usage(&mut self, scope: Scope<'a>, name: Identifier<'a>) {
+    self.track_variable(scope, name, true);
+  }
+
+  // The pattern that declares a variable isn't a usage of it, but we still track it like one so that inherited variables are correct.
+  pub fn track_variable_declaration(&mut self, scope: Scope<'a>, name: Identifier<'a>) {
+    self.track_variable(scope, name, false);
+  }
+
+  // A direct `eval` can access the variables of every scope it's in, so mark them all.
+  pub fn track_direct_eval(&mut self, scope: Scope<'a>) {
+    let mut cur = Some(scope);
+    while let Some(scope) = cur {
+      self
+        .scopes
+        .entry(scope)
+        .or_insert_with(|| MinifyScope::new(self.session))
+        .has_direct_eval = true;
+      cur = scope.parent();
+    }
+  }
 }
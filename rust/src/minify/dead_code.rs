@@ -0,0 +1,258 @@
+use crate::MinifyError;
+use parse_js::ast::new_node;
+use parse_js::ast::ClassOrObjectMemberValue;
+use parse_js::ast::Node;
+use parse_js::ast::NodeData;
+use parse_js::ast::Syntax;
+use parse_js::ast::VarDeclMode;
+use parse_js::ast::VariableDeclarator;
+use parse_js::operator::OperatorName;
+use parse_js::session::Session;
+use parse_js::session::SessionVec;
+use parse_js::symbol::Scope;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+
+// Returns whether an expression is always truthy or always falsy, if it's a literal (or the negation of one) and so can be determined without side effects.
+pub fn literal_truthiness<'a>(stx: &Syntax<'a>) -> Option<bool> {
+  match stx {
+    Syntax::LiteralBooleanExpr { value } => Some(*value),
+    Syntax::LiteralNull {} => Some(false),
+    Syntax::LiteralNumberExpr { value } => Some(value.0 != 0.0 && !value.0.is_nan()),
+    Syntax::LiteralStringExpr { value } => Some(!value.is_empty()),
+    Syntax::UnaryExpr {
+      operator: OperatorName::LogicalNot,
+      argument,
+      ..
+    } => literal_truthiness(&argument.stx).map(|v| !v),
+    _ => None,
+  }
+}
+
+// Takes the declarations out of unreachable code that are hoisted, and so are still observable from reachable code: function declarations, and the names declared by `var`.
+struct HoistedDecls<'a> {
+  session: &'a Session,
+  // Statements kept as is: function declarations, and the top-level statements kept by `keeps_top_level_statement`.
+  statements: Vec<Node<'a>>,
+  // IdentifierPattern nodes for the declared names, and the scope of the first `var` declaration.
+  vars: Vec<Node<'a>>,
+  var_scope: Option<Scope<'a>>,
+  // The first internal error, which is returned once the statements have been processed.
+  error: Option<MinifyError<'a>>,
+}
+
+impl<'a> HoistedDecls<'a> {
+  fn collect_pattern_names(&mut self, n: &mut NodeData<'a>) -> Result<(), MinifyError<'a>> {
+    match &mut n.stx {
+      Syntax::ArrayPattern { elements, rest } => {
+        for e in elements.iter_mut().flatten() {
+          self.collect_pattern_names(e.target)?;
+        }
+        if let Some(rest) = rest {
+          self.collect_pattern_names(rest)?;
+        }
+      }
+      Syntax::ObjectPattern { properties, rest } => {
+        for p in properties {
+          self.collect_pattern_names(p)?;
+        }
+        if let Some(rest) = rest {
+          self.collect_pattern_names(rest)?;
+        }
+      }
+      Syntax::ObjectPatternProperty { target, .. } => {
+        self.collect_pattern_names(target)?;
+      }
+      Syntax::IdentifierPattern { name } => {
+        self.vars.push(new_node(
+          self.session,
+          n.scope,
+          n.loc,
+          Syntax::IdentifierPattern { name: *name },
+        ));
+      }
+      _ => {
+        return Err(MinifyError::Internal(
+          "variable declaration has an unexpected pattern",
+        ))
+      }
+    };
+    Ok(())
+  }
+
+  // Appends the hoisted declarations found so far as statements, which is safe to do anywhere in the closure as they're hoisted anyway.
+  fn append_to(self, body: &mut SessionVec<'a, Node<'a>>) -> () {
+    body.extend(self.statements);
+    if let Some(scope) = self.var_scope {
+      let loc = self.vars[0].loc;
+      let mut declarators = self.session.new_vec();
+      for pattern in self.vars {
+        declarators.push(VariableDeclarator {
+          pattern,
+          initializer: None,
+        });
+      }
+      body.push(new_node(self.session, scope, loc, Syntax::VarDecl {
+        export: false,
+        mode: VarDeclMode::Var,
+        declarators,
+      }));
+    };
+  }
+}
+
+impl<'a> Visitor<'a> for HoistedDecls<'a> {
+  fn on_syntax_down(&mut self, n: &mut NodeData<'a>, ctl: &mut JourneyControls) -> () {
+    match &mut n.stx {
+      Syntax::FunctionDecl { .. } => {
+        self.statements.push(n.take(self.session));
+        ctl.skip();
+      }
+      Syntax::VarDecl {
+        mode: VarDeclMode::Var,
+        declarators,
+        ..
+      } => {
+        for decl in declarators.iter_mut() {
+          if let Err(err) = self.collect_pattern_names(decl.pattern) {
+            self.error.get_or_insert(err);
+          };
+        }
+        self.var_scope.get_or_insert(n.scope);
+        // Initialisers can't contain declarations outside of nested functions.
+        ctl.skip();
+      }
+      // Declarations within these belong to their own closure.
+      Syntax::ArrowFunctionExpr { .. }
+      | Syntax::ClassDecl { .. }
+      | Syntax::ClassExpr { .. }
+      | Syntax::FunctionExpr { .. } => ctl.skip(),
+      _ => {}
+    };
+  }
+
+  // Methods, getters, and setters are closures too.
+  fn visit_class_or_object_value(&mut self, _value: &mut ClassOrObjectMemberValue<'a>) -> () {}
+}
+
+// Imports and exports make up a module's interface even if they're unreachable, and top-level `let`, `const` and `class` declarations can be exported by an `export {...}` elsewhere, or are visible to other scripts, so these are kept as is when removing unreachable top-level statements.
+fn keeps_top_level_statement<'a>(stx: &Syntax<'a>) -> bool {
+  match stx {
+    Syntax::ImportStmt { .. }
+    | Syntax::ExportDefaultExprStmt { .. }
+    | Syntax::ExportListStmt { .. }
+    | Syntax::ClassDecl { .. } => true,
+    Syntax::FunctionDecl { export, .. } => *export,
+    Syntax::VarDecl { export, mode, .. } => *export || *mode != VarDeclMode::Var,
+    _ => false,
+  }
+}
+
+// - Remove statements after an unconditional `return`, `throw`, `break`, or `continue`.
+// - Replace `if` statements with a constant condition with the branch that's taken.
+// - Remove `while` loops with a constant falsy condition.
+// Function declarations and `var` names in removed code are hoisted and may still be referenced, so they're kept: functions as is, and `var` declarations without their initialisers. At the top level, imports, exports and lexical declarations are kept too; see `keeps_top_level_statement`.
+// This runs before Pass1, so that Pass1 doesn't process or track usages in removed code, and Pass2 can still hoist the kept function declarations.
+pub struct DeadCode<'a> {
+  pub session: &'a Session,
+  // The first internal error, which stops minification once this pass is done, as the tree may have been left half transformed.
+  pub error: Option<MinifyError<'a>>,
+}
+
+impl<'a> DeadCode<'a> {
+  fn remove_dead_statements(
+    &mut self,
+    body: &mut SessionVec<'a, Node<'a>>,
+    is_top_level: bool,
+  ) -> Result<(), MinifyError<'a>> {
+    let mut hoisted = HoistedDecls {
+      session: self.session,
+      statements: Vec::new(),
+      vars: Vec::new(),
+      var_scope: None,
+      error: None,
+    };
+    let mut i = 0;
+    while i < body.len() {
+      let mut terminates = false;
+      // If Some, the statement is replaced with the inner value, or removed if it's None.
+      let replacement = match &mut body[i].stx {
+        Syntax::IfStmt {
+          test,
+          consequent,
+          alternate,
+        } => match literal_truthiness(&test.stx) {
+          Some(true) => {
+            if let Some(alt) = alternate {
+              hoisted.visit(alt);
+            };
+            Some(Some(consequent.take(self.session)))
+          }
+          Some(false) => {
+            hoisted.visit(consequent);
+            Some(alternate.as_mut().map(|alt| alt.take(self.session)))
+          }
+          None => None,
+        },
+        Syntax::WhileStmt {
+          condition,
+          body: loop_body,
+        } if literal_truthiness(&condition.stx) == Some(false) => {
+          hoisted.visit(loop_body);
+          Some(None)
+        }
+        Syntax::BreakStmt { .. }
+        | Syntax::ContinueStmt { .. }
+        | Syntax::ReturnStmt { .. }
+        | Syntax::ThrowStmt { .. } => {
+          terminates = true;
+          None
+        }
+        _ => None,
+      };
+      match replacement {
+        // Don't advance, so that the taken branch is processed too (e.g. `else if`).
+        Some(Some(stmt)) => body[i] = stmt,
+        Some(None) => {
+          body.remove(i);
+        }
+        None if terminates => {
+          for stmt in body[i + 1..].iter_mut() {
+            if is_top_level && keeps_top_level_statement(&stmt.stx) {
+              hoisted.statements.push(stmt.take(self.session));
+            } else {
+              hoisted.visit(stmt);
+            };
+          }
+          body.truncate(i + 1);
+          break;
+        }
+        None => i += 1,
+      };
+    }
+    if let Some(err) = hoisted.error.take() {
+      return Err(err);
+    };
+    hoisted.append_to(body);
+    Ok(())
+  }
+}
+
+impl<'a> Visitor<'a> for DeadCode<'a> {
+  // This is bottom-up so that nested blocks have already been processed when their statements are moved into a parent block.
+  fn on_syntax_up(&mut self, node: &mut NodeData<'a>) -> () {
+    match &mut node.stx {
+      Syntax::TopLevel { body } => {
+        if let Err(err) = self.remove_dead_statements(body, true) {
+          self.error.get_or_insert(err);
+        };
+      }
+      Syntax::BlockStmt { body } | Syntax::SwitchBranch { body, .. } => {
+        if let Err(err) = self.remove_dead_statements(body, false) {
+          self.error.get_or_insert(err);
+        };
+      }
+      _ => {}
+    };
+  }
+}
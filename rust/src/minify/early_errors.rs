@@ -0,0 +1,289 @@
+use crate::MinifyError;
+use parse_js::ast::ClassOrObjectMemberValue;
+use parse_js::ast::ExportNames;
+use parse_js::ast::NodeData;
+use parse_js::ast::ObjectMemberType;
+use parse_js::ast::Syntax;
+use parse_js::ast::VarDeclMode;
+use parse_js::source::SourceRange;
+use parse_js::symbol::Scope;
+use parse_js::symbol::ScopeType;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Declaration {
+  // `var` and parameters. A `var` is declared in every scope up to its closure, as none of them can have a `let` of the same name.
+  Var,
+  // Function declarations can be redeclared by `var` and other functions in a closure, but are like `let` in a block.
+  Function,
+  // The parameter of a `catch` can be redeclared by `var`, but not by `let` in the `catch` body.
+  CatchParameter,
+  // `let`, `const`, `class`, and imports can't be redeclared at all.
+  Lexical,
+}
+
+fn identifier_name(name: SourceRange) -> String {
+  String::from_utf8_lossy(name.as_slice()).into_owned()
+}
+
+fn collect_pattern_names<'a>(
+  pattern: &NodeData<'a>,
+  names: &mut Vec<(Scope<'a>, SourceRange<'a>)>,
+) -> () {
+  match &pattern.stx {
+    Syntax::IdentifierPattern { name } => names.push((pattern.scope, *name)),
+    Syntax::ArrayPattern { elements, rest } => {
+      for e in elements.iter().flatten() {
+        collect_pattern_names(e.target, names);
+      }
+      if let Some(rest) = rest {
+        collect_pattern_names(rest, names);
+      }
+    }
+    Syntax::ObjectPattern { properties, rest } => {
+      for p in properties.iter() {
+        if let Syntax::ObjectPatternProperty { target, .. } = &p.stx {
+          collect_pattern_names(target, names);
+        };
+      }
+      if let Some(rest) = rest {
+        collect_pattern_names(rest, names);
+      }
+    }
+    _ => {}
+  };
+}
+
+// The parser accepts some code that's illegal because of what it declares or exports, such as `let a; let a` or two exports named `b`. The passes assume these don't happen (e.g. Pass3 expects each export to refer to a different top-level declaration), and the output would be just as illegal, so this finds them before anything is changed.
+pub struct EarlyErrors<'a> {
+  declarations: HashMap<(Scope<'a>, SourceRange<'a>), Declaration>,
+  export_names: HashSet<SourceRange<'a>>,
+  // The parser doesn't create a function scope for getters, only a block scope for their body, so these are treated as closures instead.
+  getter_scopes: HashSet<Scope<'a>>,
+  pub error: Option<MinifyError<'a>>,
+}
+
+impl<'a> EarlyErrors<'a> {
+  pub fn new() -> Self {
+    Self {
+      declarations: HashMap::new(),
+      export_names: HashSet::new(),
+      getter_scopes: HashSet::new(),
+      error: None,
+    }
+  }
+
+  fn set_error(&mut self, error: MinifyError<'a>) -> () {
+    self.error.get_or_insert(error);
+  }
+
+  fn is_closure(&self, scope: Scope<'a>) -> bool {
+    scope.typ().is_closure_or_global() || self.getter_scopes.contains(&scope)
+  }
+
+  fn add_getter_scope(&mut self, value: &ClassOrObjectMemberValue<'a>) -> () {
+    if let ClassOrObjectMemberValue::Getter { body } = value {
+      if let Syntax::BlockStmt { body } = &body.stx {
+        if let Some(stmt) = body.first() {
+          self.getter_scopes.insert(stmt.scope);
+        };
+      };
+    };
+  }
+
+  fn existing(&self, scope: Scope<'a>, name: SourceRange<'a>) -> Option<Declaration> {
+    self.declarations.get(&(scope, name)).copied()
+  }
+
+  fn declare(&mut self, scope: Scope<'a>, name: SourceRange<'a>, decl: Declaration) -> () {
+    let is_duplicate = match decl {
+      Declaration::Lexical => {
+        self.existing(scope, name).is_some()
+          || scope
+            .parent()
+            .is_some_and(|p| self.existing(p, name) == Some(Declaration::CatchParameter))
+      }
+      Declaration::Function => match self.existing(scope, name) {
+        Some(Declaration::Lexical | Declaration::CatchParameter) => true,
+        Some(Declaration::Var) => !self.is_closure(scope),
+        _ => false,
+      },
+      Declaration::CatchParameter => self.existing(scope, name).is_some(),
+      Declaration::Var => {
+        let mut is_duplicate = false;
+        let mut cur = Some(scope);
+        while let Some(scope) = cur {
+          is_duplicate |= match self.existing(scope, name) {
+            Some(Declaration::Lexical) => true,
+            Some(Declaration::Function) => !self.is_closure(scope),
+            _ => false,
+          };
+          self.declarations.entry((scope, name)).or_insert(decl);
+          cur = scope.parent().filter(|_| !self.is_closure(scope));
+        }
+        is_duplicate
+      }
+    };
+    if is_duplicate {
+      self.set_error(MinifyError::DuplicateDeclaration {
+        name: identifier_name(name),
+        position: name.start(),
+      });
+    };
+    self.declarations.entry((scope, name)).or_insert(decl);
+  }
+
+  fn declare_pattern(&mut self, pattern: &NodeData<'a>, decl: Declaration) -> () {
+    let mut names = Vec::new();
+    collect_pattern_names(pattern, &mut names);
+    for (scope, name) in names {
+      self.declare(scope, name, decl);
+    }
+  }
+
+  fn export(&mut self, name: SourceRange<'a>, position: usize) -> () {
+    if !self.export_names.insert(name) {
+      self.set_error(MinifyError::DuplicateExport {
+        name: identifier_name(name),
+        position,
+      });
+    };
+  }
+
+  fn export_pattern(&mut self, pattern: &NodeData<'a>) -> () {
+    let mut names = Vec::new();
+    collect_pattern_names(pattern, &mut names);
+    for (_, name) in names {
+      self.export(name, name.start());
+    }
+  }
+}
+
+impl<'a> Visitor<'a> for EarlyErrors<'a> {
+  fn on_syntax_down(&mut self, node: &mut NodeData<'a>, ctl: &mut JourneyControls) -> () {
+    if self.error.is_some() {
+      ctl.skip();
+      return;
+    };
+    let is_export = match &node.stx {
+      Syntax::ClassDecl { export, .. }
+      | Syntax::FunctionDecl { export, .. }
+      | Syntax::VarDecl { export, .. } => *export,
+      Syntax::ExportDefaultExprStmt { .. } | Syntax::ExportListStmt { .. } => true,
+      _ => false,
+    };
+    if is_export && node.scope.typ() != ScopeType::Module {
+      self.set_error(MinifyError::ExportOutsideModule {
+        position: node.loc.start(),
+      });
+      return;
+    };
+    match &node.stx {
+      Syntax::VarDecl {
+        export,
+        mode,
+        declarators,
+      } => {
+        let decl = match mode {
+          VarDeclMode::Var => Declaration::Var,
+          VarDeclMode::Let | VarDeclMode::Const => Declaration::Lexical,
+        };
+        for d in declarators.iter() {
+          self.declare_pattern(d.pattern, decl);
+          if *export {
+            self.export_pattern(d.pattern);
+          };
+        }
+      }
+      Syntax::ClassDecl {
+        export,
+        export_default,
+        name,
+        ..
+      }
+      | Syntax::FunctionDecl {
+        export,
+        export_default,
+        name,
+        ..
+      } => {
+        let decl = match &node.stx {
+          Syntax::ClassDecl { .. } => Declaration::Lexical,
+          _ => Declaration::Function,
+        };
+        if let Some(name) = name {
+          if let Syntax::ClassOrFunctionName { name } = &name.stx {
+            self.declare(node.scope, *name, decl);
+            if *export && !*export_default {
+              self.export(*name, name.start());
+            };
+          };
+        };
+        if *export_default {
+          self.export(SourceRange::from_slice(b"default"), node.loc.start());
+        };
+        if let Syntax::ClassDecl { members, .. } = &node.stx {
+          for m in members.iter() {
+            self.add_getter_scope(&m.value);
+          }
+        };
+      }
+      Syntax::ClassExpr { members, .. } => {
+        for m in members.iter() {
+          self.add_getter_scope(&m.value);
+        }
+      }
+      Syntax::ObjectMember {
+        typ: ObjectMemberType::Valued { value, .. },
+      } => {
+        self.add_getter_scope(value);
+      }
+      Syntax::ParamDecl { pattern, .. } => {
+        self.declare_pattern(pattern, Declaration::Var);
+      }
+      Syntax::CatchBlock {
+        parameter: Some(parameter),
+        ..
+      } => {
+        self.declare_pattern(parameter, Declaration::CatchParameter);
+      }
+      Syntax::ImportStmt { default, names, .. } => {
+        if let Some(default) = default {
+          self.declare_pattern(default, Declaration::Lexical);
+        };
+        match names {
+          Some(ExportNames::All(Some(alias))) => self.declare_pattern(alias, Declaration::Lexical),
+          Some(ExportNames::Specific(names)) => {
+            for n in names.iter() {
+              self.declare_pattern(n.alias, Declaration::Lexical);
+            }
+          }
+          _ => {}
+        };
+      }
+      Syntax::ExportDefaultExprStmt { .. } => {
+        self.export(SourceRange::from_slice(b"default"), node.loc.start());
+      }
+      Syntax::ExportListStmt { names, from } => match names {
+        ExportNames::All(Some(alias)) => self.export_pattern(alias),
+        ExportNames::All(None) => {}
+        ExportNames::Specific(names) => {
+          for n in names.iter() {
+            self.export_pattern(n.alias);
+            // Exports from another module don't refer to this one's declarations.
+            if from.is_none() && node.scope.get_symbol(n.target).is_none() {
+              self.set_error(MinifyError::UndeclaredExport {
+                name: identifier_name(n.target),
+                position: n.target.start(),
+              });
+            };
+          }
+        }
+      },
+      _ => {}
+    };
+  }
+}
@@ -0,0 +1,405 @@
+use crate::number::js_number_to_string;
+use parse_js::ast::new_node;
+use parse_js::ast::NodeData;
+use parse_js::ast::Syntax;
+use parse_js::num::JsNumber;
+use parse_js::operator::OperatorName;
+use parse_js::session::Session;
+use parse_js::symbol::Scope;
+use parse_js::visit::Visitor;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+// A primitive value that's known at compile time.
+#[derive(Clone, Debug)]
+enum Value<'a> {
+  Undefined,
+  Null,
+  Boolean(bool),
+  Number(f64),
+  String(Cow<'a, str>),
+}
+
+// ECMAScript's WhiteSpace and LineTerminator code points, which are Unicode's Space_Separator category plus a few others. Unlike Rust's `char::is_whitespace`, this excludes U+0085 (NEXT LINE).
+fn is_js_whitespace(c: char) -> bool {
+  matches!(
+    c,
+    '\t'
+      | '\n'
+      | '\u{b}'
+      | '\u{c}'
+      | '\r'
+      | ' '
+      | '\u{a0}'
+      | '\u{1680}'
+      | '\u{2000}'..='\u{200a}'
+      | '\u{2028}'
+      | '\u{2029}'
+      | '\u{202f}'
+      | '\u{205f}'
+      | '\u{3000}'
+      | '\u{feff}'
+  )
+}
+
+// Implements the ECMAScript StringToNumber abstract operation for decimal strings. Returns None for anything else (e.g. hex), so we don't fold what we can't evaluate correctly.
+fn string_to_number(s: &str) -> Option<f64> {
+  let s = s.trim_matches(is_js_whitespace);
+  if s.is_empty() {
+    return Some(0.0);
+  };
+  match s {
+    "Infinity" | "+Infinity" => return Some(f64::INFINITY),
+    "-Infinity" => return Some(f64::NEG_INFINITY),
+    _ => {}
+  };
+  // Rust also accepts values like `inf` and `NaN`, which JS doesn't.
+  if !s
+    .bytes()
+    .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+  {
+    return None;
+  };
+  s.parse::<f64>().ok()
+}
+
+fn to_int32(v: f64) -> i32 {
+  if !v.is_finite() {
+    return 0;
+  };
+  (v.trunc() % 4294967296.0) as i64 as u32 as i32
+}
+
+fn to_uint32(v: f64) -> u32 {
+  to_int32(v) as u32
+}
+
+impl<'a> Value<'a> {
+  fn truthy(&self) -> bool {
+    match self {
+      Value::Undefined | Value::Null => false,
+      Value::Boolean(v) => *v,
+      Value::Number(v) => *v != 0.0 && !v.is_nan(),
+      Value::String(v) => !v.is_empty(),
+    }
+  }
+
+  fn to_number(&self) -> Option<f64> {
+    match self {
+      Value::Undefined => Some(f64::NAN),
+      Value::Null => Some(0.0),
+      Value::Boolean(v) => Some(if *v { 1.0 } else { 0.0 }),
+      Value::Number(v) => Some(*v),
+      Value::String(v) => string_to_number(v),
+    }
+  }
+
+  fn to_js_string(&self) -> Cow<'a, str> {
+    match self {
+      Value::Undefined => Cow::Borrowed("undefined"),
+      Value::Null => Cow::Borrowed("null"),
+      Value::Boolean(v) => Cow::Borrowed(if *v { "true" } else { "false" }),
+      Value::Number(v) => Cow::Owned(js_number_to_string(*v)),
+      Value::String(v) => v.clone(),
+    }
+  }
+
+  fn type_of(&self) -> &'static str {
+    match self {
+      Value::Undefined => "undefined",
+      Value::Null => "object",
+      Value::Boolean(_) => "boolean",
+      Value::Number(_) => "number",
+      Value::String(_) => "string",
+    }
+  }
+
+  fn strict_equals(&self, other: &Value<'a>) -> bool {
+    match (self, other) {
+      (Value::Undefined, Value::Undefined) | (Value::Null, Value::Null) => true,
+      (Value::Boolean(a), Value::Boolean(b)) => a == b,
+      (Value::Number(a), Value::Number(b)) => a == b,
+      (Value::String(a), Value::String(b)) => a == b,
+      _ => false,
+    }
+  }
+
+  fn loose_equals(&self, other: &Value<'a>) -> Option<bool> {
+    Some(match (self, other) {
+      (Value::Undefined | Value::Null, Value::Undefined | Value::Null) => true,
+      (Value::Undefined | Value::Null, _) | (_, Value::Undefined | Value::Null) => false,
+      (Value::Boolean(_), _)
+      | (_, Value::Boolean(_))
+      | (Value::Number(_), Value::String(_))
+      | (Value::String(_), Value::Number(_)) => self.to_number()? == other.to_number()?,
+      _ => self.strict_equals(other),
+    })
+  }
+
+  // Returns None if either side is NaN (in which case all comparisons are false) or we can't convert a value.
+  fn compare(&self, other: &Value<'a>) -> Option<Option<Ordering>> {
+    Some(match (self, other) {
+      // Strings are compared by UTF-16 code units, not code points.
+      (Value::String(a), Value::String(b)) => Some(a.encode_utf16().cmp(b.encode_utf16())),
+      _ => self.to_number()?.partial_cmp(&other.to_number()?),
+    })
+  }
+}
+
+// Returns the value of an expression if it's a literal, a negated number literal, `undefined`, or `void` of a literal.
+fn value_of<'a>(scope: Scope<'a>, stx: &Syntax<'a>) -> Option<Value<'a>> {
+  match stx {
+    Syntax::LiteralBooleanExpr { value } => Some(Value::Boolean(*value)),
+    Syntax::LiteralNull {} => Some(Value::Null),
+    Syntax::LiteralNumberExpr { value } => Some(Value::Number(value.0)),
+    Syntax::LiteralStringExpr { value } => Some(Value::String(Cow::Borrowed(*value))),
+    // `undefined` is a variable and could be shadowed.
+    Syntax::IdentifierExpr { name }
+      if name.as_slice() == b"undefined" && scope.find_symbol(*name).is_none() =>
+    {
+      Some(Value::Undefined)
+    }
+    Syntax::UnaryExpr {
+      operator: OperatorName::Void,
+      argument,
+      ..
+    } => value_of(argument.scope, &argument.stx).map(|_| Value::Undefined),
+    // Negative numbers don't have a literal syntax.
+    Syntax::UnaryExpr {
+      operator: OperatorName::UnaryNegation,
+      argument,
+      ..
+    } => match &argument.stx {
+      Syntax::LiteralNumberExpr { value } => Some(Value::Number(-value.0)),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// Creates the syntax for a value, or None if there's no literal for it (e.g. NaN and negative numbers, which would need an operator and so aren't any shorter).
+fn value_to_syntax<'a>(
+  session: &'a Session,
+  scope: Scope<'a>,
+  node: &NodeData<'a>,
+  value: Value<'a>,
+) -> Option<Syntax<'a>> {
+  Some(match value {
+    Value::Undefined => Syntax::UnaryExpr {
+      parenthesised: false,
+      operator: OperatorName::Void,
+      argument: new_node(session, scope, node.loc, Syntax::LiteralNumberExpr {
+        value: JsNumber(0.0),
+      }),
+    },
+    Value::Null => Syntax::LiteralNull {},
+    Value::Boolean(value) => Syntax::LiteralBooleanExpr { value },
+    Value::Number(v) if v.is_finite() && v.is_sign_positive() => {
+      Syntax::LiteralNumberExpr { value: JsNumber(v) }
+    }
+    Value::Number(_) => return None,
+    Value::String(v) => Syntax::LiteralStringExpr {
+      value: match v {
+        Cow::Borrowed(v) => v,
+        Cow::Owned(v) => session.get_allocator().alloc_str(&v),
+      },
+    },
+  })
+}
+
+fn fold_unary<'a>(operator: OperatorName, arg: Value<'a>) -> Option<Value<'a>> {
+  Some(match operator {
+    OperatorName::LogicalNot => Value::Boolean(!arg.truthy()),
+    OperatorName::UnaryNegation => Value::Number(-arg.to_number()?),
+    OperatorName::UnaryPlus => Value::Number(arg.to_number()?),
+    OperatorName::BitwiseNot => Value::Number(!to_int32(arg.to_number()?) as f64),
+    OperatorName::Typeof => Value::String(Cow::Borrowed(arg.type_of())),
+    OperatorName::Void => Value::Undefined,
+    _ => return None,
+  })
+}
+
+fn fold_binary<'a>(operator: OperatorName, l: Value<'a>, r: Value<'a>) -> Option<Value<'a>> {
+  let num = |f: fn(f64, f64) -> f64| Some(Value::Number(f(l.to_number()?, r.to_number()?)));
+  let int = |f: fn(i32, i32) -> i32| {
+    Some(Value::Number(
+      f(to_int32(l.to_number()?), to_int32(r.to_number()?)) as f64,
+    ))
+  };
+  let cmp = |f: fn(Ordering) -> bool| Some(Value::Boolean(l.compare(&r)?.is_some_and(f)));
+  match operator {
+    OperatorName::Addition => match (&l, &r) {
+      (Value::String(_), _) | (_, Value::String(_)) => Some(Value::String(Cow::Owned(format!(
+        "{}{}",
+        l.to_js_string(),
+        r.to_js_string()
+      )))),
+      _ => num(|a, b| a + b),
+    },
+    OperatorName::Subtraction => num(|a, b| a - b),
+    OperatorName::Multiplication => num(|a, b| a * b),
+    OperatorName::Division => num(|a, b| a / b),
+    // Rust's remainder on floats has the same semantics as JS's: truncated, with the sign of the dividend.
+    OperatorName::Remainder => num(|a, b| a % b),
+    OperatorName::Exponentiation => num(|a, b| {
+      // `powf` returns 1 for these, but JS returns NaN.
+      if b.is_nan() || (a.abs() == 1.0 && b.is_infinite()) {
+        f64::NAN
+      } else {
+        a.powf(b)
+      }
+    }),
+    OperatorName::BitwiseAnd => int(|a, b| a & b),
+    OperatorName::BitwiseOr => int(|a, b| a | b),
+    OperatorName::BitwiseXor => int(|a, b| a ^ b),
+    OperatorName::BitwiseLeftShift => int(|a, b| a.wrapping_shl(b as u32 & 31)),
+    OperatorName::BitwiseRightShift => int(|a, b| a.wrapping_shr(b as u32 & 31)),
+    OperatorName::BitwiseUnsignedRightShift => Some(Value::Number(
+      (to_uint32(l.to_number()?) >> (to_uint32(r.to_number()?) & 31)) as f64,
+    )),
+    OperatorName::StrictEquality => Some(Value::Boolean(l.strict_equals(&r))),
+    OperatorName::StrictInequality => Some(Value::Boolean(!l.strict_equals(&r))),
+    OperatorName::Equality => Some(Value::Boolean(l.loose_equals(&r)?)),
+    OperatorName::Inequality => Some(Value::Boolean(!l.loose_equals(&r)?)),
+    OperatorName::LessThan => cmp(|o| o == Ordering::Less),
+    OperatorName::LessThanOrEqual => cmp(|o| o != Ordering::Greater),
+    OperatorName::GreaterThan => cmp(|o| o == Ordering::Greater),
+    OperatorName::GreaterThanOrEqual => cmp(|o| o != Ordering::Less),
+    _ => None,
+  }
+}
+
+// Whether an expression can replace the `&&`, `||`, `??` or `?:` expression it's an operand of without changing behaviour. Member expressions and `eval` can't, as `(0 || a.b)()` doesn't bind `this` and `(0 || eval)()` is an indirect eval. Function, class, and object expressions can't, as they need parentheses at the start of a statement, which we'd lose. Comma expressions can't, as the emitter doesn't parenthesise them where a comma separates list items (e.g. `f((a, b))`).
+// If the parent was parenthesised, it may be somewhere that only parentheses allow its replacement to be (e.g. `new (1 ? f() : g)` or `(1 ? -a : b) ** 2`), so calls and unary expressions can't replace it. Other expressions are parenthesised by precedence, with `parenthesised` copied from the parent for operators of the same precedence; see `replace_parent`.
+fn can_replace_parent<'a>(stx: &Syntax<'a>, parent_parenthesised: bool) -> bool {
+  match stx {
+    Syntax::IdentifierExpr { name } => name.as_slice() != b"eval",
+    Syntax::BinaryExpr { operator, .. } => *operator != OperatorName::Comma,
+    Syntax::CallExpr { .. } | Syntax::UnaryExpr { .. } => !parent_parenthesised,
+    Syntax::ConditionalExpr { .. }
+    | Syntax::LiteralBooleanExpr { .. }
+    | Syntax::LiteralNull {}
+    | Syntax::LiteralNumberExpr { .. }
+    | Syntax::LiteralStringExpr { .. } => true,
+    _ => false,
+  }
+}
+
+// Replaces `node` with `operand`, keeping the parentheses around `node` (e.g. `d - (1 ? a - b : c)` must not become `d-a-b`).
+fn replace_parent<'a>(
+  node: &mut NodeData<'a>,
+  operand: &mut NodeData<'a>,
+  parenthesised: bool,
+) -> () {
+  if parenthesised {
+    if let Syntax::BinaryExpr { parenthesised, .. }
+    | Syntax::ConditionalExpr { parenthesised, .. } = &mut operand.stx
+    {
+      *parenthesised = true;
+    };
+  };
+  core::mem::swap(node, operand);
+}
+
+fn is_parenthesised<'a>(stx: &Syntax<'a>) -> bool {
+  match stx {
+    Syntax::BinaryExpr { parenthesised, .. } | Syntax::ConditionalExpr { parenthesised, .. } => {
+      *parenthesised
+    }
+    _ => false,
+  }
+}
+
+// Evaluates expressions of literals at compile time, following ECMAScript semantics:
+// - Unary `!`, `-`, `+`, `~`, `typeof` and `void` of a literal.
+// - Arithmetic, bitwise, string concatenation, equality and relational operators where both operands are literals.
+// - `&&`, `||`, `??` and `?:` where the left operand or test is a literal, which selects the operand that's evaluated.
+// This runs before DeadCode, so that `if` statements and loops whose condition folds to a constant can be removed.
+pub struct ConstantFold<'a> {
+  pub session: &'a Session,
+}
+
+impl<'a> Visitor<'a> for ConstantFold<'a> {
+  // This is bottom-up so that operands have already been folded.
+  fn on_syntax_up(&mut self, node: &mut NodeData<'a>) -> () {
+    let scope = node.scope;
+    let parenthesised = is_parenthesised(&node.stx);
+    let folded = match &mut node.stx {
+      Syntax::UnaryExpr {
+        operator, argument, ..
+      } => {
+        match value_of(argument.scope, &argument.stx) {
+          // Don't replace `void 0` with itself, or negated number literals with themselves.
+          Some(_) if *operator == OperatorName::Void => None,
+          Some(_)
+            if *operator == OperatorName::UnaryNegation
+              && matches!(argument.stx, Syntax::LiteralNumberExpr { .. }) =>
+          {
+            None
+          }
+          Some(arg) => fold_unary(*operator, arg),
+          None => None,
+        }
+      }
+      Syntax::BinaryExpr {
+        operator:
+          operator @ (OperatorName::LogicalAnd
+          | OperatorName::LogicalOr
+          | OperatorName::NullishCoalescing),
+        left,
+        right,
+        ..
+      } => {
+        let Some(l) = value_of(left.scope, &left.stx) else {
+          return;
+        };
+        let use_left = match operator {
+          OperatorName::LogicalAnd => !l.truthy(),
+          OperatorName::LogicalOr => l.truthy(),
+          _ => !matches!(l, Value::Undefined | Value::Null),
+        };
+        if use_left {
+          Some(l)
+        } else if can_replace_parent(&right.stx, parenthesised) {
+          let right = right.take(self.session);
+          replace_parent(node, right, parenthesised);
+          return;
+        } else {
+          None
+        }
+      }
+      Syntax::BinaryExpr {
+        operator,
+        left,
+        right,
+        ..
+      } => match (
+        value_of(left.scope, &left.stx),
+        value_of(right.scope, &right.stx),
+      ) {
+        (Some(l), Some(r)) => fold_binary(*operator, l, r),
+        _ => None,
+      },
+      Syntax::ConditionalExpr {
+        test,
+        consequent,
+        alternate,
+        ..
+      } => {
+        let Some(test) = value_of(test.scope, &test.stx) else {
+          return;
+        };
+        let branch = if test.truthy() { consequent } else { alternate };
+        if can_replace_parent(&branch.stx, parenthesised) {
+          let branch = branch.take(self.session);
+          replace_parent(node, branch, parenthesised);
+        };
+        return;
+      }
+      _ => None,
+    };
+    if let Some(stx) = folded.and_then(|v| value_to_syntax(self.session, scope, node, v)) {
+      node.stx = stx;
+    };
+  }
+}
@@ -0,0 +1,310 @@
+use super::ctx::restore_hoisted_functions;
+use super::ctx::take_hoisted_functions;
+use super::ctx::MinifyScope;
+use super::ctx::MinifySymbol;
+use super::unused::is_local_to_function;
+use super::unused::is_pure_initializer;
+use parse_js::ast::Node;
+use parse_js::ast::NodeData;
+use parse_js::ast::ObjectMemberType;
+use parse_js::ast::Syntax;
+use parse_js::ast::VarDeclMode;
+use parse_js::operator::OperatorName;
+use parse_js::session::SessionHashMap;
+use parse_js::session::SessionVec;
+use parse_js::symbol::Scope;
+use parse_js::symbol::ScopeType;
+use parse_js::symbol::Symbol;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Returns whether every variable visible from `scope` refers to the same declaration as it does from `ancestor`, which is the case if they're only separated by blocks that don't declare anything.
+fn is_same_bindings<'a>(scope: Scope<'a>, ancestor: Scope<'a>) -> bool {
+  let mut cur = scope;
+  while cur != ancestor {
+    if cur.typ() != ScopeType::Block || cur.symbol_count() > 0 {
+      return false;
+    };
+    let Some(parent) = cur.parent() else {
+      return false;
+    };
+    cur = parent;
+  }
+  true
+}
+
+struct Declaration<'a> {
+  scope: Scope<'a>,
+  end: usize,
+  is_function: bool,
+  loop_depth: usize,
+}
+
+struct Usage<'a> {
+  scope: Scope<'a>,
+  start: usize,
+  loop_depth: usize,
+  is_call: bool,
+  // Whether the IdentifierExpr can be replaced with an arbitrary expression, which isn't the case for e.g. `x++` or `{x}`.
+  is_replaceable: bool,
+}
+
+// Finds `const` declarations and the single usage of each of their variables.
+struct Collect<'a, 'b> {
+  symbols: &'b SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  scopes: &'b SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  declarations: HashMap<Symbol, Declaration<'a>>,
+  usages: HashMap<Symbol, Usage<'a>>,
+  // Start offsets of IdentifierExpr nodes that are called, and that must remain identifiers. We reach their parents first.
+  callees: HashSet<usize>,
+  unreplaceable: HashSet<usize>,
+  // How many loops we're in.
+  loop_depth: usize,
+}
+
+fn is_loop<'a>(stx: &Syntax<'a>) -> bool {
+  matches!(
+    stx,
+    Syntax::DoWhileStmt { .. } | Syntax::ForStmt { .. } | Syntax::WhileStmt { .. }
+  )
+}
+
+impl<'a, 'b> Collect<'a, 'b> {
+  fn collect_declarations(&mut self, body: &SessionVec<'a, Node<'a>>) -> () {
+    for stmt in body.iter() {
+      let Syntax::VarDecl {
+        export: false,
+        mode: VarDeclMode::Const,
+        declarators,
+      } = &stmt.stx
+      else {
+        continue;
+      };
+      for decl in declarators.iter() {
+        let (Syntax::IdentifierPattern { name }, Some(init)) =
+          (&decl.pattern.stx, &decl.initializer)
+        else {
+          continue;
+        };
+        if !is_pure_initializer(&init.stx) || !is_local_to_function(self.scopes, decl.pattern.scope)
+        {
+          continue;
+        };
+        let Some(sym) = decl.pattern.scope.find_symbol(*name) else {
+          continue;
+        };
+        let Some(min_sym) = self.symbols.get(&sym) else {
+          continue;
+        };
+        if min_sym.usage_count == 1
+          && !min_sym.is_used_as_jsx_component
+          && !min_sym.is_used_as_constructor
+        {
+          self.declarations.insert(sym, Declaration {
+            scope: decl.pattern.scope,
+            end: init.loc.end(),
+            is_function: matches!(
+              init.stx,
+              Syntax::ArrowFunctionExpr { .. } | Syntax::FunctionExpr { .. }
+            ),
+            loop_depth: self.loop_depth,
+          });
+        };
+      }
+    }
+  }
+}
+
+impl<'a, 'b> Visitor<'a> for Collect<'a, 'b> {
+  fn on_syntax_down(&mut self, n: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    match &n.stx {
+      stx if is_loop(stx) => self.loop_depth += 1,
+      Syntax::BlockStmt { body } | Syntax::SwitchBranch { body, .. } => {
+        self.collect_declarations(body)
+      }
+      Syntax::CallExpr { callee, .. } if matches!(callee.stx, Syntax::IdentifierExpr { .. }) => {
+        self.callees.insert(callee.loc.start());
+      }
+      // The emitter doesn't always parenthesise literals correctly as operands (e.g. `1.toString()` and `!0**2`), and the rest can only be identifiers.
+      Syntax::MemberExpr { left: operand, .. }
+      | Syntax::ComputedMemberExpr {
+        object: operand, ..
+      }
+      | Syntax::BinaryExpr {
+        operator: OperatorName::Exponentiation,
+        left: operand,
+        ..
+      }
+      | Syntax::UnaryExpr {
+        operator:
+          OperatorName::Delete | OperatorName::PrefixDecrement | OperatorName::PrefixIncrement,
+        argument: operand,
+        ..
+      }
+      | Syntax::UnaryPostfixExpr {
+        argument: operand, ..
+      }
+      // A string at the start of a function would become a directive.
+      | Syntax::ExpressionStmt {
+        expression: operand,
+      }
+      | Syntax::ObjectMember {
+        typ: ObjectMemberType::Shorthand {
+          identifier: operand,
+        },
+      } if matches!(operand.stx, Syntax::IdentifierExpr { .. }) => {
+        self.unreplaceable.insert(operand.loc.start());
+      }
+      Syntax::IdentifierExpr { name } => {
+        if let Some(sym) = n.scope.find_symbol(*name) {
+          self.usages.insert(sym, Usage {
+            scope: n.scope,
+            start: n.loc.start(),
+            loop_depth: self.loop_depth,
+            is_call: self.callees.contains(&n.loc.start()),
+            is_replaceable: !self.unreplaceable.contains(&n.loc.start()),
+          });
+        };
+      }
+      _ => {}
+    };
+  }
+
+  fn on_syntax_up(&mut self, n: &mut NodeData<'a>) -> () {
+    if is_loop(&n.stx) {
+      self.loop_depth -= 1;
+    };
+  }
+}
+
+// Takes the initialisers of the declarations to inline out of the tree.
+struct Take<'a> {
+  inlined: HashSet<Symbol>,
+  values: HashMap<Symbol, Node<'a>>,
+}
+
+impl<'a> Take<'a> {
+  fn take_declarations(&mut self, body: &mut SessionVec<'a, Node<'a>>) -> () {
+    body.retain_mut(|stmt| match &mut stmt.stx {
+      Syntax::VarDecl {
+        mode: VarDeclMode::Const,
+        declarators,
+        ..
+      } => {
+        declarators.retain_mut(|decl| {
+          let Syntax::IdentifierPattern { name } = &decl.pattern.stx else {
+            return true;
+          };
+          match decl.pattern.scope.find_symbol(*name) {
+            Some(sym) if self.inlined.contains(&sym) => {
+              self.values.insert(sym, decl.initializer.take().unwrap());
+              false
+            }
+            _ => true,
+          }
+        });
+        !declarators.is_empty()
+      }
+      _ => true,
+    });
+  }
+}
+
+impl<'a> Visitor<'a> for Take<'a> {
+  fn on_syntax_down(&mut self, n: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    match &mut n.stx {
+      Syntax::BlockStmt { body } | Syntax::SwitchBranch { body, .. } => {
+        self.take_declarations(body)
+      }
+      _ => {}
+    };
+  }
+}
+
+// Replaces the usages with the taken initialisers. This is a separate pass from `Take`, as a usage could be in a hoisted function that we visit before the declaration.
+struct Replace<'a> {
+  values: HashMap<Symbol, Node<'a>>,
+}
+
+impl<'a> Visitor<'a> for Replace<'a> {
+  fn on_syntax_down(&mut self, n: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    let Syntax::IdentifierExpr { name } = &n.stx else {
+      return;
+    };
+    let Some(sym) = n.scope.find_symbol(*name) else {
+      return;
+    };
+    if let Some(value) = self.values.remove(&sym) {
+      // The value is visited next, as its own nested usages may need to be replaced too.
+      core::mem::swap(n, value);
+      // Functions are only inlined as callees, where they must be parenthesised.
+      match &mut n.stx {
+        Syntax::ArrowFunctionExpr { parenthesised, .. }
+        | Syntax::FunctionExpr { parenthesised, .. } => *parenthesised = true,
+        _ => {}
+      };
+    };
+  }
+}
+
+// - Replace the single usage of a function-local `const` variable with its initialiser, if it's a literal. Literals can't be affected by anything between the declaration and the usage, so this is always safe.
+// - Replace the single call of a function-local `const` arrow function or function expression with an immediately invoked function (e.g. `const f = (a) => a * 2; return f(x)` becomes `return ((a) => a * 2)(x)`). The call must be after the declaration, not in a loop that the declaration isn't in, so that the function isn't created on every iteration, and in the same scope or a block in it that doesn't declare anything, so that the function's variables still refer to the same declarations, and a call that would have thrown because the variable was uninitialised isn't changed. Functions that call themselves by their variable have more than one usage, so are never inlined.
+// This must run after Pass1, which counts usages, and Pass2, which hoists function declarations out of the tree.
+pub fn inline<'a>(
+  symbols: &SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  top_level_node: &mut NodeData<'a>,
+) -> () {
+  let mut hoisted = take_hoisted_functions(scopes);
+
+  let mut collect = Collect {
+    symbols,
+    scopes: &*scopes,
+    declarations: HashMap::new(),
+    usages: HashMap::new(),
+    callees: HashSet::new(),
+    unreplaceable: HashSet::new(),
+    loop_depth: 0,
+  };
+  collect.visit(top_level_node);
+  for (_, _, func) in hoisted.iter_mut() {
+    collect.visit(func);
+  }
+  let inlined = collect
+    .declarations
+    .iter()
+    .filter(|(sym, decl)| {
+      collect.usages.get(sym).is_some_and(|usage| {
+        usage.is_replaceable
+          && usage.start >= decl.end
+          && (!decl.is_function
+            || (usage.is_call
+              && usage.loop_depth == decl.loop_depth
+              && is_same_bindings(usage.scope, decl.scope)))
+      })
+    })
+    .map(|(sym, _)| *sym)
+    .collect::<HashSet<_>>();
+
+  if !inlined.is_empty() {
+    let mut take = Take {
+      inlined,
+      values: HashMap::new(),
+    };
+    take.visit(top_level_node);
+    for (_, _, func) in hoisted.iter_mut() {
+      take.visit(func);
+    }
+    let mut replace = Replace {
+      values: take.values,
+    };
+    replace.visit(top_level_node);
+    for (_, _, func) in hoisted.iter_mut() {
+      replace.visit(func);
+    }
+  };
+
+  restore_hoisted_functions(scopes, hoisted);
+}
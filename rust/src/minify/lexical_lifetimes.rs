@@ -1,11 +1,29 @@
+use super::ctx::restore_hoisted_functions;
+use super::ctx::take_hoisted_functions;
 use super::ctx::Ctx;
+use super::ctx::MinifyScope;
 use super::ctx::MinifySymbol;
+use super::unused::is_local_to_function;
+use crate::MinifyError;
+use crate::MinifyOptions;
+use parse_js::ast::ForInOfStmtHeaderLhs;
+use parse_js::ast::ForStmtHeader;
+use parse_js::ast::ForThreeInit;
 use parse_js::ast::NodeData;
+use parse_js::ast::ObjectMemberType;
 use parse_js::ast::Syntax;
+use parse_js::ast::VarDeclMode;
+use parse_js::operator::OperatorName;
 use parse_js::session::Session;
+use parse_js::session::SessionHashMap;
 use parse_js::session::SessionVec;
+use parse_js::symbol::Identifier;
+use parse_js::symbol::Scope;
+use parse_js::symbol::Symbol;
 use parse_js::visit::JourneyControls;
 use parse_js::visit::Visitor;
+use std::collections::HashMap;
+use std::collections::HashSet;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct LexicalLifetime<'a>(SessionVec<'a, u32>);
@@ -23,6 +41,10 @@ impl<'a> LexicalLifetime<'a> {
     Self(vec)
   }
 
+  pub fn is_infinite(&self) -> bool {
+    self.0[0] == u32::MAX
+  }
+
   pub fn bump(&mut self) {
     *self.0.last_mut().unwrap() += 1;
   }
@@ -31,16 +53,79 @@ impl<'a> LexicalLifetime<'a> {
     self.0.push(0);
   }
 
-  pub fn is_sibling_of(&self, other: &Self) -> bool {
-    let lvl = self.0.len();
-    lvl == other.0.len() && (lvl == 1 || self.0[lvl - 2] == other.0[lvl - 2])
+  // Returns whether `self` is at the same level as `other` or nested in a later sibling of it, i.e. `other` is always reached before `self` when `self` is reached (ignoring loops).
+  pub fn is_dominated_by(&self, other: &Self) -> bool {
+    let lvl = other.0.len();
+    self.0.len() >= lvl && self.0[..lvl - 1] == other.0[..lvl - 1]
+  }
+}
+
+struct SymbolLifetime<'a> {
+  start: LexicalLifetime<'a>,
+  end: LexicalLifetime<'a>,
+}
+
+impl<'a> SymbolLifetime<'a> {
+  fn update(&mut self, lifetime: &LexicalLifetime<'a>) {
+    if *lifetime < self.start {
+      self.start = lifetime.clone();
+    };
+    if *lifetime > self.end {
+      self.end = lifetime.clone();
+    };
   }
 }
 
+struct Loop<'a> {
+  start: LexicalLifetime<'a>,
+  end: LexicalLifetime<'a>,
+  // Symbols that appear anywhere in the loop, including nested loops.
+  symbols: HashSet<Symbol>,
+}
+
+fn collect_identifier_patterns<'a>(
+  out: &mut Vec<(Scope<'a>, Identifier<'a>, usize)>,
+  n: &NodeData<'a>,
+) -> () {
+  match &n.stx {
+    Syntax::ArrayPattern { elements, rest } => {
+      for e in elements.iter().flatten() {
+        collect_identifier_patterns(out, e.target);
+      }
+      if let Some(rest) = rest {
+        collect_identifier_patterns(out, rest);
+      }
+    }
+    Syntax::ObjectPattern { properties, rest } => {
+      for p in properties {
+        collect_identifier_patterns(out, p);
+      }
+      if let Some(rest) = rest {
+        collect_identifier_patterns(out, rest);
+      }
+    }
+    Syntax::ObjectPatternProperty { target, .. } => collect_identifier_patterns(out, target),
+    Syntax::IdentifierPattern { name } => out.push((n.scope, *name, n.loc.start())),
+    _ => {}
+  };
+}
+
+// See [notes/Lexical lifetimes.md] for the algorithm. Nodes are visited in the order they're evaluated, so that lifetime values increase along every path through a function, except for loops, which are handled separately.
 pub struct LexicalLifetimesPass<'a, 'b> {
   pub ctx: Ctx<'a, 'b>,
   pub stack: LexicalLifetime<'a>,
-  pub in_loop: bool,
+  lifetimes: HashMap<Symbol, SymbolLifetime<'a>>,
+  // Loops we're currently in, innermost last.
+  loops: Vec<Loop<'a>>,
+  // Loops we've exited, inner loops before outer loops.
+  exited_loops: Vec<Loop<'a>>,
+  // Start offsets of IdentifierPattern nodes that are always assigned when their statement runs: declarators with initialisers, `for-in` and `for-of` left sides, and `=` assignment statements. Other assignments may not happen (e.g. `x && (y = 1)`) or also read the variable (e.g. `y += 1`), so they're treated like usages.
+  assignments: HashSet<usize>,
+  // Symbols declared by `var`, and symbols also declared in other ways (e.g. parameters and function declarations), which can't share a name.
+  var_symbols: HashMap<Symbol, Scope<'a>>,
+  other_symbols: HashSet<Symbol>,
+  // The first internal error, which stops minification once this pass is done.
+  error: Option<MinifyError<'a>>,
 }
 
 impl<'a, 'b> LexicalLifetimesPass<'a, 'b> {
@@ -49,7 +134,13 @@ impl<'a, 'b> LexicalLifetimesPass<'a, 'b> {
     Self {
       ctx,
       stack,
-      in_loop: false,
+      lifetimes: HashMap::new(),
+      loops: Vec::new(),
+      exited_loops: Vec::new(),
+      assignments: HashSet::new(),
+      var_symbols: HashMap::new(),
+      other_symbols: HashSet::new(),
+      error: None,
     }
   }
 
@@ -68,6 +159,136 @@ impl<'a, 'b> LexicalLifetimesPass<'a, 'b> {
     &mut self.stack
   }
 
+  fn track(&mut self, scope: Scope<'a>, name: Identifier<'a>, is_assignment: bool) {
+    let Some((decl_scope, symbol)) = scope.find_symbol_with_scope(name) else {
+      return;
+    };
+    let (Some(usage_closure_scope), Some(decl_closure_scope)) = (
+      scope.find_self_or_ancestor(|s| s.is_closure_or_class()),
+      decl_scope.find_self_or_ancestor(|s| s.is_closure()),
+    ) else {
+      // Global code; its variables are never minified.
+      return;
+    };
+    let lifetime = if decl_closure_scope == usage_closure_scope {
+      self.bump_stack().clone()
+    } else {
+      // We're in a nested closure or class.
+      LexicalLifetime::new_infinite(self.ctx.session)
+    };
+    match self.lifetimes.get_mut(&symbol) {
+      Some(existing) => {
+        // If the first assignment doesn't always happen before this, the variable could still have its initial `undefined` value here.
+        if !lifetime.is_dominated_by(&existing.start) {
+          existing.start = LexicalLifetime::new_zero(self.ctx.session);
+        };
+        existing.update(&lifetime);
+      }
+      None => {
+        // If a variable is used before it's assigned, it relies on its initial `undefined` value, so it's live from the start.
+        let start = if is_assignment {
+          lifetime.clone()
+        } else {
+          LexicalLifetime::new_zero(self.ctx.session)
+        };
+        self.lifetimes.insert(symbol, SymbolLifetime {
+          start,
+          end: lifetime,
+        });
+      }
+    };
+    if let Some(l) = self.loops.last_mut() {
+      l.symbols.insert(symbol);
+    };
+  }
+
+  // Visits each declarator's initialiser before its pattern, as that's when the variable is assigned. Declarators without initialisers don't do anything, except in `for-in` and `for-of` headers.
+  fn visit_var_decl(&mut self, decl: &mut NodeData<'a>, is_loop_header: bool) {
+    let Syntax::VarDecl {
+      mode, declarators, ..
+    } = &mut decl.stx
+    else {
+      self
+        .error
+        .get_or_insert(MinifyError::Internal("declaration isn't a VarDecl"));
+      return;
+    };
+    for d in declarators.iter_mut() {
+      let mut patterns = Vec::new();
+      collect_identifier_patterns(&mut patterns, d.pattern);
+      if *mode == VarDeclMode::Var {
+        for (scope, name, _) in patterns.iter() {
+          if let Some((decl_scope, sym)) = scope.find_symbol_with_scope(*name) {
+            self.var_symbols.insert(sym, decl_scope);
+          };
+        }
+      };
+      match &mut d.initializer {
+        Some(init) => self.visit(init),
+        None if !is_loop_header => continue,
+        None => {}
+      };
+      self
+        .assignments
+        .extend(patterns.iter().map(|(_, _, start)| *start));
+      self.visit(d.pattern);
+    }
+  }
+
+  fn collect_assignments(&mut self, expr: &NodeData<'a>) {
+    match &expr.stx {
+      Syntax::BinaryExpr {
+        operator: OperatorName::Comma,
+        left,
+        right,
+        ..
+      } => {
+        self.collect_assignments(left);
+        self.collect_assignments(right);
+      }
+      Syntax::BinaryExpr {
+        operator: OperatorName::Assignment,
+        left,
+        ..
+      } => {
+        let mut patterns = Vec::new();
+        collect_identifier_patterns(&mut patterns, left);
+        self
+          .assignments
+          .extend(patterns.iter().map(|(_, _, start)| *start));
+      }
+      _ => {}
+    };
+  }
+
+  // Code that may or may not run gets its own nested lifetime values. Sibling calls get later values, so that values still increase along paths from one to the next (e.g. `switch` fallthrough and `try` to `catch`).
+  fn visit_forked(&mut self, node: &mut NodeData<'a>) {
+    let orig = self.bump_stack().clone();
+    self.fork_stack();
+    self.visit(node);
+    self.join_stack(&orig);
+  }
+
+  fn enter_loop(&mut self) -> () {
+    let start = self.bump_stack().clone();
+    self.loops.push(Loop {
+      start: start.clone(),
+      end: start,
+      symbols: HashSet::new(),
+    });
+    self.fork_stack();
+  }
+
+  fn exit_loop(&mut self) -> () {
+    let mut l = self.loops.pop().unwrap();
+    self.join_stack(&l.start);
+    l.end = self.bump_stack().clone();
+    if let Some(parent) = self.loops.last_mut() {
+      parent.symbols.extend(l.symbols.iter().copied());
+    };
+    self.exited_loops.push(l);
+  }
+
   fn visit_conditional(
     &mut self,
     test: &mut NodeData<'a>,
@@ -77,15 +298,10 @@ impl<'a, 'b> LexicalLifetimesPass<'a, 'b> {
   ) {
     self.visit(test);
 
-    // Conditionals get their own lifetime value, and then each branch is a fork (i.e. subelements).
-    let if_stack = self.bump_stack().clone();
-    self.fork_stack();
-    self.visit(consequent);
-    self.join_stack(&if_stack);
+    // Each branch is a fork (i.e. subelements) of its own lifetime value, so that a value in one branch is never considered to dominate a value in the other.
+    self.visit_forked(consequent);
     if let Some(alternate) = alternate {
-      self.fork_stack();
-      self.visit(alternate);
-      self.join_stack(&if_stack);
+      self.visit_forked(alternate);
     };
 
     ctl.skip();
@@ -94,35 +310,49 @@ impl<'a, 'b> LexicalLifetimesPass<'a, 'b> {
 
 impl<'a, 'b> Visitor<'a> for LexicalLifetimesPass<'a, 'b> {
   fn on_syntax_down(&mut self, node: &mut NodeData<'a>, ctl: &mut JourneyControls) -> () {
-    let usage_scope = node.scope;
-    let Some(usage_closure_scope) = usage_scope.find_self_or_ancestor(|s| s.is_closure_or_class())
-    else {
-      // TODO Assert we're at the top level.
-      return;
-    };
     // We could reset the stack when entering a closure and restore on exit, but this isn't strictly necessary since values are still distinct even if we continue to use existing stack. It also avoids some complexity and performance costs.
+    let scope = node.scope;
+    let start = node.loc.start();
     match &mut node.stx {
       Syntax::IdentifierExpr { name } => {
-        if let Some((decl_scope, symbol)) = usage_scope.find_symbol_with_scope(*name) {
-          let decl_closure_scope = decl_scope
-            .find_self_or_ancestor(|s| s.is_closure())
-            .unwrap();
-          let lifetime = if decl_closure_scope == usage_closure_scope {
-            self.bump_stack().clone()
-          } else {
-            // We're in a nested closure or class.
-            LexicalLifetime::new_infinite(self.ctx.session)
+        self.track(scope, *name, false);
+      }
+      // The visitor doesn't descend into shorthand members (e.g. `{a}`).
+      Syntax::ObjectMember {
+        typ: ObjectMemberType::Shorthand { identifier },
+      } => {
+        self.track(scope, identifier.loc, false);
+      }
+      Syntax::IdentifierPattern { name } => {
+        let is_assignment = self.assignments.remove(&start);
+        self.track(scope, *name, is_assignment);
+      }
+      Syntax::ParamDecl { pattern, .. } => {
+        let mut patterns = Vec::new();
+        collect_identifier_patterns(&mut patterns, pattern);
+        for (scope, name, _) in patterns {
+          if let Some(sym) = scope.find_symbol(name) {
+            self.other_symbols.insert(sym);
           };
-          self
-            .ctx
-            .symbols
-            .entry(symbol)
-            .or_insert_with(|| MinifySymbol::new(self.ctx.session))
-            .update_lifetime(lifetime);
         }
       }
-      Syntax::IdentifierPattern { name } => {
-        // TODO
+      Syntax::VarDecl { .. } => {
+        self.visit_var_decl(node, false);
+        ctl.skip();
+      }
+      Syntax::ExpressionStmt { expression } => {
+        self.collect_assignments(expression);
+      }
+      // The variable is assigned after the value is evaluated, which may use its previous value.
+      Syntax::BinaryExpr {
+        operator: OperatorName::Assignment,
+        left,
+        right,
+        ..
+      } => {
+        self.visit(right);
+        self.visit(left);
+        ctl.skip();
       }
       Syntax::IfStmt {
         test,
@@ -144,16 +374,160 @@ impl<'a, 'b> Visitor<'a> for LexicalLifetimesPass<'a, 'b> {
       } => {
         self.visit_conditional(test, consequent, Some(alternate), ctl);
       }
+      Syntax::SwitchStmt { test, branches } => {
+        self.visit(test);
+        for branch in branches.iter_mut() {
+          self.visit_forked(branch);
+        }
+        ctl.skip();
+      }
+      Syntax::TryStmt {
+        wrapped,
+        catch,
+        finally,
+      } => {
+        self.visit_forked(wrapped);
+        if let Some(catch) = catch {
+          self.visit_forked(catch);
+        };
+        if let Some(finally) = finally {
+          self.visit_forked(finally);
+        };
+        ctl.skip();
+      }
+      // A labelled statement can be exited early with `break`.
+      Syntax::LabelStmt { statement, .. } => {
+        self.visit_forked(statement);
+        ctl.skip();
+      }
       Syntax::ForStmt { header, body } => {
-        // TODO
+        match header {
+          ForStmtHeader::Three {
+            init,
+            condition,
+            post,
+          } => {
+            match init {
+              ForThreeInit::None => {}
+              ForThreeInit::Expression(expr) => self.visit(expr),
+              ForThreeInit::Declaration(decl) => self.visit(decl),
+            };
+            self.enter_loop();
+            if let Some(condition) = condition {
+              self.visit(condition);
+            };
+            self.visit(body);
+            if let Some(post) = post {
+              self.visit(post);
+            };
+            self.exit_loop();
+          }
+          ForStmtHeader::InOf { lhs, rhs, .. } => {
+            self.visit(rhs);
+            self.enter_loop();
+            match lhs {
+              ForInOfStmtHeaderLhs::Declaration(decl) => self.visit_var_decl(decl, true),
+              ForInOfStmtHeaderLhs::Pattern(pat) => {
+                let mut patterns = Vec::new();
+                collect_identifier_patterns(&mut patterns, pat);
+                self
+                  .assignments
+                  .extend(patterns.iter().map(|(_, _, start)| *start));
+                self.visit(pat);
+              }
+            };
+            self.visit(body);
+            self.exit_loop();
+          }
+        };
+        ctl.skip();
       }
       Syntax::WhileStmt { condition, body } => {
-        // TODO
+        self.enter_loop();
+        self.visit(condition);
+        self.visit(body);
+        self.exit_loop();
+        ctl.skip();
       }
       Syntax::DoWhileStmt { condition, body } => {
-        // TODO
+        self.enter_loop();
+        self.visit(body);
+        self.visit(condition);
+        self.exit_loop();
+        ctl.skip();
       }
       _ => {}
     };
   }
 }
+
+// Computes `lexical_lifetime_start` and `lexical_lifetime_end` for `var` variables local to a function, so that variables whose lifetimes don't overlap can share a name. Other symbols keep lifetimes that overlap everything.
+// A variable's value can survive from one iteration of a loop to the next, or be assigned in a loop and used after it (even if the assignment comes after an earlier `break`), so a variable that's used both inside and outside a loop, or may be used before it's assigned, is live for the entire loop.
+// This must run after Pass2, which hoists function declarations out of the tree, and after any passes that remove code, so that lifetimes are as short as possible.
+pub fn compute_lexical_lifetimes<'a>(
+  session: &'a Session,
+  options: &MinifyOptions,
+  symbols: &mut SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  top_level_node: &mut NodeData<'a>,
+) -> Result<(), MinifyError<'a>> {
+  let mut hoisted = take_hoisted_functions(scopes);
+  let mut pass = LexicalLifetimesPass::new(Ctx {
+    session,
+    options,
+    symbols,
+    scopes,
+  });
+  for (scope, name, _) in hoisted.iter() {
+    if let Some(sym) = scope.get_symbol(*name) {
+      pass.other_symbols.insert(sym);
+    };
+  }
+  pass.visit(top_level_node);
+  for (_, _, func) in hoisted.iter_mut() {
+    pass.visit(func);
+  }
+
+  let LexicalLifetimesPass {
+    ctx,
+    mut lifetimes,
+    exited_loops,
+    var_symbols,
+    other_symbols,
+    error,
+    ..
+  } = pass;
+  if let Some(err) = error {
+    restore_hoisted_functions(ctx.scopes, hoisted);
+    return Err(err);
+  };
+  for l in exited_loops.iter() {
+    for sym in l.symbols.iter() {
+      let lifetime = lifetimes.get_mut(sym).unwrap();
+      if lifetime.start < l.start || lifetime.end > l.end {
+        lifetime.update(&l.start);
+        lifetime.update(&l.end);
+      };
+    }
+  }
+  for (sym, lifetime) in lifetimes {
+    let Some(scope) = var_symbols.get(&sym) else {
+      continue;
+    };
+    if other_symbols.contains(&sym)
+      || lifetime.end.is_infinite()
+      || !is_local_to_function(ctx.scopes, *scope)
+    {
+      continue;
+    };
+    let min_sym = ctx
+      .symbols
+      .entry(sym)
+      .or_insert_with(|| MinifySymbol::new(session));
+    min_sym.lexical_lifetime_start = lifetime.start;
+    min_sym.lexical_lifetime_end = lifetime.end;
+  }
+
+  restore_hoisted_functions(ctx.scopes, hoisted);
+  Ok(())
+}
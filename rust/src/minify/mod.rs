@@ -1,18 +1,37 @@
 pub mod advanced_if;
 pub mod ctx;
+pub mod dead_code;
+pub mod early_errors;
+pub mod fold;
+pub mod inline;
 pub mod lexical_lifetimes;
 pub mod name;
 pub mod pass1;
 pub mod pass2;
 pub mod pass3;
+pub mod properties;
+pub mod unused;
 
 use self::ctx::Ctx;
 use self::ctx::MinifyScope;
 use self::ctx::MinifySymbol;
+use self::dead_code::DeadCode;
+use self::early_errors::EarlyErrors;
+use self::fold::ConstantFold;
+use self::inline::inline;
+use self::lexical_lifetimes::compute_lexical_lifetimes;
+use self::name::count_unmangled_chars;
 use self::name::minify_names;
+use self::name::NameAlphabet;
 use self::pass1::Pass1;
 use self::pass2::Pass2;
 use self::pass3::Pass3;
+use self::properties::mangle_properties;
+use self::unused::remove_unused;
+use crate::MinifyError;
+use crate::MinifyOptions;
+use crate::NameCache;
+use crate::Warning;
 use parse_js::ast::new_node;
 use parse_js::ast::ExportName;
 use parse_js::ast::ExportNames;
@@ -22,10 +41,24 @@ use parse_js::session::Session;
 use parse_js::symbol::Scope;
 use parse_js::symbol::Symbol;
 use parse_js::visit::Visitor;
+use std::collections::HashSet;
+use std::str::from_utf8;
 
-pub fn minify_js<'a>(session: &'a Session, top_level_node: &mut NodeData<'a>) -> () {
+// If `name_cache` is provided, cached names are reused for top-level variables and mangled properties where possible, and the chosen names are added to it. Returns warnings about code that prevented some minification.
+pub fn minify_js<'a>(
+  session: &'a Session,
+  options: &MinifyOptions,
+  top_level_node: &mut NodeData<'a>,
+  mut name_cache: Option<&mut NameCache>,
+) -> Result<Vec<Warning>, MinifyError<'a>> {
   let top_level_scope = top_level_node.scope;
 
+  let mut early_errors = EarlyErrors::new();
+  early_errors.visit(top_level_node);
+  if let Some(err) = early_errors.error {
+    return Err(err);
+  };
+
   // Our custom data/state associated with a Symbol.
   let mut symbols = session.new_hashmap::<Symbol, MinifySymbol>();
   // Our custom data/state associated with a Scope.
@@ -33,17 +66,43 @@ pub fn minify_js<'a>(session: &'a Session, top_level_node: &mut NodeData<'a>) ->
   // Exports: what they refer to and what they're named.
   let mut export_bindings = Vec::new();
 
-  Pass1 {
+  if options.fold_constants {
+    ConstantFold { session }.visit(top_level_node);
+  };
+
+  if options.dead_code {
+    let mut dead_code = DeadCode {
+      session,
+      error: None,
+    };
+    dead_code.visit(top_level_node);
+    if let Some(err) = dead_code.error {
+      return Err(err);
+    };
+  };
+
+  let mut pass1 = Pass1 {
     ctx: Ctx {
+      options,
       scopes: &mut scopes,
       session,
       symbols: &mut symbols,
     },
-  }
-  .visit(top_level_node);
+    declaration_patterns: HashSet::new(),
+    directives: HashSet::new(),
+    class_depth: 0,
+    warnings: Vec::new(),
+    error: None,
+  };
+  pass1.visit(top_level_node);
+  if let Some(err) = pass1.error {
+    return Err(err);
+  };
+  let warnings = pass1.warnings;
 
   Pass2 {
     ctx: Ctx {
+      options,
       scopes: &mut scopes,
       session,
       symbols: &mut symbols,
@@ -51,23 +110,82 @@ pub fn minify_js<'a>(session: &'a Session, top_level_node: &mut NodeData<'a>) ->
   }
   .visit(top_level_node);
 
-  minify_names(session, top_level_scope, &mut scopes, &mut symbols);
+  if options.remove_unused {
+    remove_unused(&symbols, &mut scopes, top_level_node);
+  };
+
+  if options.inline {
+    inline(&symbols, &mut scopes, top_level_node);
+  };
+
+  if options.mangle {
+    compute_lexical_lifetimes(session, options, &mut symbols, &mut scopes, top_level_node)?;
+  };
+
+  let alphabet = if options.mangle && options.frequency_alphabet {
+    NameAlphabet::by_frequency(session, &count_unmangled_chars(top_level_node, &symbols))
+  } else {
+    NameAlphabet::default()
+  };
 
-  Pass3 {
+  minify_names(
     session,
+    options,
+    alphabet,
+    top_level_scope,
+    &mut scopes,
+    &mut symbols,
+    name_cache.as_deref(),
+  )?;
+  if let Some(name_cache) = name_cache.as_deref_mut() {
+    if options.mangle {
+      for &name in top_level_scope.symbol_names().iter() {
+        let sym = top_level_scope.get_symbol(name).unwrap();
+        let minified = symbols
+          .get(&sym)
+          .and_then(|s| s.minified_name)
+          .ok_or(MinifyError::Internal("top-level variable has no minified name"))?;
+        // Names are always UTF-8, as they're from the source or generated.
+        name_cache.top_level.insert(
+          from_utf8(name.as_slice()).unwrap().to_string(),
+          from_utf8(minified.as_slice()).unwrap().to_string(),
+        );
+      }
+    };
+  };
+
+  let mut pass3 = Pass3 {
+    session,
+    options,
     export_bindings: &mut export_bindings,
     symbols: &mut symbols,
     scopes: &mut scopes,
-  }
-  .visit(top_level_node);
+    error: None,
+  };
+  pass3.visit(top_level_node);
+  if let Some(err) = pass3.error {
+    return Err(err);
+  };
+
+  if let Some(pattern) = &options.mangle_properties {
+    mangle_properties(
+      session,
+      pattern,
+      alphabet,
+      top_level_node,
+      name_cache.map(|c| &mut c.properties),
+    );
+  };
 
   let mut export_names = session.new_vec();
   for e in export_bindings.iter() {
-    let target_symbol = top_level_scope
+    // EarlyErrors has already checked that exports refer to top-level declarations.
+    let target = top_level_scope
       .find_symbol(e.target)
-      .expect(format!("failed to find top-level export `{:?}`", e.target).as_str());
+      .and_then(|sym| symbols.get(&sym)?.minified_name)
+      .ok_or(MinifyError::Internal("export doesn't refer to a top-level variable"))?;
     export_names.push(ExportName {
-      target: symbols[&target_symbol].minified_name.unwrap(),
+      target,
       alias: new_node(
         session,
         top_level_scope,
@@ -91,7 +209,8 @@ pub fn minify_js<'a>(session: &'a Session, top_level_node: &mut NodeData<'a>) ->
       Syntax::TopLevel { body } => {
         body.push(final_export_stmt);
       }
-      _ => unreachable!(),
+      _ => return Err(MinifyError::Internal("top-level node isn't TopLevel")),
     }
   }
+  Ok(warnings)
 }
@@ -1,149 +1,495 @@
 use super::ctx::MinifyScope;
 use super::ctx::MinifySymbol;
+use super::lexical_lifetimes::LexicalLifetime;
+use crate::MinifyError;
+use crate::MinifyOptions;
+use crate::name_cache::is_identifier_name;
+use crate::NameCache;
 use parse_js::char::ID_CONTINUE_CHARSTR;
 use parse_js::char::ID_START_CHARSTR;
+use parse_js::ast::NodeData;
 use parse_js::lex::KEYWORD_STRS;
 use parse_js::session::Session;
 use parse_js::session::SessionHashMap;
 use parse_js::session::SessionHashSet;
-use parse_js::session::SessionVec;
 use parse_js::source::SourceRange;
 use parse_js::symbol::Identifier;
 use parse_js::symbol::Scope;
 use parse_js::symbol::Symbol;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::str::from_utf8;
 
-// Generator of minified names. Works by generating the next smallest possible name (starting from `a`), and then repeats until it finds one that is not a keyword or would conflict with an inherited variable (a variable that is in scope **and** used by code that we would otherwise shadow).
+// Characters that minified names are made of, in the order they're tried. Names start with a character from `start`, and continue with characters from `continue_`.
+#[derive(Clone, Copy)]
+pub struct NameAlphabet<'a> {
+  pub start: &'a [u8],
+  pub continue_: &'a [u8],
+}
+
+impl<'a> Default for NameAlphabet<'a> {
+  fn default() -> Self {
+    NameAlphabet {
+      start: ID_START_CHARSTR,
+      continue_: ID_CONTINUE_CHARSTR,
+    }
+  }
+}
+
+impl<'a> NameAlphabet<'a> {
+  // Orders characters by how often they appear in `counts` (indexed by byte), most frequent first, so that minified names are made of the characters that are already most common in the output, which compresses better under gzip and brotli. Ties keep their default order.
+  pub fn by_frequency(session: &'a Session, counts: &[usize; 256]) -> NameAlphabet<'a> {
+    let sort = |chars: &[u8]| {
+      let sorted = session.get_allocator().alloc_slice_copy(chars);
+      sorted.sort_by_key(|&c| std::cmp::Reverse(counts[c as usize]));
+      &*sorted
+    };
+    NameAlphabet {
+      start: sort(ID_START_CHARSTR),
+      continue_: sort(ID_CONTINUE_CHARSTR),
+    }
+  }
+}
+
+// Counts how often each byte appears in the source code of `top_level_node`, excluding the original names of declared variables, which will be replaced. The output doesn't exist yet when names are chosen, so this approximates it.
+pub fn count_unmangled_chars<'a>(
+  top_level_node: &NodeData<'a>,
+  minify_symbols: &SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+) -> [usize; 256] {
+  let mut counts = [0usize; 256];
+  for &c in top_level_node.loc.as_slice() {
+    counts[c as usize] += 1;
+  }
+  let mut scopes = vec![top_level_node.scope];
+  while let Some(scope) = scopes.pop() {
+    for &sym_name in scope.symbol_names().iter() {
+      let sym = scope.get_symbol(sym_name).unwrap();
+      // Each usage, plus the declaration.
+      let occurrences = minify_symbols.get(&sym).map_or(0, |s| s.usage_count) + 1;
+      for &c in sym_name.as_slice() {
+        counts[c as usize] = counts[c as usize].saturating_sub(occurrences);
+      }
+    }
+    scopes.extend(scope.children().iter().copied());
+  }
+  counts
+}
+
+// Generator of minified names. Names are generated in order of their ordinal: all one-character names in alphabet order (starting from `a` by default), then all two-character names, and so on. Names that are keywords or would conflict with an inherited variable (a variable that is in scope **and** used by code that we would otherwise shadow) are blocked.
+// Blocked names are converted to ordinals up front, so skipping one is a comparison instead of generating the name and looking it up, which matters when there's a long run of them (e.g. an ancestor scope's minified names, which every descendant scope that uses them inherits).
 pub struct MinifiedNameGenerator<'a> {
   session: &'a Session,
-  // Index of each character of the last generated name, in reverse order (i.e. first character is last element) for optimised extension.
-  state: SessionVec<'a, usize>,
+  alphabet: NameAlphabet<'a>,
+  // Ordinals of blocked names, sorted and deduplicated.
+  blocked: Vec<usize>,
+  // Index into `blocked` of the first ordinal that's at least `next`.
+  next_blocked: usize,
+  // Ordinal of the next name to try.
+  next: usize,
 }
 
 impl<'a> MinifiedNameGenerator<'a> {
-  pub fn new(session: &'a Session) -> MinifiedNameGenerator<'a> {
-    MinifiedNameGenerator {
+  pub fn new(
+    session: &'a Session,
+    alphabet: NameAlphabet<'a>,
+    inherited_vars: &SessionHashSet<Identifier<'a>>,
+  ) -> MinifiedNameGenerator<'a> {
+    let mut generator = MinifiedNameGenerator {
       session,
-      state: session.new_vec(),
+      alphabet,
+      blocked: Vec::new(),
+      next_blocked: 0,
+      next: 0,
+    };
+    // Names that can't be generated (e.g. `camelCase` if there's no ordinal that large) don't need to be blocked.
+    let mut blocked: Vec<usize> = KEYWORD_STRS
+      .keys()
+      .copied()
+      .chain(inherited_vars.iter().map(|n| n.as_slice()))
+      .filter_map(|n| generator.ordinal_of(n))
+      .collect();
+    blocked.sort_unstable();
+    blocked.dedup();
+    generator.blocked = blocked;
+    generator
+  }
+
+  // Returns the number of names that are `len` characters long, or None if it overflows.
+  fn count_of_len(&self, len: usize) -> Option<usize> {
+    let mut count = self.alphabet.start.len();
+    for _ in 1..len {
+      count = count.checked_mul(self.alphabet.continue_.len())?;
     }
+    Some(count)
   }
 
-  fn transition_to_next_possible_minified_name(&mut self) -> SessionVec<'a, u8> {
-    let n = &mut self.state;
-    let mut overflow = true;
-    for i in 0..n.len() {
-      let charset = if i == n.len() - 1 {
-        ID_START_CHARSTR
-      } else {
-        ID_CONTINUE_CHARSTR
-      };
-      if n[i] == charset.len() - 1 {
-        n[i] = 0;
-      } else {
-        n[i] += 1;
-        overflow = false;
-        break;
-      };
+  // Returns the ordinal of the first name that's `len` characters long, or None if it overflows.
+  fn first_of_len(&self, len: usize) -> Option<usize> {
+    let mut ordinal: usize = 0;
+    for l in 1..len {
+      ordinal = ordinal.checked_add(self.count_of_len(l)?)?;
     }
-    if overflow {
-      n.push(0);
-    };
+    Some(ordinal)
+  }
+
+  fn ordinal_of(&self, name: &[u8]) -> Option<usize> {
+    let (&first, rest) = name.split_first()?;
+    let mut index = self.alphabet.start.iter().position(|&c| c == first)?;
+    for &c in rest {
+      let digit = self.alphabet.continue_.iter().position(|&d| d == c)?;
+      index = index
+        .checked_mul(self.alphabet.continue_.len())?
+        .checked_add(digit)?;
+    }
+    self.first_of_len(name.len())?.checked_add(index)
+  }
+
+  // Returns the length of the names at `ordinal`, and the ordinal's index within names of that length.
+  fn len_and_index_of(&self, ordinal: usize) -> (usize, usize) {
+    let mut len = 1;
+    let mut index = ordinal;
+    // Names of the next length would have overflowed `ordinal`, so `count_of_len` can't overflow before we find the length.
+    while index >= self.count_of_len(len).unwrap() {
+      index -= self.count_of_len(len).unwrap();
+      len += 1;
+    }
+    (len, index)
+  }
+
+  fn name_of(&self, ordinal: usize) -> Identifier<'a> {
+    let (len, mut index) = self.len_and_index_of(ordinal);
+    let name = self.session.get_allocator().alloc_slice_fill_default(len);
+    for c in name[1..].iter_mut().rev() {
+      *c = self.alphabet.continue_[index % self.alphabet.continue_.len()];
+      index /= self.alphabet.continue_.len();
+    }
+    name[0] = self.alphabet.start[index];
+    SourceRange::from_slice(name)
+  }
 
-    let mut name = self.session.new_vec(); // TODO Capacity
-    for (i, idx) in n.iter().enumerate() {
-      let charset = if i == n.len() - 1 {
-        ID_START_CHARSTR
-      } else {
-        ID_CONTINUE_CHARSTR
+  // Moves `next` past any blocked ordinals.
+  fn skip_blocked(&mut self) -> () {
+    while let Some(&b) = self.blocked.get(self.next_blocked) {
+      if b > self.next {
+        break;
       };
-      name.push(charset[*idx]);
+      if b == self.next {
+        self.next += 1;
+      };
+      self.next_blocked += 1;
     }
-    name.reverse();
+  }
+
+  pub fn generate_next_available_minified_name(&mut self) -> Identifier<'a> {
+    self.skip_blocked();
+    let name = self.name_of(self.next);
+    self.next += 1;
     name
   }
 
-  // TODO This needs optimisation, in case inherited_vars has a long sequence of used minified names (likely).
-  pub fn generate_next_available_minified_name(
-    &mut self,
-    inherited_vars: &SessionHashSet<Identifier<'a>>,
-  ) -> Identifier<'a> {
+  // JSX treats lowercase element names as HTML tags, so components need names that don't start with a lowercase letter. Names of the same length are ordered by their first character, so this jumps straight past every name that starts with a lowercase letter, instead of generating each one.
+  pub fn generate_next_available_jsx_component_name(&mut self) -> Identifier<'a> {
     loop {
-      let name = self.transition_to_next_possible_minified_name();
-      if KEYWORD_STRS.contains_key(name.as_slice()) {
-        continue;
+      self.skip_blocked();
+      let (len, index) = self.len_and_index_of(self.next);
+      let per_start = self.count_of_len(len).unwrap() / self.alphabet.start.len();
+      let start = index / per_start;
+      if !self.alphabet.start[start].is_ascii_lowercase() {
+        break;
       };
-      let name = self
-        .session
-        .get_allocator()
-        .alloc_slice_copy(name.as_slice());
-      let as_ident = SourceRange::new(name, 0, name.len());
-      if inherited_vars.contains(&as_ident) {
-        continue;
+      self.next = match self.alphabet.start[start..]
+        .iter()
+        .position(|c| !c.is_ascii_lowercase())
+      {
+        Some(offset) => self.first_of_len(len).unwrap() + (start + offset) * per_start,
+        None => self.first_of_len(len + 1).unwrap(),
       };
-      return as_ident;
     }
+    self.generate_next_available_minified_name()
+  }
+}
+
+fn keep_original_names<'a>(
+  session: &'a Session,
+  scope: Scope<'a>,
+  minify_symbols: &mut SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+) -> () {
+  for &sym_name in scope.symbol_names().iter() {
+    let sym = scope.get_symbol(sym_name).unwrap();
+    minify_symbols
+      .entry(sym)
+      .or_insert_with(|| MinifySymbol::new(session))
+      .minified_name = Some(sym_name);
   }
 }
 
 // This should be run after Pass2 and before Pass3 visitor runs.
 // The Pass1 pass collects all usages of variables to determine inherited variables for each scope, so we can know what minified names can be safely used (see `MinifiedNameGenerator`). This function will then go through each declaration in each scope and generate and update their corresponding `MinifySymbol.minified_name`.
 // Some pecularities to note: globals aren't minified (whether declared or not), so when blacklisting minified names, they are directly disallowed. However, all other variables will be minified, so we need to blacklist their minified name, not their original name. This is why this function processes scopes top-down (from the root), as we need to know the minified names of ancestor variables first before we can blacklist them.
+// If mangling is disabled, every symbol's "minified" name is simply its original name, so later passes don't need to special case it.
+// `name_cache` should only be provided for the top-level scope. Its cached names are reused for the scope's symbols where possible, and are never chosen for other symbols in the scope.
 pub fn minify_names<'a>(
   session: &'a Session,
+  options: &MinifyOptions,
+  alphabet: NameAlphabet<'a>,
   scope: Scope<'a>,
   minify_scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
   minify_symbols: &mut SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
-) {
+  name_cache: Option<&NameCache>,
+) -> Result<(), MinifyError<'a>> {
+  if !options.mangle {
+    keep_original_names(session, scope, minify_symbols);
+    for &c in scope.children().iter() {
+      minify_names(
+        session,
+        options,
+        alphabet,
+        c,
+        minify_scopes,
+        minify_symbols,
+        None,
+      )?;
+    }
+    return Ok(());
+  };
+  // Our `inherited_vars` contains original names; we need to retrieve their minified names. A variable inherited by this scope is either declared by the parent scope, or inherited by it too, so we don't need to search the whole scope chain, which is slow when it's deep.
+  let parent = scope.parent();
+  let parent_minified_inherited_vars = parent
+    .and_then(|p| minify_scopes.get(&p))
+    .map(|p| &p.minified_inherited_vars);
+  let mut resolved_inherited_vars = session.new_hashmap();
   // It's possible that the entry doesn't exist, if there were no inherited variables during the first pass.
+  if let Some(minify_scope) = minify_scopes.get(&scope) {
+    for &original_inherited_var in minify_scope.inherited_vars.iter() {
+      let min_name = match parent.and_then(|p| p.get_symbol(original_inherited_var)) {
+        // Ancestor scopes are named first, so this should already have a name.
+        Some(sym) => minify_symbols
+          .get(&sym)
+          .and_then(|s| s.minified_name)
+          .ok_or(MinifyError::Internal(
+            "inherited variable has no minified name",
+          ))?,
+        None => parent_minified_inherited_vars
+          .and_then(|p| p.get(&original_inherited_var))
+          .copied()
+          // Global (undeclared or declared).
+          .unwrap_or(original_inherited_var),
+      };
+      resolved_inherited_vars.insert(original_inherited_var, min_name);
+    }
+  };
+  let mut minified_inherited_vars = session.new_hashset();
+  minified_inherited_vars.extend(resolved_inherited_vars.values().copied());
   let minify_scope = minify_scopes
     .entry(scope)
     .or_insert_with(|| MinifyScope::new(session));
-  // Our `inherited_vars` contains original names; we need to retrieve their minified names.
-  let mut minified_inherited_vars = session.new_hashset();
-  for &original_inherited_var in minify_scope.inherited_vars.iter() {
-    match scope.find_symbol(original_inherited_var) {
-      None => {
-        // Global (undeclared or declared).
-        minified_inherited_vars.insert(original_inherited_var);
+  minify_scope.minified_inherited_vars = resolved_inherited_vars;
+  // Code evaluated by a direct `eval` can use any of this scope's variables by their original names. Descendant scopes that don't contain the call can still be minified, and their inherited variables are resolved as usual.
+  if minify_scope.has_direct_eval {
+    keep_original_names(session, scope, minify_symbols);
+    for &c in scope.children().iter() {
+      minify_names(
+        session,
+        options,
+        alphabet,
+        c,
+        minify_scopes,
+        minify_symbols,
+        None,
+      )?;
+    }
+    return Ok(());
+  };
+  let mut cached_symbols = HashSet::new();
+  if let Some(name_cache) = name_cache {
+    let mut used = HashSet::new();
+    for &sym_name in scope.symbol_names().iter() {
+      let Some(cached) = from_utf8(sym_name.as_slice())
+        .ok()
+        .and_then(|n| name_cache.top_level.get(n))
+        .filter(|c| is_identifier_name(c))
+      else {
+        continue;
+      };
+      let cached =
+        SourceRange::from_slice(session.get_allocator().alloc_slice_copy(cached.as_bytes()));
+      let sym = scope.get_symbol(sym_name).unwrap();
+      let min_sym = minify_symbols
+        .entry(sym)
+        .or_insert_with(|| MinifySymbol::new(session));
+      // The cached name can't be used if this file uses a global with that name, or it'd be shadowed.
+      if minified_inherited_vars.contains(&cached)
+        || KEYWORD_STRS.contains_key(cached.as_slice())
+        || (min_sym.is_used_as_jsx_component && cached.as_slice()[0].is_ascii_lowercase())
+        || !used.insert(cached)
+      {
+        continue;
+      };
+      min_sym.minified_name = Some(cached);
+      cached_symbols.insert(sym);
+    }
+    // Names cached for other symbols, possibly in other files, must not be reused for new symbols.
+    for cached in name_cache.top_level.values() {
+      minified_inherited_vars.insert(SourceRange::from_slice(
+        session.get_allocator().alloc_slice_copy(cached.as_bytes()),
+      ));
+    }
+  };
+  // Variables whose lexical lifetimes don't overlap can share a name, as each one's value is no longer needed by the time the next one is assigned. We assign them to slots greedily in order of their lifetime's start, which uses the fewest slots. Each slot has the end of the last lifetime assigned to it.
+  let mut by_start = session.new_vec();
+  for &sym_name in scope.symbol_names().iter() {
+    let sym = scope.get_symbol(sym_name).unwrap();
+    if let Some(min_sym) = minify_symbols.get(&sym) {
+      if !min_sym.lexical_lifetime_end.is_infinite()
+        && !min_sym.is_used_as_jsx_component
+        && !cached_symbols.contains(&sym)
+      {
+        by_start.push(sym);
+      };
+    };
+  }
+  by_start.sort_by(|a, b| {
+    minify_symbols[a]
+      .lexical_lifetime_start
+      .cmp(&minify_symbols[b].lexical_lifetime_start)
+  });
+  let mut slot_ends: Vec<LexicalLifetime<'a>> = Vec::new();
+  let mut slot_of = HashMap::new();
+  for sym in by_start {
+    let min_sym = minify_symbols.get_mut(&sym).ok_or(MinifyError::Internal(
+      "variable with a lifetime isn't tracked",
+    ))?;
+    match slot_ends
+      .iter()
+      .position(|end| *end < min_sym.lexical_lifetime_start)
+    {
+      Some(slot) => {
+        slot_ends[slot] = min_sym.lexical_lifetime_end.clone();
+        min_sym.reuses_var_slot = true;
+        slot_of.insert(sym, slot);
       }
-      Some(sym) => {
-        let min_sym = minify_symbols.get(&sym).unwrap();
-        let min_name = min_sym.minified_name.unwrap();
-        minified_inherited_vars.insert(min_name);
+      None => {
+        slot_of.insert(sym, slot_ends.len());
+        slot_ends.push(min_sym.lexical_lifetime_end.clone());
       }
     };
   }
-  // Yes, we start from the very beginning in case there are possible gaps/opportunities due to inherited variables on ancestors.
-  let mut next_min_name = MinifiedNameGenerator::new(session);
+  // The most used symbols are named first, so they get the shortest names. Symbols that share a slot share a name, so their usages (and declarations) are counted together. Ties keep declaration order.
+  let mut groups: Vec<(usize, Vec<Symbol>)> = Vec::new();
+  let mut slot_groups = vec![None; slot_ends.len()];
   for &sym_name in scope.symbol_names().iter() {
     let sym = scope.get_symbol(sym_name).unwrap();
+    if cached_symbols.contains(&sym) {
+      continue;
+    };
     let min_sym = minify_symbols
       .entry(sym)
       .or_insert_with(|| MinifySymbol::new(session));
-    assert!(min_sym.minified_name.is_none());
+    if min_sym.minified_name.is_some() {
+      return Err(MinifyError::Internal("variable was named more than once"));
+    };
     if min_sym.is_used_as_jsx_component {
       // We'll process these in another iteration, as there's fewer characters allowed for the identifier start, and we don't want to skip past valid identifiers for non-JSX-component names.
       continue;
     };
-    min_sym.minified_name =
-      Some(next_min_name.generate_next_available_minified_name(&minified_inherited_vars));
+    let i = match slot_of.get(&sym) {
+      Some(&slot) => *slot_groups[slot].get_or_insert(groups.len()),
+      None => groups.len(),
+    };
+    if i == groups.len() {
+      groups.push((0, Vec::new()));
+    };
+    groups[i].0 += min_sym.usage_count + 1;
+    groups[i].1.push(sym);
+  }
+  groups.sort_by_key(|(usages, _)| std::cmp::Reverse(*usages));
+  // Yes, we start from the very beginning in case there are possible gaps/opportunities due to inherited variables on ancestors.
+  let mut next_min_name = MinifiedNameGenerator::new(session, alphabet, &minified_inherited_vars);
+  for (_, syms) in groups {
+    let min_name = next_min_name.generate_next_available_minified_name();
+    for sym in syms {
+      minify_symbols
+        .get_mut(&sym)
+        .ok_or(MinifyError::Internal("variable to name isn't tracked"))?
+        .minified_name = Some(min_name);
+    }
   }
   for &sym_name in scope.symbol_names().iter() {
     let sym = scope.get_symbol(sym_name).unwrap();
-    let min_sym = minify_symbols.get_mut(&sym).unwrap();
-    if !min_sym.is_used_as_jsx_component {
+    let min_sym = minify_symbols
+      .get_mut(&sym)
+      .ok_or(MinifyError::Internal("variable to name isn't tracked"))?;
+    if !min_sym.is_used_as_jsx_component || cached_symbols.contains(&sym) {
       continue;
     };
-    // TODO This is very slow and dumb.
-    let mut min_name;
-    loop {
-      min_name = next_min_name.generate_next_available_minified_name(&minified_inherited_vars);
-      if !min_name.as_slice()[0].is_ascii_lowercase() {
-        break;
-      };
-    }
+    let min_name = next_min_name.generate_next_available_jsx_component_name();
     min_sym.minified_name = Some(min_name)
   }
   for &c in scope.children().iter() {
-    minify_names(session, c, minify_scopes, minify_symbols);
+    minify_names(
+      session,
+      options,
+      alphabet,
+      c,
+      minify_scopes,
+      minify_symbols,
+      None,
+    )?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::MinifiedNameGenerator;
+  use super::NameAlphabet;
+  use parse_js::session::Session;
+  use parse_js::source::SourceRange;
+
+  fn generate(
+    session: &Session,
+    blocked: &[&'static str],
+    jsx_from: usize,
+    count: usize,
+  ) -> Vec<String> {
+    let mut inherited_vars = session.new_hashset();
+    for name in blocked {
+      inherited_vars.insert(SourceRange::from_slice(name.as_bytes()));
+    }
+    let mut generator =
+      MinifiedNameGenerator::new(session, NameAlphabet::default(), &inherited_vars);
+    (0..count)
+      .map(|i| {
+        let name = if i < jsx_from {
+          generator.generate_next_available_minified_name()
+        } else {
+          generator.generate_next_available_jsx_component_name()
+        };
+        name.as_str().to_string()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_generate_next_available_minified_name() {
+    let session = Session::new();
+    let names = generate(&session, &["b", "c", "$", "a0", "averyverylongname"], 600, 600);
+    assert_eq!(&names[..3], &["a", "d", "e"]);
+    // After one-character names come two-character names, skipping keywords.
+    assert_eq!(&names[50..53], &["_", "a1", "a2"]);
+    for keyword in ["do", "if", "in"] {
+      assert!(!names.iter().any(|n| n == keyword));
+    }
+    assert!(names.iter().any(|n| n == "dp"));
+  }
+
+  #[test]
+  fn test_generate_next_available_jsx_component_name() {
+    let session = Session::new();
+    assert_eq!(generate(&session, &["B"], 1, 4), vec!["a", "A", "C", "D"]);
+    // Two-character names jump straight past those starting with a lowercase letter.
+    let names = generate(&session, &[], 60, 62);
+    assert_eq!(&names[53..56], &["$", "a0", "a1"]);
+    assert_eq!(&names[60..], &["A0", "A1"]);
   }
 }
@@ -1,21 +1,34 @@
 use super::advanced_if::analyse_if_branch;
 use super::advanced_if::process_if_branch;
+use super::ctx::is_strict;
 use super::ctx::Ctx;
 use super::ctx::MinifyScope;
 use super::ctx::MinifySymbol;
+use crate::directive::directive_source;
+use crate::directive::is_use_strict;
+use crate::MinifyError;
+use crate::Warning;
 use parse_js::ast::new_node;
+use parse_js::ast::Node;
 use parse_js::ast::NodeData;
+use parse_js::ast::ObjectMemberType;
 use parse_js::ast::Syntax;
 use parse_js::operator::OperatorName;
 use parse_js::session::Session;
+use parse_js::session::SessionVec;
+use parse_js::symbol::Scope;
 use parse_js::visit::JourneyControls;
 use parse_js::visit::Visitor;
+use std::collections::HashSet;
 use std::str::from_utf8_unchecked;
 
 // - Detect all usages of JSX components, as React determines `<link>` to be the HTML tag and `<Link>` to be the variable `Link` as a component, so we cannot minify `Link` to `link` or `a0` or `bb` (i.e. make capitalised JSX elements uncapitalised).
 // - Find all references of variables so we can determine inherited variables (see `MinifiedNameGenerator` and `MinifyScope`). This is because JS allows variables to be lexically referenced before they're used, so we cannot do this in the same pass. For example, `let b = 1; { let a = () => b; let b = 2; }`.
 // - Find uses of `new <var>` and set `is_used_as_constructor`.
 // - Find uses of `<var>.prototype` and set `has_prototype`.
+// - Find direct calls to `eval` and set `has_direct_eval` on the scopes they can access, with a warning. `with` statements would need the same, as they can also access variables by name at runtime, but the parser doesn't support them, so they never get here.
+// - Find the directives (e.g. `"use strict"`) at the start of each function and the script, removing duplicates and `"use strict"` where the code is already strict, and set `has_use_strict`.
+// - Remove expression statements of only a string literal that aren't directives, as they could become directives if they end up at the start of a function (e.g. after unwrapping blocks or folding `"use " + "strict"`).
 // - Combine consecutive expression statements into one.
 // - Convert `if (x) { expr; }` to `x && expr`.
 // - Convert `if (x) { expr1; } else { expr2; }` to `x ? expr1 : expr2`.
@@ -25,6 +38,41 @@ use std::str::from_utf8_unchecked;
 // - Normalise `if-else` branches into block statements.
 pub struct Pass1<'a, 'b> {
   pub ctx: Ctx<'a, 'b>,
+  // Start offsets of the IdentifierPattern nodes that declare a variable or parameter, which we reach before visiting them, so they aren't tracked as usages.
+  pub declaration_patterns: HashSet<usize>,
+  // Start offsets of the directive statements that are kept.
+  pub directives: HashSet<usize>,
+  // How many classes we're currently in, as all code in a class is strict.
+  pub class_depth: usize,
+  pub warnings: Vec<Warning>,
+  // The first internal error, which stops minification once this pass is done, as the tree may have been left half transformed.
+  pub error: Option<MinifyError<'a>>,
+}
+
+fn collect_declaration_patterns<'a>(out: &mut HashSet<usize>, n: &NodeData<'a>) -> () {
+  match &n.stx {
+    Syntax::ArrayPattern { elements, rest } => {
+      for e in elements.iter().flatten() {
+        collect_declaration_patterns(out, e.target);
+      }
+      if let Some(rest) = rest {
+        collect_declaration_patterns(out, rest);
+      }
+    }
+    Syntax::ObjectPattern { properties, rest } => {
+      for p in properties {
+        collect_declaration_patterns(out, p);
+      }
+      if let Some(rest) = rest {
+        collect_declaration_patterns(out, rest);
+      }
+    }
+    Syntax::ObjectPatternProperty { target, .. } => collect_declaration_patterns(out, target),
+    Syntax::IdentifierPattern { .. } => {
+      out.insert(n.loc.start());
+    }
+    _ => {}
+  };
 }
 
 fn stmt_has_return<'a>(stx: &Syntax<'a>) -> bool {
@@ -113,6 +161,7 @@ mod tests {
   use crate::minify::ctx::MinifySymbol;
   use crate::minify::pass1::maybe_combine_string_literals;
   use crate::minify::pass1::maybe_ensure_if_statement_consequent_and_alternate_are_wrapped;
+  use crate::MinifyOptions;
   use parse_js::ast::NodeData;
   use parse_js::ast::Syntax;
   use parse_js::parse::toplevel::TopLevelMode;
@@ -129,7 +178,9 @@ mod tests {
       let $n = parse_js::parse(&session, $source.as_bytes(), TopLevelMode::Global).unwrap();
       let mut symbols = session.new_hashmap::<Symbol, MinifySymbol>();
       let mut scopes = session.new_hashmap::<Scope<'_>, MinifyScope<'_>>();
+      let options = MinifyOptions::default();
       let $ctx = Ctx {
+        options: &options,
         scopes: &mut scopes,
         session: &session,
         symbols: &mut symbols,
@@ -186,18 +237,65 @@ mod tests {
 
     setup!(n, ctx, "'a' + 'b';");
     P { ctx }.visit(n);
-    check!(n, "`ab`");
+    check!(n, "\"ab\"");
 
     setup!(n, ctx, "'a' + 'b' + 'c';");
     P { ctx }.visit(n);
-    check!(n, "`abc`");
+    check!(n, "\"abc\"");
+  }
+}
+
+impl<'a, 'b> Pass1<'a, 'b> {
+  // `scope` is the closure or global scope that `body` is the code of. Enclosing code is visited first, so whether it's strict is already known.
+  fn process_directive_prologue(&mut self, scope: Scope<'a>, body: &mut SessionVec<'a, Node<'a>>) {
+    let mut seen = HashSet::new();
+    let mut i = 0;
+    while let Some(raw) = body.get(i).and_then(|stmt| directive_source(stmt)) {
+      let raw = raw.as_slice();
+      // `"use client"` and `'use client'` are the same directive.
+      let redundant = !seen.insert(raw[1..raw.len() - 1].to_vec())
+        || is_use_strict(raw) && (self.class_depth > 0 || is_strict(self.ctx.scopes, scope));
+      if redundant {
+        body.remove(i);
+        continue;
+      };
+      if is_use_strict(raw) {
+        self
+          .ctx
+          .scopes
+          .entry(scope)
+          .or_insert_with(|| MinifyScope::new(self.ctx.session))
+          .has_use_strict = true;
+      };
+      self.directives.insert(body[i].loc.start());
+      i += 1;
+    }
+  }
+
+  fn is_removable_string_stmt(&self, stmt: &NodeData<'a>) -> bool {
+    match &stmt.stx {
+      Syntax::ExpressionStmt {
+        expression:
+          NodeData {
+            stx: Syntax::LiteralStringExpr { .. },
+            ..
+          },
+      } => !self.directives.contains(&stmt.loc.start()),
+      _ => false,
+    }
   }
 }
 
 impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
   fn on_syntax_down(&mut self, n: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
     let scope = n.scope;
+    let loc = n.loc;
     maybe_ensure_if_statement_consequent_and_alternate_are_wrapped(&mut self.ctx, n);
+    if let Syntax::TopLevel { body } | Syntax::BlockStmt { body } = &mut n.stx {
+      if scope.typ().is_closure_or_global() {
+        self.process_directive_prologue(scope, body);
+      };
+    };
     match &mut n.stx {
       Syntax::BlockStmt { body } => {
         let mut i = 0;
@@ -236,12 +334,48 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
           i += 1;
         }
       }
+      Syntax::ClassDecl { .. } | Syntax::ClassExpr { .. } => {
+        self.class_depth += 1;
+      }
       Syntax::IdentifierExpr { name } => {
         self.ctx.track_variable_usage(scope, *name);
       }
-      // IdentifierPattern also appears in destructuring, not just declarations. It's safe either way; if it's a declaration, its scope will have its declaration, so there will be no inheritance.
+      // The visitor doesn't descend into shorthand members, but their identifier is still a usage (e.g. `{a}`).
+      Syntax::ObjectMember {
+        typ: ObjectMemberType::Shorthand { identifier },
+      } => {
+        self.ctx.track_variable_usage(scope, identifier.loc);
+      }
+      Syntax::VarDecl { declarators, .. } => {
+        for decl in declarators.iter() {
+          collect_declaration_patterns(&mut self.declaration_patterns, decl.pattern);
+        }
+      }
+      Syntax::ParamDecl { pattern, .. } => {
+        collect_declaration_patterns(&mut self.declaration_patterns, pattern);
+      }
+      // IdentifierPattern also appears in destructuring assignments, not just declarations, where it's a usage.
       Syntax::IdentifierPattern { name } => {
-        self.ctx.track_variable_usage(scope, *name);
+        if self.declaration_patterns.remove(&n.loc.start()) {
+          self.ctx.track_variable_declaration(scope, *name);
+        } else {
+          self.ctx.track_variable_usage(scope, *name);
+        };
+      }
+      // A call is only a direct eval if `eval` is the global (e.g. not `eval?.(x)` or `(0, eval)(x)`).
+      Syntax::CallExpr {
+        optional_chaining: false,
+        callee:
+          NodeData {
+            stx: Syntax::IdentifierExpr { name },
+            ..
+          },
+        ..
+      } if name.as_slice() == b"eval" && scope.find_symbol(*name).is_none() => {
+        self.ctx.track_direct_eval(scope);
+        self.warnings.push(Warning::DirectEval {
+          position: loc.start(),
+        });
       }
       Syntax::MemberExpr {
         right: p2,
@@ -314,24 +448,21 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
     match &mut node.stx {
       // This is bottom-up as we could remove nested blocks recursively.
       Syntax::BlockStmt { body } => {
-        let mut returned = false;
         // Next writable slot when shifting down due to gaps from deleting merged ExpressionStmt values.
         let mut w = 0;
         // Next readable slot to process.
         let mut r = 0;
         // We can't use a for loop or cache `body.len()` as it might change (e.g. unpacking redundant block statement).
         while r < body.len() {
-          if returned {
-            // Drop remaining unreachable code.
-            // TODO There may be more code outside this block that's now unreachable and can be removed.
-            break;
-          };
           // Get `scope` before we borrow mutably for `stx`.
           let r_scope = body[r].scope;
+          let r_is_removable_string = self.is_removable_string_stmt(body[r]);
           let keep = match &mut body[r].stx {
             // NOTE: We must match here as BlockStmt may not always be a block statement (e.g. `for`, `while`, function bodies).
             Syntax::BlockStmt { body: block_body } => {
-              if block_body.is_empty() {
+              if !self.ctx.options.unwrap_blocks {
+                true
+              } else if block_body.is_empty() {
                 false
               } else if r_scope.symbol_names().is_empty() {
                 // This block statement doesn't have any block-scoped declarations, so it's unnecessary.
@@ -346,14 +477,12 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
                 true
               }
             }
+            Syntax::ExpressionStmt { expression: _ } if r_is_removable_string => false,
             Syntax::ExpressionStmt { expression: _ } => {
               // TODO Remove if pure.
               true
             }
-            Syntax::ReturnStmt { .. } => {
-              returned = true;
-              true
-            }
+            Syntax::DebuggerStmt {} => !self.ctx.options.drop_debugger,
             _ => true,
           };
           if keep {
@@ -364,19 +493,37 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
         }
         body.truncate(w);
       }
+      Syntax::ClassDecl { .. } | Syntax::ClassExpr { .. } => {
+        self.class_depth -= 1;
+      }
+      Syntax::TopLevel { body } => {
+        body.retain(|stmt| !self.is_removable_string_stmt(stmt));
+      }
       Syntax::IfStmt {
         test,
         consequent,
         alternate,
-      } => {
+      } if self.ctx.options.if_to_expression => {
         // Note that we cannot process unless both branches can be processed, otherwise we'll be left with one branch mutated.
-        let cons_ok = analyse_if_branch(&consequent.stx);
-        let alt_ok = alternate.as_ref().map(|alt| analyse_if_branch(&alt.stx));
+        let max_depth = self.ctx.options.max_nesting_depth;
+        let cons_ok = analyse_if_branch(&consequent.stx, max_depth);
+        let alt_ok = alternate
+          .as_ref()
+          .map(|alt| analyse_if_branch(&alt.stx, max_depth));
 
         match (cons_ok, alt_ok) {
           (true, None) => {
-            let closure_scope = scope.find_self_or_ancestor(|t| t.is_closure()).unwrap();
-            let cons_expr = process_if_branch(self.ctx.session, scope, consequent);
+            // The top-level scope is a module or global, so this always finds one.
+            let closure_scope = scope
+              .find_self_or_ancestor(|t| t.is_closure_or_global())
+              .unwrap();
+            let cons_expr = match process_if_branch(self.ctx.session, scope, consequent) {
+              Ok(expr) => expr,
+              Err(err) => {
+                self.error.get_or_insert(err);
+                return;
+              }
+            };
             let min_scope = self
               .ctx
               .scopes
@@ -403,9 +550,18 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
             }
           }
           (true, Some(true)) => {
-            let closure_scope = scope.find_self_or_ancestor(|t| t.is_closure()).unwrap();
+            let closure_scope = scope
+              .find_self_or_ancestor(|t| t.is_closure_or_global())
+              .unwrap();
             let cons_expr = process_if_branch(self.ctx.session, scope, consequent);
             let alt_expr = process_if_branch(self.ctx.session, scope, alternate.as_mut().unwrap());
+            let (cons_expr, alt_expr) = match (cons_expr, alt_expr) {
+              (Ok(cons_expr), Ok(alt_expr)) => (cons_expr, alt_expr),
+              (Err(err), _) | (_, Err(err)) => {
+                self.error.get_or_insert(err);
+                return;
+              }
+            };
             let min_scope = self
               .ctx
               .scopes
@@ -418,7 +574,12 @@ impl<'a, 'b> Visitor<'a> for Pass1<'a, 'b> {
               .hoisted_vars
               .extend_from_slice(&alt_expr.hoisted_vars);
             // Due to normalisation, it's not possible for an `if-else` to return in either branch, because one branch would've been unwrapped.
-            assert!(cons_expr.returns && alt_expr.returns);
+            if cons_expr.returns || alt_expr.returns {
+              self.error.get_or_insert(MinifyError::Internal(
+                "if-else has a branch that returns",
+              ));
+              return;
+            };
             let test = test.take(self.ctx.session);
             let consequent = cons_expr.expression;
             let alternate = alt_expr.expression;
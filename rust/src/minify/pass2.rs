@@ -35,7 +35,6 @@ impl<'a, 'b> Visitor<'a> for Pass2<'a, 'b> {
         .or_insert_with(|| MinifyScope::new(self.ctx.session))
         .hoisted_functions
         .insert(name, n.replace(self.ctx.session, Syntax::EmptyStmt {}));
-      return;
     };
   }
 }
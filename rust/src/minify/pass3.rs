@@ -1,19 +1,26 @@
+use super::ctx::has_direct_eval;
+use super::ctx::uses_this_or_arguments;
 use super::ctx::MinifyScope;
 use super::ctx::MinifySymbol;
+use crate::directive::directive_prologue_len;
+use crate::MinifyError;
+use crate::MinifyOptions;
 use parse_js::ast::new_node;
 use parse_js::ast::ClassOrObjectMemberKey;
 use parse_js::ast::ClassOrObjectMemberValue;
 use parse_js::ast::ExportNames;
+use parse_js::ast::Node;
 use parse_js::ast::NodeData;
 use parse_js::ast::ObjectMemberType;
 use parse_js::ast::Syntax;
 use parse_js::ast::VarDeclMode;
 use parse_js::ast::VariableDeclarator;
+use parse_js::operator::OperatorName;
 use parse_js::session::Session;
 use parse_js::session::SessionHashMap;
+use parse_js::session::SessionVec;
 use parse_js::source::SourceRange;
 use parse_js::symbol::Scope;
-use parse_js::symbol::ScopeFlag;
 use parse_js::symbol::Symbol;
 use parse_js::visit::JourneyControls;
 use parse_js::visit::Visitor;
@@ -32,45 +39,126 @@ fn unwrap_block_statement_if_possible<'a>(session: &'a Session, node: &mut NodeD
   };
 }
 
+// Variables that reuse another variable's slot (see `MinifySymbol::reuses_var_slot`) have the same name, which is already declared, so `var` statements that only declare them can become assignments (e.g. `var b = 2, c` becomes `b = 2`). Declarations in `for` loop headers aren't in a statement list, so they're kept.
+fn replace_redundant_var_decls<'a>(
+  session: &'a Session,
+  symbols: &SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  body: &mut SessionVec<'a, Node<'a>>,
+) -> () {
+  for stmt in body.iter_mut() {
+    let scope = stmt.scope;
+    let loc = stmt.loc;
+    let Syntax::VarDecl {
+      export: false,
+      mode: VarDeclMode::Var,
+      declarators,
+    } = &mut stmt.stx
+    else {
+      continue;
+    };
+    let redundant = declarators.iter().all(|d| match &d.pattern.stx {
+      Syntax::IdentifierPattern { name } => d
+        .pattern
+        .scope
+        .find_symbol(*name)
+        .and_then(|sym| symbols.get(&sym))
+        .is_some_and(|sym| sym.reuses_var_slot),
+      _ => false,
+    });
+    if !redundant {
+      continue;
+    };
+    let mut expression = None;
+    for d in declarators.iter_mut() {
+      let Some(init) = d.initializer.take() else {
+        continue;
+      };
+      let assignment = new_node(
+        session,
+        d.pattern.scope,
+        d.pattern.loc,
+        Syntax::BinaryExpr {
+          parenthesised: false,
+          operator: OperatorName::Assignment,
+          left: d.pattern.take(session),
+          right: init,
+        },
+      );
+      expression = Some(match expression {
+        None => assignment,
+        Some(left) => new_node(session, scope, loc, Syntax::BinaryExpr {
+          parenthesised: false,
+          operator: OperatorName::Comma,
+          left,
+          right: assignment,
+        }),
+      });
+    }
+    stmt.stx = match expression {
+      Some(expression) => Syntax::ExpressionStmt { expression },
+      None => Syntax::EmptyStmt {},
+    };
+  }
+}
+
 // This should be run after the `minify_names` function.
 pub struct Pass3<'a, 'b> {
   pub session: &'a Session,
-  // Exports with the same exported name (including multiple default exports) are illegal, so we don't have to worry about/handle that case.
+  pub options: &'b MinifyOptions,
+  // Exports with the same exported name (including multiple default exports) are illegal, and EarlyErrors has already rejected them, so we don't have to worry about/handle that case.
   pub export_bindings: &'b mut Vec<ExportBinding<'a>>,
   pub symbols: &'b mut SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
   pub scopes: &'b mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  // The first internal error, which stops minification once this pass is done, as the tree may have been left half transformed.
+  pub error: Option<MinifyError<'a>>,
 }
 
 impl<'a, 'b> Pass3<'a, 'b> {
-  fn visit_exported_pattern(&mut self, n: &mut NodeData<'a>) -> () {
+  fn visit_exported_pattern(&mut self, n: &mut NodeData<'a>) -> Result<(), MinifyError<'a>> {
     match &mut n.stx {
       Syntax::ArrayPattern { elements, rest } => {
         for e in elements {
           if let Some(e) = e {
-            self.visit_exported_pattern(e.target);
+            self.visit_exported_pattern(e.target)?;
           }
         }
         if let Some(rest) = rest {
-          self.visit_exported_pattern(*rest);
+          self.visit_exported_pattern(*rest)?;
         }
       }
       Syntax::ObjectPattern { properties, rest } => {
         for p in properties {
-          self.visit_exported_pattern(*p);
+          self.visit_exported_pattern(*p)?;
         }
         if let Some(rest) = rest {
-          self.visit_exported_pattern(*rest);
+          self.visit_exported_pattern(*rest)?;
         }
       }
       Syntax::ObjectPatternProperty { target, .. } => {
-        self.visit_exported_pattern(*target);
+        self.visit_exported_pattern(*target)?;
       }
       Syntax::IdentifierPattern { name } => self.export_bindings.push(ExportBinding {
         target: *name,
         alias: *name,
       }),
-      _ => unreachable!(),
-    }
+      _ => {
+        return Err(MinifyError::Internal(
+          "exported declaration has an unexpected pattern",
+        ))
+      }
+    };
+    Ok(())
+  }
+
+  // Returns the minified name of a declared variable, which `minify_names` has chosen for every one, or records an internal error if it hasn't.
+  fn minified_name(&mut self, sym: Symbol) -> Option<SourceRange<'a>> {
+    let minified = self.symbols.get(&sym).and_then(|s| s.minified_name);
+    if minified.is_none() {
+      self
+        .error
+        .get_or_insert(MinifyError::Internal("variable has no minified name"));
+    };
+    minified
   }
 }
 
@@ -80,14 +168,19 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
     let loc = node.loc;
     let scope = node.scope;
     let mut new_stx: Option<Syntax<'_>> = None;
+    if let Syntax::BlockStmt { body } | Syntax::SwitchBranch { body, .. } = &mut node.stx {
+      replace_redundant_var_decls(self.session, self.symbols, body);
+    };
     match &mut node.stx {
       Syntax::TopLevel { body } | Syntax::BlockStmt { body } => {
         // TODO Are all global/closure scopes associated with exactly one BlockStmt or TopLevel?
         if scope.typ().is_closure_or_global() {
           if let Some(min_scope) = self.scopes.get_mut(&scope) {
+            // Declarations must come after the directives (e.g. `"use strict"`), or they'd no longer be directives.
+            let start = directive_prologue_len(body);
             if !min_scope.hoisted_vars.is_empty() {
               body.insert(
-                0,
+                start,
                 new_node(self.session, scope, loc, Syntax::VarDecl {
                   export: false,
                   mode: VarDeclMode::Var,
@@ -106,10 +199,14 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
                 }),
               );
             }
-            for fn_decl in min_scope.hoisted_functions.values_mut() {
-              // TODO Batch prepend to avoid repeated Vec shifting.
-              body.insert(0, fn_decl.take(self.session));
-            }
+            // Map order depends on hashing, so sort them back into source order to keep the output the same on every run.
+            let mut fn_decls: Vec<_> = min_scope
+              .hoisted_functions
+              .values_mut()
+              .map(|fn_decl| fn_decl.take(self.session))
+              .collect();
+            fn_decls.sort_by_key(|fn_decl| fn_decl.loc.start());
+            body.splice(start..start, fn_decls);
           };
         };
       }
@@ -141,16 +238,16 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
         name: None,
         signature,
         body,
-      } => {
+      } if self.options.arrow_functions => {
         let fn_scope = body.scope;
         // TODO This will still work for named functions as long as that name isn't used (including if it's shadowed).
         // TODO Detect property access of "prototype" on variable referencing function to reduce (but not remove) false negatives.
         // TODO Can this work sometimes even when `arguments` is used?
         // TODO This is still not risk-free, as the function's prototype could still be used even if there is no `this`.
         // TODO Detect `function(){}.bind(this)`, which is pretty much risk free unless somehow Function.prototype.bind has been overridden. However, any other value for the first argument of `.bind` means that it is no longer safe.
-        if !fn_scope
-          .flags()
-          .has_any(ScopeFlag::UsesArguments | ScopeFlag::UsesThis)
+        // A direct `eval` could use `this` or `arguments` too.
+        if !uses_this_or_arguments(fn_scope)
+          && !has_direct_eval(self.scopes, fn_scope)
         {
           new_stx = Some(Syntax::ArrowFunctionExpr {
             // TODO
@@ -169,14 +266,15 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
         name: Some(name),
         signature,
         ..
-      } => {
+      } if self.options.arrow_functions => {
         let fn_scope = body.scope;
         // TODO Consider `export function` and `export default function`.
         // TODO Detect property access of "prototype" on variable referencing function to reduce (but not remove) false negatives.
         // TODO Can this work sometimes even when `arguments` is used?
         // TODO This is still not risk-free, as the function's prototype could still be used even if there is no `this`.
         // TODO Detect `function(){}.bind(this)`, which is pretty much risk free unless somehow Function.prototype.bind has been overridden. However, any other value for the first argument of `.bind` means that it is no longer safe.
-        if !fn_scope.flags().has_any(ScopeFlag::UsesArguments | ScopeFlag::UsesThis)
+        if !uses_this_or_arguments(fn_scope)
+          && !has_direct_eval(self.scopes, fn_scope)
           // Use `find_symbol` as we might not be in a closure scope and the function declaration's symbol would've been added to an ancestor.
           // If no symbol is found (e.g. global), or it exists but is not `is_used_as_constructor` and not `has_prototype`, then we can safely proceed.
           && scope.find_symbol(name.loc).and_then(|sym| self.symbols.get(&sym)).filter(|sym| sym.is_used_as_constructor || sym.has_prototype).is_none()
@@ -220,22 +318,19 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
       }
       Syntax::IdentifierPattern { name } => {
         let sym = scope.find_symbol(*name);
-        if let Some(sym) = sym {
-          let minified = self.symbols[&sym].minified_name.unwrap();
+        if let Some(minified) = sym.and_then(|sym| self.minified_name(sym)) {
           new_stx = Some(Syntax::IdentifierPattern { name: minified });
         };
       }
       Syntax::IdentifierExpr { name } => {
         let sym = scope.find_symbol(*name);
-        if let Some(sym) = sym {
-          let minified = self.symbols[&sym].minified_name.unwrap();
+        if let Some(minified) = sym.and_then(|sym| self.minified_name(sym)) {
           new_stx = Some(Syntax::IdentifierExpr { name: minified });
         };
       }
       Syntax::ClassOrFunctionName { name } => {
         let sym = scope.find_symbol(*name);
-        if let Some(sym) = sym {
-          let minified = self.symbols[&sym].minified_name.unwrap();
+        if let Some(minified) = sym.and_then(|sym| self.minified_name(sym)) {
           new_stx = Some(Syntax::ClassOrFunctionName { name: minified });
         };
       }
@@ -263,7 +358,11 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
                 },
               });
             }
-            _ => unreachable!(),
+            _ => {
+              self.error.get_or_insert(MinifyError::Internal(
+                "exported declaration's name isn't a ClassOrFunctionName",
+              ));
+            }
           };
         }
       }
@@ -273,7 +372,9 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
         ..
       } => {
         for decl in declarators.iter_mut() {
-          self.visit_exported_pattern(decl.pattern);
+          if let Err(err) = self.visit_exported_pattern(decl.pattern) {
+            self.error.get_or_insert(err);
+          };
         }
       }
       Syntax::ExportListStmt { names, from } => {
@@ -282,17 +383,25 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
           None => match names {
             ExportNames::Specific(names) => {
               for e in names {
+                let Syntax::IdentifierPattern { name: alias } = &e.alias.stx else {
+                  self.error.get_or_insert(MinifyError::Internal(
+                    "export alias isn't an IdentifierPattern",
+                  ));
+                  continue;
+                };
                 self.export_bindings.push(ExportBinding {
                   target: e.target,
-                  alias: match &e.alias.stx {
-                    Syntax::IdentifierPattern { name } => *name,
-                    _ => unreachable!(),
-                  },
+                  alias: *alias,
                 });
                 new_stx = Some(Syntax::EmptyStmt {});
               }
             }
-            ExportNames::All(_) => unreachable!(),
+            // The parser only allows `export *` with `from`.
+            ExportNames::All(_) => {
+              self.error.get_or_insert(MinifyError::Internal(
+                "`export *` has no module to export from",
+              ));
+            }
           },
           // `export ... from ...` do not touch/alter the module's scope, so we can ignore completely.
           _ => {}
@@ -341,6 +450,9 @@ impl<'a, 'b> Visitor<'a> for Pass3<'a, 'b> {
   }
 
   fn on_syntax_up(&mut self, node: &mut NodeData<'a>) -> () {
+    if !self.options.unwrap_blocks {
+      return;
+    };
     match &mut node.stx {
       Syntax::IfStmt {
         consequent,
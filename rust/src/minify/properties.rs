@@ -0,0 +1,574 @@
+use super::name::MinifiedNameGenerator;
+use super::name::NameAlphabet;
+use crate::name_cache;
+use crate::PropertyPattern;
+use lazy_static::lazy_static;
+use parse_js::ast::ClassOrObjectMemberKey;
+use parse_js::ast::ClassOrObjectMemberValue;
+use parse_js::ast::LiteralTemplatePart;
+use parse_js::ast::NodeData;
+use parse_js::ast::ObjectMemberType;
+use parse_js::ast::Syntax;
+use parse_js::session::Session;
+use parse_js::source::SourceRange;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::str::from_utf8;
+
+// Property names defined by ECMAScript and commonly used DOM and Node.js APIs, which must never be mangled even if they match, and which mangled names must not collide with.
+const RESERVED_PROPERTIES: &[&str] = &[
+  // Object and Function.
+  "__defineGetter__",
+  "__defineSetter__",
+  "__lookupGetter__",
+  "__lookupSetter__",
+  "__proto__",
+  "apply",
+  "arguments",
+  "assign",
+  "bind",
+  "call",
+  "caller",
+  "constructor",
+  "create",
+  "defineProperties",
+  "defineProperty",
+  "entries",
+  "freeze",
+  "fromEntries",
+  "getOwnPropertyDescriptor",
+  "getOwnPropertyDescriptors",
+  "getOwnPropertyNames",
+  "getOwnPropertySymbols",
+  "getPrototypeOf",
+  "groupBy",
+  "hasOwn",
+  "hasOwnProperty",
+  "is",
+  "isExtensible",
+  "isFrozen",
+  "isPrototypeOf",
+  "isSealed",
+  "keys",
+  "length",
+  "name",
+  "preventExtensions",
+  "propertyIsEnumerable",
+  "prototype",
+  "seal",
+  "setPrototypeOf",
+  "toLocaleString",
+  "toString",
+  "valueOf",
+  "values",
+  // Array, TypedArray, and String.
+  "at",
+  "buffer",
+  "byteLength",
+  "byteOffset",
+  "charAt",
+  "charCodeAt",
+  "codePointAt",
+  "concat",
+  "copyWithin",
+  "endsWith",
+  "every",
+  "fill",
+  "filter",
+  "find",
+  "findIndex",
+  "findLast",
+  "findLastIndex",
+  "flat",
+  "flatMap",
+  "forEach",
+  "from",
+  "fromCharCode",
+  "fromCodePoint",
+  "includes",
+  "indexOf",
+  "isArray",
+  "join",
+  "lastIndexOf",
+  "localeCompare",
+  "map",
+  "match",
+  "matchAll",
+  "normalize",
+  "of",
+  "padEnd",
+  "padStart",
+  "pop",
+  "push",
+  "raw",
+  "reduce",
+  "reduceRight",
+  "repeat",
+  "replace",
+  "replaceAll",
+  "reverse",
+  "search",
+  "set",
+  "shift",
+  "slice",
+  "some",
+  "sort",
+  "splice",
+  "split",
+  "startsWith",
+  "subarray",
+  "substr",
+  "substring",
+  "toLowerCase",
+  "toReversed",
+  "toSorted",
+  "toSpliced",
+  "toUpperCase",
+  "trim",
+  "trimEnd",
+  "trimStart",
+  "unshift",
+  "with",
+  // Number, Math, Date, JSON, and RegExp.
+  "E",
+  "EPSILON",
+  "MAX_SAFE_INTEGER",
+  "MAX_VALUE",
+  "MIN_SAFE_INTEGER",
+  "MIN_VALUE",
+  "NaN",
+  "PI",
+  "abs",
+  "ceil",
+  "cos",
+  "exec",
+  "exp",
+  "flags",
+  "floor",
+  "getDate",
+  "getDay",
+  "getFullYear",
+  "getHours",
+  "getMilliseconds",
+  "getMinutes",
+  "getMonth",
+  "getSeconds",
+  "getTime",
+  "getTimezoneOffset",
+  "global",
+  "hypot",
+  "ignoreCase",
+  "index",
+  "input",
+  "isFinite",
+  "isInteger",
+  "isNaN",
+  "lastIndex",
+  "log",
+  "max",
+  "min",
+  "multiline",
+  "now",
+  "parse",
+  "parseFloat",
+  "parseInt",
+  "pow",
+  "random",
+  "round",
+  "sign",
+  "sin",
+  "source",
+  "sqrt",
+  "stringify",
+  "sticky",
+  "tan",
+  "test",
+  "toDateString",
+  "toExponential",
+  "toFixed",
+  "toISOString",
+  "toJSON",
+  "toPrecision",
+  "toTimeString",
+  "toUTCString",
+  "trunc",
+  "unicode",
+  "UTC",
+  // Map, Set, WeakMap, Symbol, Promise, Proxy, Reflect, and iterators.
+  "add",
+  "all",
+  "allSettled",
+  "any",
+  "asyncIterator",
+  "catch",
+  "clear",
+  "construct",
+  "delete",
+  "deleteProperty",
+  "description",
+  "done",
+  "finally",
+  "for",
+  "get",
+  "has",
+  "hasInstance",
+  "iterator",
+  "next",
+  "ownKeys",
+  "race",
+  "reject",
+  "resolve",
+  "return",
+  "size",
+  "species",
+  "then",
+  "throw",
+  "toPrimitive",
+  "toStringTag",
+  "value",
+  "withResolvers",
+  // Errors and the console.
+  "cause",
+  "error",
+  "errors",
+  "info",
+  "message",
+  "stack",
+  "table",
+  "trace",
+  "warn",
+  // DOM.
+  "addEventListener",
+  "append",
+  "appendChild",
+  "body",
+  "checked",
+  "childNodes",
+  "children",
+  "classList",
+  "className",
+  "click",
+  "cloneNode",
+  "closest",
+  "contains",
+  "createElement",
+  "createTextNode",
+  "currentTarget",
+  "data",
+  "dataset",
+  "detail",
+  "dispatchEvent",
+  "document",
+  "documentElement",
+  "firstChild",
+  "focus",
+  "getAttribute",
+  "getBoundingClientRect",
+  "getElementById",
+  "getElementsByClassName",
+  "getElementsByTagName",
+  "hasAttribute",
+  "head",
+  "height",
+  "hidden",
+  "href",
+  "id",
+  "innerHTML",
+  "innerText",
+  "insertBefore",
+  "key",
+  "lastChild",
+  "left",
+  "location",
+  "nextSibling",
+  "nodeName",
+  "nodeType",
+  "onclick",
+  "onload",
+  "parentElement",
+  "parentNode",
+  "preventDefault",
+  "previousSibling",
+  "querySelector",
+  "querySelectorAll",
+  "remove",
+  "removeAttribute",
+  "removeChild",
+  "removeEventListener",
+  "replaceChild",
+  "requestAnimationFrame",
+  "setAttribute",
+  "setTimeout",
+  "src",
+  "stopPropagation",
+  "style",
+  "tagName",
+  "target",
+  "textContent",
+  "top",
+  "type",
+  "width",
+  "window",
+  "x",
+  "y",
+  // Modules and Node.js.
+  "default",
+  "exports",
+  "module",
+  "require",
+];
+
+lazy_static! {
+  static ref RESERVED_PROPERTY_SET: HashSet<&'static [u8]> =
+    RESERVED_PROPERTIES.iter().map(|p| p.as_bytes()).collect();
+}
+
+// Only names written as identifiers are mangled; quoted and numeric keys, and private names, are left alone.
+fn is_identifier_name(name: &[u8]) -> bool {
+  name
+    .first()
+    .is_some_and(|c| !matches!(c, b'0'..=b'9' | b'"' | b'\'' | b'#' | b'.'))
+}
+
+// The name a quoted key refers to, without its quotes. Escapes aren't decoded, which is consistent with how the parser reads string literals.
+fn unquote<'a>(session: &'a Session, name: SourceRange<'a>) -> SourceRange<'a> {
+  match name.as_slice() {
+    [q @ (b'"' | b'\''), inner @ .., end] if end == q => {
+      SourceRange::from_slice(session.get_allocator().alloc_slice_copy(inner))
+    }
+    _ => name,
+  }
+}
+
+// Finds every property name in the file, in order of first appearance, so we know which to mangle and which names are already taken.
+struct Collect<'a> {
+  session: &'a Session,
+  names: Vec<SourceRange<'a>>,
+  seen: HashSet<SourceRange<'a>>,
+  // Every string in the file, unquoted. Any of them could be used to access a property (e.g. `o["a"]`, `"a" in o`, or `o.hasOwnProperty("a")`), so properties with these names must not be mangled.
+  strings: HashSet<SourceRange<'a>>,
+}
+
+impl<'a> Collect<'a> {
+  fn add(&mut self, name: SourceRange<'a>) -> () {
+    if self.seen.insert(name) {
+      self.names.push(name);
+    };
+  }
+}
+
+// Renames the properties found by `Collect`.
+struct Rename<'a> {
+  session: &'a Session,
+  renamed: HashMap<SourceRange<'a>, SourceRange<'a>>,
+}
+
+// Both passes see the same property names through these, so they can't disagree on what's a property.
+trait PropertyVisitor<'a> {
+  // Returns whether the name was changed.
+  fn on_property(&mut self, name: &mut SourceRange<'a>) -> bool;
+
+  // Shorthands are only expanded if the property is renamed, which requires the session.
+  fn on_shorthand_member(&mut self, node: &mut NodeData<'a>) -> ();
+
+  // Strings aren't renamed, but properties accessed through them must not be either.
+  fn on_string(&mut self, _value: &'a str) -> () {}
+
+  fn on_node(&mut self, n: &mut NodeData<'a>) -> () {
+    match &mut n.stx {
+      Syntax::LiteralStringExpr { value } => self.on_string(value),
+      Syntax::LiteralTemplateExpr { parts } => {
+        for part in parts.iter() {
+          if let LiteralTemplatePart::String(value) = part {
+            self.on_string(value);
+          };
+        }
+      }
+      Syntax::MemberExpr { right, .. } => {
+        self.on_property(right);
+      }
+      Syntax::ObjectPatternProperty {
+        key: ClassOrObjectMemberKey::Direct(name),
+        shorthand,
+        ..
+      } => {
+        // Pass3 has already expanded the shorthand if the target is a declared variable, but not if it's a global.
+        *shorthand &= !self.on_property(name);
+      }
+      Syntax::ObjectMember {
+        typ: ObjectMemberType::Shorthand { .. },
+      } => self.on_shorthand_member(n),
+      _ => {}
+    };
+  }
+
+  fn on_key(&mut self, key: &mut ClassOrObjectMemberKey<'a>) -> () {
+    if let ClassOrObjectMemberKey::Direct(name) = key {
+      self.on_property(name);
+    };
+  }
+}
+
+impl<'a> PropertyVisitor<'a> for Collect<'a> {
+  fn on_property(&mut self, name: &mut SourceRange<'a>) -> bool {
+    let unquoted = unquote(self.session, *name);
+    if unquoted != *name {
+      self.strings.insert(unquoted);
+    };
+    self.add(*name);
+    false
+  }
+
+  fn on_shorthand_member(&mut self, node: &mut NodeData<'a>) -> () {
+    if let Syntax::ObjectMember {
+      typ: ObjectMemberType::Shorthand { identifier },
+    } = &node.stx
+    {
+      self.add(identifier.loc);
+    };
+  }
+
+  fn on_string(&mut self, value: &'a str) -> () {
+    self
+      .strings
+      .insert(SourceRange::from_slice(value.as_bytes()));
+  }
+}
+
+impl<'a> PropertyVisitor<'a> for Rename<'a> {
+  fn on_property(&mut self, name: &mut SourceRange<'a>) -> bool {
+    let Some(renamed) = self.renamed.get(name) else {
+      return false;
+    };
+    *name = *renamed;
+    true
+  }
+
+  fn on_shorthand_member(&mut self, node: &mut NodeData<'a>) -> () {
+    let Syntax::ObjectMember {
+      typ: ObjectMemberType::Shorthand { identifier },
+    } = &mut node.stx
+    else {
+      return;
+    };
+    let Some(renamed) = self.renamed.get(&identifier.loc) else {
+      return;
+    };
+    let initializer = identifier.take(self.session);
+    node.stx = Syntax::ObjectMember {
+      typ: ObjectMemberType::Valued {
+        key: ClassOrObjectMemberKey::Direct(*renamed),
+        value: ClassOrObjectMemberValue::Property {
+          initializer: Some(initializer),
+        },
+      },
+    };
+  }
+}
+
+// The Visitor trait can't be implemented generically for both, as it's a foreign trait.
+macro_rules! impl_visitor {
+  ($t:ident) => {
+    impl<'a> Visitor<'a> for $t<'a> {
+      fn on_syntax_down(&mut self, n: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+        self.on_node(n);
+      }
+
+      fn visit_class_or_object_key(&mut self, key: &mut ClassOrObjectMemberKey<'a>) -> () {
+        self.on_key(key);
+        if let ClassOrObjectMemberKey::Computed(key) = key {
+          self.visit(*key);
+        };
+      }
+    }
+  };
+}
+
+impl_visitor!(Collect);
+impl_visitor!(Rename);
+
+// Renames properties whose names match `pattern` to short names, which are the same everywhere in the file: member accesses (`a._b`), destructuring, and object literal and class member keys. Names that also appear in a string anywhere in the file (e.g. a quoted key, `a["_b"]`, or `"_b" in a`) aren't renamed, as the property could be accessed through it, nor are numeric names or names in `RESERVED_PROPERTIES`. Properties accessed by other files must not match `pattern`.
+// If `cache` is provided, names in it are reused where possible and are never chosen for other properties, and new names are added to it.
+// This runs after Pass3, when variables have been renamed and any shorthands referring to them have been expanded.
+pub fn mangle_properties<'a>(
+  session: &'a Session,
+  pattern: &PropertyPattern,
+  alphabet: NameAlphabet<'a>,
+  top_level_node: &mut NodeData<'a>,
+  mut cache: Option<&mut BTreeMap<String, String>>,
+) -> () {
+  let mut collect = Collect {
+    session,
+    names: Vec::new(),
+    seen: HashSet::new(),
+    strings: HashSet::new(),
+  };
+  collect.visit(top_level_node);
+
+  let mut to_mangle = Vec::new();
+  // Mangled names must not collide with names that aren't mangled, which could be on the same object. They also don't reuse original names that are mangled, so a name is never renamed twice (e.g. a shorthand's key after it's been expanded).
+  let mut taken = session.new_hashset();
+  for name in collect.names {
+    let raw = name.as_slice();
+    if is_identifier_name(raw)
+      && !RESERVED_PROPERTY_SET.contains(raw)
+      && !collect.strings.contains(&name)
+      && from_utf8(raw).is_ok_and(|s| pattern.is_match(s))
+    {
+      to_mangle.push(name);
+    };
+    taken.insert(unquote(session, name));
+  }
+  for string in collect.strings {
+    taken.insert(string);
+  }
+  for reserved in RESERVED_PROPERTIES {
+    taken.insert(SourceRange::from_slice(reserved.as_bytes()));
+  }
+
+  let alloc_name =
+    |name: &str| SourceRange::from_slice(session.get_allocator().alloc_slice_copy(name.as_bytes()));
+  let mut renamed = HashMap::new();
+  let mut uncached = Vec::new();
+  if let Some(cache) = cache.as_deref() {
+    let mut used = HashSet::new();
+    for name in to_mangle {
+      let cached = from_utf8(name.as_slice())
+        .ok()
+        .and_then(|n| cache.get(n))
+        .filter(|c| name_cache::is_identifier_name(c))
+        .map(|c| alloc_name(c))
+        .filter(|c| !taken.contains(c) && used.insert(*c));
+      match cached {
+        Some(cached) => {
+          renamed.insert(name, cached);
+        }
+        None => uncached.push(name),
+      };
+    }
+    for cached in cache.values() {
+      taken.insert(alloc_name(cached));
+    }
+  } else {
+    uncached = to_mangle;
+  };
+
+  let mut generator = MinifiedNameGenerator::new(session, alphabet, &taken);
+  for name in uncached {
+    let minified = generator.generate_next_available_minified_name();
+    if let Some(cache) = cache.as_deref_mut() {
+      // Names were already checked to be UTF-8 when matching `pattern`.
+      cache.insert(
+        from_utf8(name.as_slice()).unwrap().to_string(),
+        from_utf8(minified.as_slice()).unwrap().to_string(),
+      );
+    };
+    renamed.insert(name, minified);
+  }
+  if !renamed.is_empty() {
+    Rename { session, renamed }.visit(top_level_node);
+  };
+}
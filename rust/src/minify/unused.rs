@@ -0,0 +1,216 @@
+use super::ctx::has_direct_eval;
+use super::ctx::restore_hoisted_functions;
+use super::ctx::take_hoisted_functions;
+use super::ctx::MinifyScope;
+use super::ctx::MinifySymbol;
+use parse_js::ast::ClassOrObjectMemberValue;
+use parse_js::ast::LiteralTemplatePart;
+use parse_js::ast::Node;
+use parse_js::ast::NodeData;
+use parse_js::ast::Syntax;
+use parse_js::ast::VariableDeclarator;
+use parse_js::operator::OperatorName;
+use parse_js::session::SessionHashMap;
+use parse_js::session::SessionVec;
+use parse_js::symbol::Scope;
+use parse_js::symbol::ScopeFlag;
+use parse_js::symbol::ScopeType;
+use parse_js::symbol::Symbol;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+
+// Returns whether evaluating an initialiser can't have any side effects (including throwing), so it can be dropped along with its unused variable.
+pub fn is_pure_initializer<'a>(stx: &Syntax<'a>) -> bool {
+  match stx {
+    Syntax::LiteralBooleanExpr { .. }
+    | Syntax::LiteralNull {}
+    | Syntax::LiteralNumberExpr { .. }
+    | Syntax::LiteralStringExpr { .. }
+    | Syntax::ArrowFunctionExpr { .. }
+    | Syntax::FunctionExpr { .. } => true,
+    Syntax::LiteralTemplateExpr { parts } => parts
+      .iter()
+      .all(|p| matches!(p, LiteralTemplatePart::String(_))),
+    Syntax::UnaryExpr {
+      operator: OperatorName::LogicalNot | OperatorName::Void,
+      argument,
+      ..
+    } => is_pure_initializer(&argument.stx),
+    _ => false,
+  }
+}
+
+// Returns whether declarations in `scope` can only be used by code we can see. Declarations in the global or module scope could be used by other scripts or modules, and a direct `eval` could use any variable visible to it.
+pub fn is_local_to_function<'a>(
+  scopes: &SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  scope: Scope<'a>,
+) -> bool {
+  let Some(closure) = scope.find_self_or_ancestor(|t| t.is_closure_or_global()) else {
+    return false;
+  };
+  matches!(
+    closure.typ(),
+    ScopeType::ArrowFunction | ScopeType::NonArrowFunction
+  ) && !has_direct_eval(scopes, closure)
+}
+
+struct Unused<'a, 'b> {
+  symbols: &'b SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  scopes: &'b SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  // Whether the next signature visited is a class or object method's.
+  is_method_signature: bool,
+  // The fewest parameters each signature being visited must keep, innermost last.
+  min_parameters: Vec<usize>,
+}
+
+impl<'a, 'b> Unused<'a, 'b> {
+  // Only simple identifiers are considered; destructuring could have side effects (e.g. getters) even if none of its variables are used.
+  fn is_unused_pattern(&self, pattern: &NodeData<'a>) -> bool {
+    let Syntax::IdentifierPattern { name } = &pattern.stx else {
+      return false;
+    };
+    is_local_to_function(self.scopes, pattern.scope)
+      && pattern
+        .scope
+        .find_symbol(*name)
+        .is_some_and(|sym| !self.symbols.get(&sym).is_some_and(|s| s.has_usage))
+  }
+
+  fn is_removable_declarator(&self, decl: &VariableDeclarator<'a>) -> bool {
+    self.is_unused_pattern(decl.pattern)
+      && match &decl.initializer {
+        Some(init) => is_pure_initializer(&init.stx),
+        None => true,
+      }
+  }
+
+  fn remove_unused_declarations(&self, body: &mut SessionVec<'a, Node<'a>>) -> () {
+    body.retain_mut(|stmt| match &mut stmt.stx {
+      Syntax::VarDecl {
+        export: false,
+        declarators,
+        ..
+      } => {
+        declarators.retain(|d| !self.is_removable_declarator(d));
+        !declarators.is_empty()
+      }
+      _ => true,
+    });
+  }
+}
+
+impl<'a, 'b> Visitor<'a> for Unused<'a, 'b> {
+  fn on_syntax_down(&mut self, node: &mut NodeData<'a>, _ctl: &mut JourneyControls) -> () {
+    if let Syntax::FunctionSignature { parameters } = &node.stx {
+      // Removing parameters of a function that uses `arguments` would change its `length` and what `arguments` contains.
+      let uses_arguments = node
+        .scope
+        .find_self_or_ancestor(|t| t.is_closure_or_global())
+        .is_some_and(|closure| {
+          closure.typ() == ScopeType::NonArrowFunction
+            && closure.flags().has(ScopeFlag::UsesArguments)
+        });
+      // The parser also represents setters as methods, and a setter must have exactly one parameter, so methods keep at least one.
+      let is_method = std::mem::take(&mut self.is_method_signature);
+      self.min_parameters.push(if uses_arguments {
+        parameters.len()
+      } else {
+        is_method as usize
+      });
+    };
+  }
+
+  fn visit_class_or_object_value(&mut self, value: &mut ClassOrObjectMemberValue<'a>) -> () {
+    match value {
+      ClassOrObjectMemberValue::Getter { body } => self.visit(body),
+      ClassOrObjectMemberValue::Method {
+        signature, body, ..
+      } => {
+        self.is_method_signature = true;
+        self.visit(signature);
+        self.visit(body);
+      }
+      ClassOrObjectMemberValue::Property { initializer } => {
+        if let Some(initializer) = initializer {
+          self.visit(initializer);
+        };
+      }
+      ClassOrObjectMemberValue::Setter { body, parameter } => {
+        self.visit(parameter);
+        self.visit(body);
+      }
+    };
+  }
+
+  fn on_syntax_up(&mut self, node: &mut NodeData<'a>) -> () {
+    match &mut node.stx {
+      // Declarations in `for` loop headers aren't in a statement list, so they're never removed.
+      Syntax::BlockStmt { body } | Syntax::SwitchBranch { body, .. } => {
+        self.remove_unused_declarations(body)
+      }
+      // Leading parameters must be kept even if unused, as they determine the position of later ones.
+      Syntax::FunctionSignature { parameters } => {
+        let min_parameters = self.min_parameters.pop().unwrap_or(parameters.len());
+        while parameters.len() > min_parameters {
+          let Some(last) = parameters.last() else {
+            break;
+          };
+          let removable = match &last.stx {
+            Syntax::ParamDecl {
+              rest: false,
+              pattern,
+              default_value: None,
+            } => self.is_unused_pattern(pattern),
+            _ => false,
+          };
+          if !removable {
+            break;
+          };
+          parameters.pop();
+        }
+      }
+      _ => {}
+    };
+  }
+}
+
+// - Remove function-local `var`, `let` and `const` declarators whose variable is never used and whose initialiser has no side effects.
+// - Remove function declarations nested in a function that are never used from `hoisted_functions`.
+// - Remove trailing function parameters that are never used, except in functions that use `arguments`, and the only parameter of methods, which may be setters.
+// This must run after Pass2, so that function declarations have been hoisted. Usages are tracked by Pass1, where declarations don't count as usages.
+pub fn remove_unused<'a>(
+  symbols: &SessionHashMap<'a, Symbol, MinifySymbol<'a>>,
+  scopes: &mut SessionHashMap<'a, Scope<'a>, MinifyScope<'a>>,
+  top_level_node: &mut NodeData<'a>,
+) -> () {
+  let is_used = |sym: Option<Symbol>| match sym {
+    Some(sym) => symbols.get(&sym).is_some_and(|s| s.has_usage),
+    // We couldn't find the declaration, so keep it to be safe.
+    None => true,
+  };
+  for (scope, min_scope) in scopes.iter_mut() {
+    if matches!(
+      scope.typ(),
+      ScopeType::ArrowFunction | ScopeType::NonArrowFunction
+    ) && !min_scope.has_direct_eval
+    {
+      // Only look in the closure's own scope; a function declared in a nested block is declared in that block's scope, and an ancestor's symbol with the same name is a different variable.
+      min_scope
+        .hoisted_functions
+        .retain(|name, _| is_used(scope.get_symbol(*name)));
+    };
+  }
+
+  let mut hoisted = take_hoisted_functions(scopes);
+  let mut pass = Unused {
+    symbols,
+    scopes: &*scopes,
+    is_method_signature: false,
+    min_parameters: Vec::new(),
+  };
+  pass.visit(top_level_node);
+  for (_, _, func) in hoisted.iter_mut() {
+    pass.visit(func);
+  }
+  restore_hoisted_functions(scopes, hoisted);
+}
@@ -0,0 +1,109 @@
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+/// An error encountered while reading a name cache from JSON.
+#[derive(Debug)]
+pub enum NameCacheError {
+  /// The input isn't valid JSON.
+  Json(serde_json::Error),
+  /// A field isn't an object of strings, or one of its minified names isn't a valid identifier.
+  InvalidField(&'static str),
+}
+
+impl fmt::Display for NameCacheError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NameCacheError::Json(err) => write!(f, "invalid name cache JSON: {}", err),
+      NameCacheError::InvalidField(field) => write!(f, "invalid name cache field `{}`", field),
+    }
+  }
+}
+
+impl Error for NameCacheError {}
+
+/// Minified names chosen by previous runs, so that the same names are chosen again across builds, and across chunks that reference each other. Pass the same cache to every run, and save it afterwards; names chosen for new symbols and properties are added to it.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::NameCache;
+///
+/// let mut cache = NameCache::from_json(r#"{"properties":{"_count":"a"},"topLevel":{}}"#).unwrap();
+/// cache.top_level.insert("helper".to_string(), "b".to_string());
+/// assert_eq!(cache.to_json(), r#"{"properties":{"_count":"a"},"topLevel":{"helper":"b"}}"#);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NameCache {
+  /// Minified names of properties renamed by `MinifyOptions::mangle_properties`, by original name.
+  pub properties: BTreeMap<String, String>,
+  /// Minified names of variables declared at the top level of modules, by original name. Variables declared at the top level of global scripts are never renamed.
+  pub top_level: BTreeMap<String, String>,
+}
+
+// Minified names are written as-is, so one that isn't an IdentifierName (e.g. `1bad`) would produce invalid code. Keywords are valid property names, and are skipped when reusing cached variable names.
+pub(crate) fn is_identifier_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .is_some_and(|c| c == '$' || c == '_' || c.is_alphabetic())
+    && chars.all(|c| c == '$' || c == '_' || c == '\u{200C}' || c == '\u{200D}' || c.is_alphanumeric())
+}
+
+fn read_names(
+  obj: &Map<String, Value>,
+  field: &'static str,
+) -> Result<BTreeMap<String, String>, NameCacheError> {
+  let mut names = BTreeMap::new();
+  match obj.get(field) {
+    None | Some(Value::Null) => {}
+    Some(Value::Object(entries)) => {
+      for (original, minified) in entries {
+        let minified = minified
+          .as_str()
+          .filter(|m| is_identifier_name(m))
+          .ok_or(NameCacheError::InvalidField(field))?;
+        names.insert(original.clone(), minified.to_string());
+      }
+    }
+    Some(_) => return Err(NameCacheError::InvalidField(field)),
+  };
+  Ok(names)
+}
+
+fn names_to_value(names: &BTreeMap<String, String>) -> Value {
+  Value::Object(
+    names
+      .iter()
+      .map(|(original, minified)| (original.clone(), Value::String(minified.clone())))
+      .collect(),
+  )
+}
+
+impl NameCache {
+  pub fn new() -> NameCache {
+    NameCache::default()
+  }
+
+  /// Reads a cache previously written by `to_json`. Missing fields are treated as empty.
+  pub fn from_json(json: &str) -> Result<NameCache, NameCacheError> {
+    let raw: Value = serde_json::from_str(json).map_err(NameCacheError::Json)?;
+    let obj = raw
+      .as_object()
+      .ok_or(NameCacheError::InvalidField("properties"))?;
+    Ok(NameCache {
+      properties: read_names(obj, "properties")?,
+      top_level: read_names(obj, "topLevel")?,
+    })
+  }
+
+  /// Serialises the cache as JSON, with names in sorted order so the output is stable.
+  pub fn to_json(&self) -> String {
+    let mut obj = Map::new();
+    obj.insert("properties".to_string(), names_to_value(&self.properties));
+    obj.insert("topLevel".to_string(), names_to_value(&self.top_level));
+    Value::Object(obj).to_string()
+  }
+}
@@ -0,0 +1,373 @@
+use parse_js::ast::NodeData;
+use parse_js::visit::JourneyControls;
+use parse_js::visit::Visitor;
+
+// Stack space to reserve for each level of nesting. Parsing, every pass, and emitting all recurse at least once per level, and the deepest of them takes up to about 16 KiB per level in debug builds and 1.5 KiB in release builds.
+const STACK_PER_LEVEL: usize = if cfg!(debug_assertions) {
+  24 * 1024
+} else {
+  4 * 1024
+};
+
+// Runs `f` with enough stack for code nested up to `max_depth` levels deep, moving onto a new stack on the heap if the current thread doesn't have that much left. The new stack is only reserved, so pages that aren't reached don't use any memory.
+pub fn with_stack_for_depth<R>(max_depth: usize, f: impl FnOnce() -> R) -> R {
+  let size = max_depth.saturating_add(1).saturating_mul(STACK_PER_LEVEL);
+  stacker::maybe_grow(size, size, f)
+}
+
+// Words that are followed by an operand, like a prefix operator, so a `/` after them starts a regex rather than being division.
+const PREFIX_KEYWORDS: &[&[u8]] = &[
+  b"await",
+  b"case",
+  b"delete",
+  b"in",
+  b"instanceof",
+  b"new",
+  b"of",
+  b"return",
+  b"throw",
+  b"typeof",
+  b"void",
+  b"yield",
+];
+
+// Words that are followed by a statement, which can itself be another one of these (e.g. `if (a) if (b) c()`).
+const STATEMENT_KEYWORDS: &[&[u8]] = &[b"do", b"else", b"for", b"if", b"while", b"with"];
+
+fn is_word_byte(c: u8) -> bool {
+  c.is_ascii_alphanumeric() || matches!(c, b'_' | b'$' | b'\\') || c >= 0x80
+}
+
+// Returns the index after the closing quote of the string starting at `i`. Strings can't span lines, so an unterminated one ends at the line break.
+fn skip_string(source: &[u8], i: usize) -> usize {
+  let quote = source[i];
+  let mut i = i + 1;
+  while i < source.len() {
+    match source[i] {
+      b'\\' => i += 2,
+      b'\n' => return i,
+      c if c == quote => return i + 1,
+      _ => i += 1,
+    };
+  }
+  source.len()
+}
+
+// Returns the index after the closing `/` of the regex starting at `i`, or None if there isn't one on the same line, in which case the `/` was division after all.
+fn skip_regex(source: &[u8], i: usize) -> Option<usize> {
+  let mut in_class = false;
+  let mut i = i + 1;
+  while i < source.len() {
+    match source[i] {
+      b'\\' => i += 1,
+      b'\n' => return None,
+      b'[' => in_class = true,
+      b']' => in_class = false,
+      b'/' if !in_class => return Some(i + 1),
+      _ => {}
+    };
+    i += 1;
+  }
+  None
+}
+
+// Returns the index after the end of the template text starting at `i` (just after a `` ` `` or a substitution's `}`), and whether it ends at a `${` rather than the closing `` ` ``.
+fn skip_template_text(source: &[u8], i: usize) -> (usize, bool) {
+  let mut i = i;
+  while i < source.len() {
+    match source[i] {
+      b'\\' => i += 2,
+      b'`' => return (i + 1, false),
+      b'$' if source.get(i + 1) == Some(&b'{') => return (i + 2, true),
+      _ => i += 1,
+    };
+  }
+  (source.len(), false)
+}
+
+// What's still being parsed within a bracket, besides nested brackets.
+#[derive(Default)]
+struct Level {
+  // Prefix operators (e.g. `!`, `typeof`) that are waiting for their operand.
+  prefixes: usize,
+  // Statement keywords (e.g. `if`) and arrows (`=>`) in the current statement or expression, whose body may not have ended yet.
+  bodies: usize,
+  // Right-associative operators (assignments, `?` and `**`) in the current expression, whose right operand may not have ended yet. Unlike bodies, these aren't ended by a `}`, which usually closes an object literal or function in the operand (e.g. `a = {}`).
+  operators: usize,
+  // Whether this is a template substitution, so its `}` goes back to the template text.
+  is_substitution: bool,
+}
+
+// Tracks how deeply the parser will recurse at each point, so the source can be checked before parsing.
+struct Nesting {
+  levels: Vec<Level>,
+  // Brackets, prefixes, bodies, and operators open across all levels.
+  depth: usize,
+}
+
+impl Nesting {
+  fn top(&mut self) -> &mut Level {
+    self.levels.last_mut().unwrap()
+  }
+
+  fn open_bracket(&mut self, is_substitution: bool) -> () {
+    self.depth += 1;
+    self.levels.push(Level {
+      is_substitution,
+      ..Level::default()
+    });
+  }
+
+  fn close_bracket(&mut self) -> () {
+    // An unmatched closing bracket is a syntax error, which the parser will report.
+    if self.levels.len() > 1 {
+      let closed = self.levels.pop().unwrap();
+      self.depth -= 1 + closed.prefixes + closed.bodies + closed.operators;
+    };
+  }
+
+  fn add_prefix(&mut self) -> () {
+    self.depth += 1;
+    self.top().prefixes += 1;
+  }
+
+  fn add_body(&mut self) -> () {
+    self.depth += 1;
+    self.top().bodies += 1;
+  }
+
+  fn add_operator(&mut self) -> () {
+    self.depth += 1;
+    self.top().operators += 1;
+  }
+
+  // An operand completes the prefix operators before it.
+  fn end_prefixes(&mut self) -> () {
+    let prefixes = std::mem::take(&mut self.top().prefixes);
+    self.depth -= prefixes;
+  }
+
+  // The end of a statement or list item completes any bodies in it. Line breaks usually end statements too, so they're treated as if they do unless they follow an operator that still needs its operand; a body that continues after one is only missed until the next one.
+  fn end_bodies(&mut self) -> () {
+    let bodies = std::mem::take(&mut self.top().bodies);
+    self.depth -= bodies;
+  }
+
+  fn end_operators(&mut self) -> () {
+    let operators = std::mem::take(&mut self.top().operators);
+    self.depth -= operators;
+  }
+}
+
+// The parser recurses for each level of brackets, even redundant ones that don't appear in the tree (e.g. `((a))`), and for each prefix operator, statement keyword, arrow and right-associative operator (e.g. `a = b = c` and `a ? b : c ? d : e`) whose operand or body it's in, so these must be counted before parsing. Returns the byte offset of the first one that's nested more than `max_depth` deep.
+// This only needs to find these, not tokenise exactly, so whether a `/` starts a regex is guessed from what's before it, like most tools that don't parse do. A wrong guess only miscounts until the end of the line.
+pub fn find_too_deep_source(source: &[u8], max_depth: usize) -> Option<usize> {
+  let mut nesting = Nesting {
+    levels: vec![Level::default()],
+    depth: 0,
+  };
+  // Whether the last token can end an operand, so that what follows is a binary operator (e.g. `/` is division, and `-` is subtraction).
+  let mut after_operand = false;
+  // Whether the last token was an operator that needs an operand after it (e.g. `=` or `=>`), so a line break after it doesn't end the statement.
+  let mut needs_operand = false;
+  let mut i = 0;
+  while i < source.len() {
+    let start = i;
+    let c = source[i];
+    let was_needs_operand = std::mem::take(&mut needs_operand);
+    match c {
+      b'\n' => {
+        if !was_needs_operand {
+          nesting.end_bodies();
+          nesting.end_operators();
+        };
+        needs_operand = was_needs_operand;
+        i += 1;
+      }
+      b' ' | b'\t' | b'\r' => {
+        needs_operand = was_needs_operand;
+        i += 1;
+      }
+      b'/' if source.get(i + 1) == Some(&b'/') => {
+        needs_operand = was_needs_operand;
+        i = source[i..]
+          .iter()
+          .position(|&c| c == b'\n')
+          .map_or(source.len(), |n| i + n);
+      }
+      b'/' if source.get(i + 1) == Some(&b'*') => {
+        needs_operand = was_needs_operand;
+        i = source[i + 2..]
+          .windows(2)
+          .position(|w| w == b"*/")
+          .map_or(source.len(), |n| i + 2 + n + 2);
+      }
+      b'/' if !after_operand && skip_regex(source, i).is_some() => {
+        i = skip_regex(source, i).unwrap();
+        nesting.end_prefixes();
+        after_operand = true;
+      }
+      b'\'' | b'"' => {
+        i = skip_string(source, i);
+        nesting.end_prefixes();
+        after_operand = true;
+      }
+      b'`' | b'}' if c == b'`' || nesting.top().is_substitution => {
+        if c == b'}' {
+          nesting.close_bracket();
+        };
+        let (end, opened_substitution) = skip_template_text(source, i + 1);
+        if opened_substitution {
+          nesting.open_bracket(true);
+        } else {
+          nesting.end_prefixes();
+        };
+        i = end;
+        after_operand = !opened_substitution;
+      }
+      b'(' | b'[' | b'{' => {
+        nesting.open_bracket(false);
+        i += 1;
+        after_operand = false;
+      }
+      b')' | b']' => {
+        nesting.close_bracket();
+        nesting.end_prefixes();
+        i += 1;
+        after_operand = true;
+      }
+      b'}' => {
+        // This ends a block or an object literal, and either way ends the bodies and prefixes before it (e.g. `if (a) {}` and `!{}`).
+        nesting.close_bracket();
+        nesting.end_prefixes();
+        nesting.end_bodies();
+        i += 1;
+        // A block is more common than an object literal, and a `/` after one starts a regex.
+        after_operand = false;
+      }
+      b';' | b',' => {
+        nesting.end_prefixes();
+        nesting.end_bodies();
+        nesting.end_operators();
+        i += 1;
+        after_operand = false;
+      }
+      b'=' if source.get(i + 1) == Some(&b'>') => {
+        nesting.add_body();
+        i += 2;
+        after_operand = false;
+        needs_operand = true;
+      }
+      b'=' if source.get(i + 1) == Some(&b'=') => {
+        // `==` and `===` are left-associative.
+        while source.get(i) == Some(&b'=') {
+          i += 1;
+        }
+        after_operand = false;
+      }
+      b'=' => {
+        // Comparisons end in `=` too, but a `<` or `>` before is only part of an assignment if it's doubled (e.g. `<<=` but not `<=`).
+        let before = &source[..i];
+        let is_comparison = match before.last() {
+          Some(b'!') => true,
+          Some(&c @ (b'<' | b'>')) => before.len() < 2 || before[before.len() - 2] != c,
+          _ => false,
+        };
+        if !is_comparison {
+          nesting.add_operator();
+          needs_operand = true;
+        };
+        i += 1;
+        after_operand = false;
+      }
+      b'?' if source.get(i + 1) == Some(&b'?') => {
+        // `??` is left-associative, and `??=` is counted at its `=`.
+        i += 2;
+        after_operand = false;
+      }
+      b'?'
+        if source.get(i + 1) == Some(&b'.')
+          && !source.get(i + 2).is_some_and(|c| c.is_ascii_digit()) =>
+      {
+        // Optional chaining (e.g. `a?.b`), unlike `a?.5:1`.
+        i += 2;
+        after_operand = false;
+      }
+      b'?' => {
+        nesting.add_operator();
+        i += 1;
+        after_operand = false;
+        needs_operand = true;
+      }
+      b'*' if source.get(i + 1) == Some(&b'*') => {
+        i += 2;
+        // `**=` is counted at its `=`.
+        if source.get(i) != Some(&b'=') {
+          nesting.add_operator();
+          needs_operand = true;
+        };
+        after_operand = false;
+      }
+      b'!' | b'~' | b'+' | b'-' if !after_operand => {
+        nesting.add_prefix();
+        i += 1;
+      }
+      c if is_word_byte(c) => {
+        while i < source.len() && is_word_byte(source[i]) {
+          i += 1;
+        }
+        let word = &source[start..i];
+        after_operand = false;
+        if STATEMENT_KEYWORDS.contains(&word) {
+          nesting.add_body();
+        } else if PREFIX_KEYWORDS.contains(&word) {
+          nesting.add_prefix();
+        } else {
+          nesting.end_prefixes();
+          after_operand = true;
+        };
+      }
+      _ => {
+        i += 1;
+        after_operand = false;
+      }
+    };
+    if nesting.depth > max_depth {
+      return Some(start);
+    };
+  }
+  None
+}
+
+// The passes and the emitter recurse for each level of the tree, which can be much deeper than the brackets (e.g. each operand of `a + b + c` is nested in the next operator), so it's checked after parsing too. This stops descending at `max_depth`, so it can't run out of stack itself.
+struct DepthCheck {
+  depth: usize,
+  max_depth: usize,
+  too_deep: Option<usize>,
+}
+
+impl<'a> Visitor<'a> for DepthCheck {
+  fn on_syntax_down(&mut self, node: &mut NodeData<'a>, ctl: &mut JourneyControls) -> () {
+    if self.too_deep.is_some() || self.depth == self.max_depth {
+      self.too_deep.get_or_insert(node.loc.start());
+      ctl.skip();
+      return;
+    };
+    self.depth += 1;
+  }
+
+  fn on_syntax_up(&mut self, _node: &mut NodeData<'a>) -> () {
+    self.depth -= 1;
+  }
+}
+
+// Returns the byte offset of the first node that's nested more than `max_depth` deep.
+pub fn find_too_deep_node<'a>(node: &mut NodeData<'a>, max_depth: usize) -> Option<usize> {
+  let mut check = DepthCheck {
+    depth: 0,
+    max_depth,
+    too_deep: None,
+  };
+  check.visit(node);
+  check.too_deep
+}
@@ -0,0 +1,238 @@
+// Returns the shortest digits that round-trip to `v`, which must be finite and positive, and the position of the decimal point relative to them, so that `v` is `0.<digits> * 10^n`. These are the `s`, `k` and `n` of the ECMAScript Number::toString abstract operation.
+fn digits_and_point(v: f64) -> (String, i32) {
+  // Rust's exponential formatting gives the shortest digits that round-trip, and picks the closest when there are several, which is also what JS requires.
+  let sci = format!("{:e}", v);
+  let (mantissa, exp) = sci.split_once('e').unwrap();
+  (mantissa.replace('.', ""), exp.parse::<i32>().unwrap() + 1)
+}
+
+// Formats a number the same way as the ECMAScript Number::toString abstract operation.
+pub fn js_number_to_string(v: f64) -> String {
+  if v.is_nan() {
+    return "NaN".to_string();
+  };
+  if v == 0.0 {
+    // Includes -0.
+    return "0".to_string();
+  };
+  if v.is_infinite() {
+    return if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+  };
+  if v < 0.0 {
+    return format!("-{}", js_number_to_string(-v));
+  };
+  let (digits, n) = digits_and_point(v);
+  let k = digits.len() as i32;
+  if k <= n && n <= 21 {
+    format!("{}{}", digits, "0".repeat((n - k) as usize))
+  } else if 0 < n && n <= 21 {
+    format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+  } else if -6 < n && n <= 0 {
+    format!("0.{}{}", "0".repeat(-n as usize), digits)
+  } else {
+    let sign = if n - 1 < 0 { '-' } else { '+' };
+    if k == 1 {
+      format!("{}e{}{}", digits, sign, (n - 1).abs())
+    } else {
+      format!(
+        "{}.{}e{}{}",
+        &digits[..1],
+        &digits[1..],
+        sign,
+        (n - 1).abs()
+      )
+    }
+  }
+}
+
+// Returns the shortest numeric literal for `v`, which must be finite and not negative: the plain decimal form without a leading zero (e.g. `.5`), an integer mantissa with an exponent (e.g. `1e6` and `25e-8`), or hex for large integers (e.g. `0xffffffffff`). On a tie, the decimal form is preferred.
+pub fn shortest_number_literal(v: f64) -> String {
+  debug_assert!(v.is_finite() && v.is_sign_positive());
+  if v == 0.0 {
+    return "0".to_string();
+  };
+  let (digits, n) = digits_and_point(v);
+  let k = digits.len() as i32;
+  let mut candidates = Vec::new();
+  if k <= n {
+    candidates.push(format!("{}{}", digits, "0".repeat((n - k) as usize)));
+    if n > k {
+      candidates.push(format!("{}e{}", digits, n - k));
+    };
+    // Every integer is exactly representable in hex, so this always parses back to the same value.
+    if v < u128::MAX as f64 {
+      candidates.push(format!("0x{:x}", v as u128));
+    };
+  } else if n > 0 {
+    candidates.push(format!(
+      "{}.{}",
+      &digits[..n as usize],
+      &digits[n as usize..]
+    ));
+  } else {
+    candidates.push(format!(".{}{}", "0".repeat(-n as usize), digits));
+    candidates.push(format!("{}e{}", digits, n - k));
+  };
+  candidates.into_iter().min_by_key(|c| c.len()).unwrap()
+}
+
+// Parses the digits of a BigInt literal (e.g. `0x1_0n`), with or without the `n` suffix, into little-endian 32-bit limbs. Returns None if it isn't a valid literal.
+fn parse_bigint(literal: &str) -> Option<Vec<u32>> {
+  let literal = literal.strip_suffix('n').unwrap_or(literal);
+  let (radix, digits) = match literal.get(..2) {
+    Some("0x" | "0X") => (16, &literal[2..]),
+    Some("0o" | "0O") => (8, &literal[2..]),
+    Some("0b" | "0B") => (2, &literal[2..]),
+    _ => (10, literal),
+  };
+  if digits.is_empty() {
+    return None;
+  };
+  let mut limbs = vec![0u32];
+  for c in digits.chars().filter(|&c| c != '_') {
+    let mut carry = c.to_digit(radix)? as u64;
+    for limb in limbs.iter_mut() {
+      let v = *limb as u64 * radix as u64 + carry;
+      *limb = v as u32;
+      carry = v >> 32;
+    }
+    if carry > 0 {
+      limbs.push(carry as u32);
+    };
+  }
+  Some(limbs)
+}
+
+fn bigint_to_decimal(mut limbs: Vec<u32>) -> String {
+  // Divide repeatedly by 10^9 so that each remainder is nine decimal digits.
+  let mut chunks = Vec::new();
+  loop {
+    let mut rem = 0u64;
+    for limb in limbs.iter_mut().rev() {
+      let v = (rem << 32) | *limb as u64;
+      *limb = (v / 1_000_000_000) as u32;
+      rem = v % 1_000_000_000;
+    }
+    chunks.push(rem as u32);
+    while limbs.len() > 1 && *limbs.last().unwrap() == 0 {
+      limbs.pop();
+    }
+    if limbs == [0] {
+      break;
+    };
+  }
+  let mut out = chunks.pop().unwrap().to_string();
+  for chunk in chunks.iter().rev() {
+    out.push_str(&format!("{:09}", chunk));
+  }
+  out
+}
+
+fn bigint_to_hex(limbs: &[u32]) -> String {
+  let mut limbs = limbs.iter().rev().skip_while(|&&l| l == 0);
+  let mut out = format!("0x{:x}", limbs.next().unwrap_or(&0));
+  for limb in limbs {
+    out.push_str(&format!("{:08x}", limb));
+  }
+  out
+}
+
+// Returns the shortest BigInt literal with the same value as `literal` (e.g. `16n` for `0x10n`, and `0xffffffffffffn` for `281474976710655n`), preferring decimal on a tie. Returns None if `literal` isn't a valid BigInt literal.
+pub fn shortest_bigint_literal(literal: &str) -> Option<String> {
+  let limbs = parse_bigint(literal)?;
+  let hex = bigint_to_hex(&limbs);
+  let decimal = bigint_to_decimal(limbs);
+  let mut shortest = if hex.len() < decimal.len() {
+    hex
+  } else {
+    decimal
+  };
+  shortest.push('n');
+  Some(shortest)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::js_number_to_string;
+  use super::shortest_bigint_literal;
+  use super::shortest_number_literal;
+
+  #[test]
+  fn test_js_number_to_string() {
+    for (v, expected) in [
+      (0.0, "0"),
+      (-0.0, "0"),
+      (1.0, "1"),
+      (-1.5, "-1.5"),
+      (0.1 + 0.2, "0.30000000000000004"),
+      (1e21, "1e+21"),
+      (123e20, "1.23e+22"),
+      (1e20, "100000000000000000000"),
+      (0.000001, "0.000001"),
+      (0.0000001, "1e-7"),
+      (1.5e-7, "1.5e-7"),
+      (f64::MAX, "1.7976931348623157e+308"),
+      (5e-324, "5e-324"),
+      (f64::NAN, "NaN"),
+      (f64::NEG_INFINITY, "-Infinity"),
+    ] {
+      assert_eq!(js_number_to_string(v), expected);
+    }
+  }
+
+  #[test]
+  fn test_shortest_number_literal() {
+    for (v, expected) in [
+      (0.0, "0"),
+      (7.0, "7"),
+      (100.0, "100"),
+      (1000.0, "1e3"),
+      (1000000.0, "1e6"),
+      (1230000.0, "123e4"),
+      (0.5, ".5"),
+      (0.05, ".05"),
+      (0.001, ".001"),
+      (0.0001, "1e-4"),
+      (0.00015, "15e-5"),
+      (1.5, "1.5"),
+      (123.456, "123.456"),
+      (4294967295.0, "4294967295"),
+      (1099511627775.0, "0xffffffffff"),
+      (2f64.powi(60), "1152921504606847e3"),
+      (1e21, "1e21"),
+      (f64::MAX, "17976931348623157e292"),
+      (5e-324, "5e-324"),
+    ] {
+      assert_eq!(shortest_number_literal(v), expected);
+    }
+  }
+
+  #[test]
+  fn test_shortest_bigint_literal() {
+    for (literal, expected) in [
+      ("0n", Some("0n")),
+      ("0x0n", Some("0n")),
+      ("123n", Some("123n")),
+      ("1_000n", Some("1000n")),
+      ("0x10n", Some("16n")),
+      ("0o777n", Some("511n")),
+      ("0b1010n", Some("10n")),
+      ("0XFFn", Some("255n")),
+      ("4294967296n", Some("4294967296n")),
+      ("281474976710655n", Some("0xffffffffffffn")),
+      (
+        "0x1234567890abcdef1234567890abcdefn",
+        Some("0x1234567890abcdef1234567890abcdefn"),
+      ),
+      (
+        "123456789012345678901234567890n",
+        Some("0x18ee90ff6c373e0ee4e3f0ad2n"),
+      ),
+      ("1000000000000000000000000000000n", Some("0xc9f2c9cd04674edea40000000n")),
+      ("0xn", None),
+      ("12an", None),
+    ] {
+      assert_eq!(shortest_bigint_literal(literal).as_deref(), expected);
+    }
+  }
+}
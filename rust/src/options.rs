@@ -0,0 +1,133 @@
+use crate::MinifyError;
+use regex::Regex;
+use std::str::FromStr;
+
+/// Which quotes to write string literals with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuoteStyle {
+  /// Pick whichever of `"`, `'` and `` ` `` needs the fewest escapes for each string, preferring them in that order on a tie.
+  #[default]
+  Auto,
+  /// Always use `"`.
+  Double,
+  /// Always use `'`.
+  Single,
+  /// Always use `` ` ``, except where a template isn't allowed (e.g. import and export module names), where `"` is used.
+  Backtick,
+}
+
+impl FromStr for QuoteStyle {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(QuoteStyle::Auto),
+      "double" => Ok(QuoteStyle::Double),
+      "single" => Ok(QuoteStyle::Single),
+      "backtick" => Ok(QuoteStyle::Backtick),
+      _ => Err("invalid quote style"),
+    }
+  }
+}
+
+/// Which property names `MinifyOptions::mangle_properties` renames: a regular expression in the syntax of the Rust `regex` crate (e.g. `^_`), matched anywhere in the name unless anchored.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::{MinifyError, PropertyPattern};
+///
+/// let pattern: PropertyPattern = "^_".parse().unwrap();
+/// assert!(matches!("(".parse::<PropertyPattern>(), Err(MinifyError::InvalidPropertyPattern { .. })));
+/// ```
+#[derive(Clone, Debug)]
+pub struct PropertyPattern(Regex);
+
+impl PropertyPattern {
+  pub(crate) fn is_match(&self, name: &str) -> bool {
+    self.0.is_match(name)
+  }
+}
+
+impl FromStr for PropertyPattern {
+  type Err = MinifyError<'static>;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Regex::new(s)
+      .map(PropertyPattern)
+      .map_err(|err| MinifyError::InvalidPropertyPattern {
+        message: err.to_string(),
+      })
+  }
+}
+
+/// Options controlling which transforms are applied during minification. Every transform is enabled by default except `mangle_properties`, which needs to be told which properties are safe to rename, and `frequency_alphabet`; disable one to work around code that breaks under it without giving up the rest.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::MinifyOptions;
+///
+/// let options = MinifyOptions {
+///   arrow_functions: false,
+///   ..MinifyOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct MinifyOptions {
+  /// Rename declared variables, functions, classes and parameters to the shortest available names. Globals are never renamed.
+  pub mangle: bool,
+  /// Transform `function` declarations and expressions into arrow functions when `new`, `this`, `arguments`, and `prototype` aren't used.
+  pub arrow_functions: bool,
+  /// Transform `if` statements into `&&` and `?:` expressions where possible.
+  pub if_to_expression: bool,
+  /// Drop `debugger` statements.
+  pub drop_debugger: bool,
+  /// Unwrap block statements that don't declare anything, and braces around single-statement `if`, `while`, `do-while` and `for` bodies.
+  pub unwrap_blocks: bool,
+  /// Evaluate operators on literals at compile time (e.g. `"a" + 1` to `"a1"`, `!0` to `true`, and `1 < 2 ? a : b` to `a`).
+  pub fold_constants: bool,
+  /// Remove code that can never run: statements after `return`, `throw`, `break` and `continue`, untaken branches of `if` statements with a literal condition, and `while` loops with a falsy literal condition. Function declarations and `var` names in removed code are kept, as they're hoisted, as are unreachable top-level imports, exports, and `let`, `const` and `class` declarations.
+  pub dead_code: bool,
+  /// Remove declarations in functions that are never used: `var`, `let` and `const` variables with side-effect-free initialisers, nested function declarations, and trailing parameters. Nothing is removed from the top level, or from functions that call `eval`.
+  pub remove_unused: bool,
+  /// Inline function-local `const` variables that are used once: literals at the usage, and arrow functions and function expressions at their call (as an immediately invoked function), unless the call is in a loop that the declaration isn't in.
+  pub inline: bool,
+  /// Rename properties whose names match this pattern (e.g. `^_`) to short names, consistently across the whole input: member accesses, destructuring, and object literal and class member keys. ECMAScript and common DOM property names are never renamed, nor are numeric names, or names that also appear in a string anywhere in the input (e.g. a quoted key, `o["_a"]` or `"_a" in o`), as the property could be accessed through it. Only match properties that are private to the input, as other code accessing them won't be updated. Disabled by default.
+  pub mangle_properties: Option<PropertyPattern>,
+  /// Build minified names from the characters that appear most often in the code, instead of from `a` to `z` and then `A` to `Z`. This makes the output compress better with gzip or brotli, but names are less predictable. Only used when mangling. Disabled by default.
+  pub frequency_alphabet: bool,
+  /// Keep legal comments (`/*! ... */`, `//! ...`, and comments containing `@license` or `@preserve`) before the statement they preceded. All other comments are always dropped. To collect them separately instead, use `MinifyOutputs::legal_comments`.
+  pub legal_comments: bool,
+  /// Format the output to be readable instead of as small as possible: one statement per line with indentation, spaces around operators, and braces around every `if`, `for`, `while` and `do-while` body. Transforms and mangling are still applied, so this is useful for debugging minified code. Disabled by default.
+  pub beautify: bool,
+  /// Which quotes to write string literals with. By default, the quote that needs the fewest escapes is chosen for each string. Directives (e.g. `"use strict"`) are always written as is.
+  pub quote_style: QuoteStyle,
+  /// Never write `</script`, `<!--` or `-->`, so the output can be inlined into an HTML `<script>` element: they're escaped in strings, templates, quoted property names, regexes and legal comments, and separated by a space between operators (e.g. `a < !--b` and `a-- > b`). Disabled by default.
+  pub inline_script: bool,
+  /// The deepest that code can be nested, counting both brackets and syntax (e.g. in `a + b + c`, `a` is two levels deeper than the whole expression). Deeper code is rejected with `MinifyError::TooDeeplyNested` instead of running out of stack, and enough stack for this many levels is reserved while minifying, so only raise it as far as needed. Defaults to 10000.
+  pub max_nesting_depth: usize,
+}
+
+impl Default for MinifyOptions {
+  fn default() -> Self {
+    Self {
+      mangle: true,
+      arrow_functions: true,
+      if_to_expression: true,
+      drop_debugger: true,
+      unwrap_blocks: true,
+      fold_constants: true,
+      dead_code: true,
+      remove_unused: true,
+      inline: true,
+      mangle_properties: None,
+      frequency_alphabet: false,
+      legal_comments: false,
+      beautify: false,
+      quote_style: QuoteStyle::Auto,
+      inline_script: false,
+      max_nesting_depth: 10000,
+    }
+  }
+}
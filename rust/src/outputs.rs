@@ -0,0 +1,30 @@
+use crate::NameCache;
+use crate::SourceMap;
+use crate::Warning;
+
+/// What `minify_with` produces besides the minified code. Set a field to `Some` to request that output; any combination can be requested at once. Warnings are always collected.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::{MinifyOutputs, NameCache, SourceMap};
+///
+/// let outputs = MinifyOutputs {
+///   source_map: Some(SourceMap::new("input.js")),
+///   name_cache: Some(NameCache::new()),
+///   ..MinifyOutputs::default()
+/// };
+/// ```
+#[derive(Debug, Default)]
+pub struct MinifyOutputs {
+  /// Source map to add mappings and names for the output to; create it with `SourceMap::new`. Use `SourceMap::to_json` to serialise it afterwards.
+  pub source_map: Option<SourceMap>,
+  /// Source map for the input, if it was itself generated (e.g. by a compiler). If provided with `source_map`, the resulting map points to the sources of this map instead of to the input.
+  pub input_source_map: Option<SourceMap>,
+  /// Destination to write legal comments (`/*! ... */`, `//! ...`, and comments containing `@license` or `@preserve`) to instead of the output, one per line in source order. `MinifyOptions::legal_comments` is ignored when this is set.
+  pub legal_comments: Option<Vec<u8>>,
+  /// Names chosen by previous runs to reuse where possible; the names chosen by this run are added to it. See `NameCache`.
+  pub name_cache: Option<NameCache>,
+  /// Code that prevented some minification, such as direct calls to `eval`, in source order; see `Warning`.
+  pub warnings: Vec<Warning>,
+}
@@ -0,0 +1,549 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn write_vlq(out: &mut String, value: i64) {
+  // The sign is stored in the least significant bit.
+  let mut v = if value < 0 {
+    ((-value) << 1) | 1
+  } else {
+    value << 1
+  };
+  loop {
+    let mut digit = v & 0b11111;
+    v >>= 5;
+    if v > 0 {
+      // Continuation bit.
+      digit |= 0b100000;
+    };
+    out.push(BASE64_CHARS[digit as usize] as char);
+    if v == 0 {
+      break;
+    };
+  }
+}
+
+// Decodes the Base64 VLQ value starting at `*pos`, advancing `*pos` past it.
+fn read_vlq(raw: &[u8], pos: &mut usize) -> Result<i64, SourceMapError> {
+  let mut v = 0i64;
+  let mut shift = 0;
+  loop {
+    let c = *raw.get(*pos).ok_or(SourceMapError::InvalidMappings)?;
+    *pos += 1;
+    let digit = match c {
+      b'A'..=b'Z' => c - b'A',
+      b'a'..=b'z' => c - b'a' + 26,
+      b'0'..=b'9' => c - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      _ => return Err(SourceMapError::InvalidMappings),
+    } as i64;
+    if shift > 32 {
+      return Err(SourceMapError::InvalidMappings);
+    };
+    v |= (digit & 0b11111) << shift;
+    shift += 5;
+    if digit & 0b100000 == 0 {
+      break;
+    };
+  }
+  Ok(if v & 1 != 0 { -(v >> 1) } else { v >> 1 })
+}
+
+// Applies a decoded VLQ delta to a field, rejecting values that end up negative or out of range.
+fn apply_delta(field: &mut i64, delta: i64) -> Result<u32, SourceMapError> {
+  *field += delta;
+  u32::try_from(*field).map_err(|_| SourceMapError::InvalidMappings)
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+  out.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+      c => out.push(c),
+    };
+  }
+  out.push('"');
+}
+
+/// An error encountered while reading a source map from JSON.
+#[derive(Debug)]
+pub enum SourceMapError {
+  /// The input isn't valid JSON.
+  Json(serde_json::Error),
+  /// A required field is missing or has the wrong type, or the map isn't version 3.
+  InvalidField(&'static str),
+  /// The `mappings` field isn't valid Base64 VLQ, or references a source or name that doesn't exist.
+  InvalidMappings,
+  /// Index maps (maps with `sections`) aren't supported.
+  IndexMap,
+}
+
+impl fmt::Display for SourceMapError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SourceMapError::Json(err) => write!(f, "invalid source map JSON: {}", err),
+      SourceMapError::InvalidField(field) => write!(f, "invalid source map field `{}`", field),
+      SourceMapError::InvalidMappings => write!(f, "invalid source map mappings"),
+      SourceMapError::IndexMap => write!(f, "index source maps are not supported"),
+    }
+  }
+}
+
+impl Error for SourceMapError {}
+
+/// A single mapping from a position in the generated code to a position in an original source. Lines and columns are zero-based, and columns are in UTF-16 code units as required by the source map spec.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+  pub generated_line: u32,
+  pub generated_column: u32,
+  /// Index into `SourceMap::sources`.
+  pub source: u32,
+  pub original_line: u32,
+  pub original_column: u32,
+  /// Index into `SourceMap::names()`, if the generated token was renamed from an original identifier.
+  pub name: Option<u32>,
+}
+
+/// A version 3 source map. Maps generated by this crate start with a single source, the code that was minified; maps read with `from_json` or produced by `remap` may reference any number of sources.
+///
+/// # Examples
+///
+/// ```
+/// use better_minify_js::{minify_with, MinifyOptions, MinifyOutputs, Session, SourceMap, TopLevelMode};
+///
+/// let code: &[u8] = b"let my_first_variable = 1;\nmy_first_variable++;";
+/// let session = Session::new();
+/// let mut out = Vec::new();
+/// let mut outputs = MinifyOutputs {
+///   source_map: Some(SourceMap::new("input.js")),
+///   ..MinifyOutputs::default()
+/// };
+/// minify_with(&session, TopLevelMode::Module, &MinifyOptions::default(), code, &mut out, &mut outputs).unwrap();
+/// assert!(outputs.source_map.unwrap().to_json().starts_with(r#"{"version":3,"sources":["input.js"]"#));
+/// ```
+#[derive(Clone, Debug)]
+pub struct SourceMap {
+  /// Name of the generated file, written as the `file` field.
+  pub file: Option<String>,
+  /// Names of the original sources.
+  pub sources: Vec<String>,
+  /// Original source code for each entry in `sources`, written to `sourcesContent` if any are set.
+  pub sources_content: Vec<Option<String>>,
+  mappings: Vec<Mapping>,
+  names: Vec<String>,
+  name_indices: HashMap<String, u32>,
+}
+
+impl SourceMap {
+  pub fn new(source: impl Into<String>) -> SourceMap {
+    SourceMap {
+      file: None,
+      sources: vec![source.into()],
+      sources_content: vec![None],
+      mappings: Vec::new(),
+      names: Vec::new(),
+      name_indices: HashMap::new(),
+    }
+  }
+
+  /// Mappings in the order they were added, which is always in increasing generated position.
+  pub fn mappings(&self) -> &[Mapping] {
+    &self.mappings
+  }
+
+  pub fn names(&self) -> &[String] {
+    &self.names
+  }
+
+  pub fn add_name(&mut self, name: &str) -> u32 {
+    if let Some(&idx) = self.name_indices.get(name) {
+      return idx;
+    };
+    let idx = self.names.len() as u32;
+    self.names.push(name.to_string());
+    self.name_indices.insert(name.to_string(), idx);
+    idx
+  }
+
+  pub fn add_mapping(&mut self, mapping: Mapping) {
+    // Mappings must be in generated order. Nested nodes often start at the same generated position as their parent (e.g. `a` in `a+b`), in which case the later, innermost one is more precise, so it replaces the earlier one.
+    if let Some(last) = self.mappings.last_mut() {
+      debug_assert!(
+        (last.generated_line, last.generated_column)
+          <= (mapping.generated_line, mapping.generated_column)
+      );
+      if last.generated_line == mapping.generated_line
+        && last.generated_column == mapping.generated_column
+      {
+        *last = mapping;
+        return;
+      };
+    };
+    self.mappings.push(mapping);
+  }
+
+  /// Reads a version 3 source map, such as one produced by a compiler that ran before minification. The `sourceRoot`, if any, is prepended to each source.
+  pub fn from_json(json: &str) -> Result<SourceMap, SourceMapError> {
+    let raw: Value = serde_json::from_str(json).map_err(SourceMapError::Json)?;
+    let obj = raw
+      .as_object()
+      .ok_or(SourceMapError::InvalidField("version"))?;
+    if obj.contains_key("sections") {
+      return Err(SourceMapError::IndexMap);
+    };
+    if obj.get("version").and_then(Value::as_u64) != Some(3) {
+      return Err(SourceMapError::InvalidField("version"));
+    };
+    let source_root = match obj.get("sourceRoot") {
+      None | Some(Value::Null) => "",
+      Some(Value::String(r)) => r.as_str(),
+      Some(_) => return Err(SourceMapError::InvalidField("sourceRoot")),
+    };
+    let mut map = SourceMap::new(String::new());
+    map.sources.clear();
+    map.sources_content.clear();
+    map.file = obj.get("file").and_then(Value::as_str).map(str::to_string);
+    for source in obj
+      .get("sources")
+      .and_then(Value::as_array)
+      .ok_or(SourceMapError::InvalidField("sources"))?
+    {
+      let source = match source {
+        // Some tools emit `null` for sources they couldn't name.
+        Value::Null => "",
+        Value::String(s) => s.as_str(),
+        _ => return Err(SourceMapError::InvalidField("sources")),
+      };
+      map.sources.push(if source_root.is_empty() {
+        source.to_string()
+      } else {
+        format!("{}/{}", source_root.trim_end_matches('/'), source)
+      });
+    }
+    let contents = match obj.get("sourcesContent") {
+      None | Some(Value::Null) => &[][..],
+      Some(Value::Array(c)) => c.as_slice(),
+      Some(_) => return Err(SourceMapError::InvalidField("sourcesContent")),
+    };
+    for i in 0..map.sources.len() {
+      map
+        .sources_content
+        .push(contents.get(i).and_then(Value::as_str).map(str::to_string));
+    }
+    for name in obj
+      .get("names")
+      .and_then(Value::as_array)
+      .map(Vec::as_slice)
+      .unwrap_or_default()
+    {
+      let name = name.as_str().ok_or(SourceMapError::InvalidField("names"))?;
+      // Don't use `add_name`, as indices in `mappings` refer to the original positions, even if a name is repeated.
+      map.names.push(name.to_string());
+      map
+        .name_indices
+        .entry(name.to_string())
+        .or_insert(map.names.len() as u32 - 1);
+    }
+    let mappings = obj
+      .get("mappings")
+      .and_then(Value::as_str)
+      .ok_or(SourceMapError::InvalidField("mappings"))?;
+    map.decode_mappings(mappings.as_bytes())?;
+    Ok(map)
+  }
+
+  fn decode_mappings(&mut self, raw: &[u8]) -> Result<(), SourceMapError> {
+    let mut generated_line = 0;
+    let mut generated_column = 0i64;
+    let mut source = 0i64;
+    let mut original_line = 0i64;
+    let mut original_column = 0i64;
+    let mut name = 0i64;
+    let mut pos = 0;
+    while pos < raw.len() {
+      match raw[pos] {
+        b';' => {
+          generated_line += 1;
+          generated_column = 0;
+          pos += 1;
+          continue;
+        }
+        b',' => {
+          pos += 1;
+          continue;
+        }
+        _ => {}
+      };
+      let mut fields = [0i64; 5];
+      let mut count = 0;
+      while pos < raw.len() && raw[pos] != b',' && raw[pos] != b';' {
+        if count == 5 {
+          return Err(SourceMapError::InvalidMappings);
+        };
+        fields[count] = read_vlq(raw, &mut pos)?;
+        count += 1;
+      }
+      let column = apply_delta(&mut generated_column, fields[0])?;
+      // Segments with only a generated column mark code that has no original source. We don't have a way to represent them, so they're dropped, leaving the generated code mapped to whatever precedes it.
+      if count == 1 {
+        continue;
+      };
+      if count != 4 && count != 5 {
+        return Err(SourceMapError::InvalidMappings);
+      };
+      let mapping = Mapping {
+        generated_line,
+        generated_column: column,
+        source: apply_delta(&mut source, fields[1])?,
+        original_line: apply_delta(&mut original_line, fields[2])?,
+        original_column: apply_delta(&mut original_column, fields[3])?,
+        name: if count == 5 {
+          Some(apply_delta(&mut name, fields[4])?)
+        } else {
+          None
+        },
+      };
+      if mapping.source as usize >= self.sources.len()
+        || mapping.name.is_some_and(|n| n as usize >= self.names.len())
+      {
+        return Err(SourceMapError::InvalidMappings);
+      };
+      self.mappings.push(mapping);
+    }
+    // Segments within a line aren't required to be in order.
+    self
+      .mappings
+      .sort_by_key(|m| (m.generated_line, m.generated_column));
+    Ok(())
+  }
+
+  // Finds the mapping covering a generated position, which is the last one at or before it on the same line.
+  fn lookup(&self, line: u32, column: u32) -> Option<&Mapping> {
+    let idx = self
+      .mappings
+      .partition_point(|m| (m.generated_line, m.generated_column) <= (line, column));
+    self.mappings[..idx]
+      .last()
+      .filter(|m| m.generated_line == line)
+  }
+
+  /// Traces this map's mappings through `input`, a map whose generated code is this map's original source, producing a map from this map's generated code directly to `input`'s sources. Mappings that point to code `input` doesn't map are dropped.
+  pub fn remap(&self, input: &SourceMap) -> SourceMap {
+    let mut remapped = SourceMap::new(String::new());
+    remapped.file = self.file.clone();
+    remapped.sources = input.sources.clone();
+    remapped.sources_content = input.sources_content.clone();
+    for m in self.mappings.iter() {
+      let Some(orig) = input.lookup(m.original_line, m.original_column) else {
+        continue;
+      };
+      // Prefer the name from the earlier map, as it's closer to what the author wrote.
+      let name = orig
+        .name
+        .map(|n| input.names[n as usize].as_str())
+        .or_else(|| m.name.map(|n| self.names[n as usize].as_str()))
+        .map(|n| remapped.add_name(n));
+      remapped.add_mapping(Mapping {
+        generated_line: m.generated_line,
+        generated_column: m.generated_column,
+        source: orig.source,
+        original_line: orig.original_line,
+        original_column: orig.original_column,
+        name,
+      });
+    }
+    remapped
+  }
+
+  /// Encodes the `mappings` field using Base64 VLQ.
+  pub fn encode_mappings(&self) -> String {
+    let mut out = String::new();
+    let mut line = 0;
+    let mut prev_generated_column = 0i64;
+    let mut prev_source = 0i64;
+    let mut prev_original_line = 0i64;
+    let mut prev_original_column = 0i64;
+    let mut prev_name = 0i64;
+    for (i, m) in self.mappings.iter().enumerate() {
+      if m.generated_line != line {
+        while line < m.generated_line {
+          out.push(';');
+          line += 1;
+        }
+        prev_generated_column = 0;
+      } else if i > 0 {
+        out.push(',');
+      };
+      write_vlq(&mut out, m.generated_column as i64 - prev_generated_column);
+      write_vlq(&mut out, m.source as i64 - prev_source);
+      write_vlq(&mut out, m.original_line as i64 - prev_original_line);
+      write_vlq(&mut out, m.original_column as i64 - prev_original_column);
+      if let Some(name) = m.name {
+        write_vlq(&mut out, name as i64 - prev_name);
+        prev_name = name as i64;
+      };
+      prev_generated_column = m.generated_column as i64;
+      prev_source = m.source as i64;
+      prev_original_line = m.original_line as i64;
+      prev_original_column = m.original_column as i64;
+    }
+    out
+  }
+
+  pub fn to_json(&self) -> String {
+    let mut out = String::new();
+    out.push_str(r#"{"version":3,"#);
+    if let Some(file) = &self.file {
+      out.push_str(r#""file":"#);
+      write_json_string(&mut out, file);
+      out.push(',');
+    };
+    out.push_str(r#""sources":["#);
+    for (i, source) in self.sources.iter().enumerate() {
+      if i > 0 {
+        out.push(',');
+      };
+      write_json_string(&mut out, source);
+    }
+    out.push_str("],");
+    if self.sources_content.iter().any(Option::is_some) {
+      out.push_str(r#""sourcesContent":["#);
+      for (i, content) in self.sources_content.iter().enumerate() {
+        if i > 0 {
+          out.push(',');
+        };
+        match content {
+          Some(content) => write_json_string(&mut out, content),
+          None => out.push_str("null"),
+        };
+      }
+      out.push_str("],");
+    };
+    out.push_str(r#""names":["#);
+    for (i, name) in self.names.iter().enumerate() {
+      if i > 0 {
+        out.push(',');
+      };
+      write_json_string(&mut out, name);
+    }
+    out.push_str(r#"],"mappings":"#);
+    write_json_string(&mut out, &self.encode_mappings());
+    out.push('}');
+    out
+  }
+}
+
+// Returns how many UTF-16 code units the UTF-8 bytes represent. Assumes the bytes are valid UTF-8 (or a prefix of it ending on a character boundary).
+fn utf16_len(bytes: &[u8]) -> u32 {
+  let mut len = 0;
+  for &b in bytes {
+    if b & 0b1100_0000 != 0b1000_0000 {
+      len += 1;
+    };
+    if b >= 0b1111_0000 {
+      // Four-byte sequences are outside the BMP and need a surrogate pair.
+      len += 1;
+    };
+  }
+  len
+}
+
+// Tracks line and column positions in both the original source and the output as the emitter progresses, and records mappings between them into a `SourceMap`.
+pub struct SourceMapBuilder<'o, 'a> {
+  map: &'o mut SourceMap,
+  source: &'a [u8],
+  // Offset of the first byte of each line in `source`.
+  source_line_starts: Vec<usize>,
+  // Output bytes before this offset have already been accounted for in `generated_line` and `generated_column`.
+  output_scanned: usize,
+  generated_line: u32,
+  generated_column: u32,
+  // The last original position we resolved, as (offset, line, column). Mappings are mostly added in increasing source order, so this avoids rescanning long lines (e.g. already minified input) from their start each time.
+  last_original: (usize, usize, u32),
+}
+
+impl<'o, 'a> SourceMapBuilder<'o, 'a> {
+  pub fn new(map: &'o mut SourceMap, source: &'a [u8], output_start: usize) -> Self {
+    let mut source_line_starts = vec![0];
+    for (i, &c) in source.iter().enumerate() {
+      if c == b'\n' || (c == b'\r' && source.get(i + 1) != Some(&b'\n')) {
+        source_line_starts.push(i + 1);
+      };
+    }
+    SourceMapBuilder {
+      map,
+      source,
+      source_line_starts,
+      output_scanned: output_start,
+      generated_line: 0,
+      generated_column: 0,
+      last_original: (0, 0, 0),
+    }
+  }
+
+  // Returns the offset of `original` within our source, or None if it points elsewhere (e.g. a node created during a transform using a static string).
+  fn offset_in_source(&self, original: &[u8]) -> Option<usize> {
+    let start = self.source.as_ptr() as usize;
+    let ptr = original.as_ptr() as usize;
+    if ptr < start || ptr + original.len() > start + self.source.len() {
+      return None;
+    };
+    Some(ptr - start)
+  }
+
+  fn advance_output(&mut self, output: &[u8]) {
+    let mut line_start = self.output_scanned;
+    for (i, &c) in output.iter().enumerate().skip(self.output_scanned) {
+      if c == b'\n' {
+        self.generated_line += 1;
+        self.generated_column = 0;
+        line_start = i + 1;
+      };
+    }
+    self.generated_column += utf16_len(&output[line_start..]);
+    self.output_scanned = output.len();
+  }
+
+  /// Maps the current end of `output` to the start of `original`, which must be a slice of the source for the mapping to be recorded. If `name` is provided, it's recorded as the original name of the generated token.
+  pub fn add(&mut self, output: &[u8], original: &[u8], name: Option<&[u8]>) {
+    let Some(offset) = self.offset_in_source(original) else {
+      return;
+    };
+    self.advance_output(output);
+    let line = match self.source_line_starts.binary_search(&offset) {
+      Ok(line) => line,
+      Err(next_line) => next_line - 1,
+    };
+    let (last_offset, last_line, last_column) = self.last_original;
+    let column = if last_line == line && last_offset <= offset {
+      last_column + utf16_len(&self.source[last_offset..offset])
+    } else {
+      utf16_len(&self.source[self.source_line_starts[line]..offset])
+    };
+    self.last_original = (offset, line, column);
+    let name = name.map(|n| {
+      self
+        .map
+        .add_name(unsafe { std::str::from_utf8_unchecked(n) })
+    });
+    self.map.add_mapping(Mapping {
+      generated_line: self.generated_line,
+      generated_column: self.generated_column,
+      source: 0,
+      original_line: line as u32,
+      original_column: column,
+      name,
+    });
+  }
+}
@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// Code in the input that stops some minification from being applied. The input is still minified correctly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+  /// A direct call to `eval` at this byte offset in the source. The evaluated code can access any variable visible to the call by its original name, so those variables aren't renamed, inlined, or removed when unused, and the functions containing the call aren't transformed into arrow functions, as the code could also use `this` or `arguments`.
+  DirectEval { position: usize },
+}
+
+impl fmt::Display for Warning {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Warning::DirectEval { position } => write!(
+        f,
+        "direct `eval` call at byte {} can access variables by name, so variables visible to it aren't renamed or inlined, and the functions containing it aren't transformed into arrow functions",
+        position
+      ),
+    }
+  }
+}